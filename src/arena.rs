@@ -0,0 +1,551 @@
+use std::marker::PhantomData;
+
+use crate::ast::{
+    self, ClassDecl, Expr, FunctionDecl, Operator, Primitive, Stmt, StmtWrapper, TryStmt,
+};
+use crate::token::LocationInfo;
+
+/// Index of an expression in an `Ast`. Only meaningful for the `Ast` that
+/// handed it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+/// Index of a statement in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StmtId(u32);
+
+pub trait NodeId: Copy {
+    fn index(self) -> usize;
+}
+
+impl NodeId for ExprId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl NodeId for StmtId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Mirrors `ast::ExprKind` with children referred to by id.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Assign(String, ExprId),
+    Binary(Operator, ExprId, ExprId),
+    Call(ExprId, Vec<ExprId>),
+    Comma(ExprId, ExprId),
+    Conditional(ExprId, ExprId, ExprId),
+    Get(ExprId, String),
+    Grouping(ExprId),
+    Index(ExprId, ExprId),
+    Interpolation(Vec<ExprId>),
+    Lambda(Vec<String>, Vec<StmtId>),
+    List(Vec<ExprId>),
+    Literal(Primitive),
+    Logical(Operator, ExprId, ExprId),
+    Map(Vec<(ExprId, ExprId)>),
+    Set(ExprId, String, ExprId),
+    SetIndex(ExprId, ExprId, ExprId),
+    Super(String),
+    This,
+    Unary(Operator, ExprId),
+    Variable(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ExprNode {
+    pub location_info: LocationInfo,
+    pub kind: ExprKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionNode {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<StmtId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassNode {
+    pub name: String,
+    pub superclass: Option<String>,
+    pub methods: Vec<FunctionNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryNode {
+    pub body: Vec<StmtId>,
+    pub catch: Option<(String, Vec<StmtId>)>,
+    pub finally: Option<Vec<StmtId>>,
+}
+
+/// Mirrors `ast::Stmt` with children referred to by id.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Block(Vec<StmtId>),
+    Break,
+    Class(ClassNode),
+    Continue,
+    Expression(ExprId),
+    ForIn(String, ExprId, StmtId),
+    Function(FunctionNode),
+    If(ExprId, StmtId, Option<StmtId>),
+    Print(ExprId),
+    Return(Option<ExprId>),
+    Throw(ExprId),
+    Try(TryNode),
+    Var(String, Option<ExprId>),
+    While(ExprId, StmtId, Option<ExprId>),
+}
+
+#[derive(Debug, Clone)]
+pub struct StmtNode {
+    pub location_info: LocationInfo,
+    pub kind: StmtKind,
+}
+
+/// A program whose nodes live in two flat vectors. Children are always
+/// allocated before their parents, so ids are handed out in post-order.
+///
+/// Nothing in the pipeline builds one yet. The parser, the optimizer and
+/// the compiler all work on the boxed `ast` types, so an `Ast` is a copy
+/// made by `lower` for passes that want ids and side tables. It saves no
+/// allocations until the parser allocates into it directly.
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+    exprs: Vec<ExprNode>,
+    stmts: Vec<StmtNode>,
+    // Top-level statements in source order
+    pub roots: Vec<StmtId>,
+}
+
+impl Ast {
+    pub fn new() -> Ast {
+        Default::default()
+    }
+
+    /// Copies a boxed program into a fresh arena.
+    pub fn lower(program: &[StmtWrapper]) -> Ast {
+        let mut ast = Ast::new();
+        ast.roots = ast.lower_block(program);
+        ast
+    }
+
+    /// Rebuilds the boxed form the rest of the pipeline takes.
+    pub fn raise(&self) -> Vec<StmtWrapper> {
+        self.raise_block(&self.roots)
+    }
+
+    pub fn alloc_expr(&mut self, location_info: LocationInfo, kind: ExprKind) -> ExprId {
+        self.exprs.push(ExprNode {
+            location_info,
+            kind,
+        });
+        ExprId(self.exprs.len() as u32 - 1)
+    }
+
+    pub fn alloc_stmt(&mut self, location_info: LocationInfo, kind: StmtKind) -> StmtId {
+        self.stmts.push(StmtNode {
+            location_info,
+            kind,
+        });
+        StmtId(self.stmts.len() as u32 - 1)
+    }
+
+    pub fn expr(&self, id: ExprId) -> &ExprNode {
+        &self.exprs[id.index()]
+    }
+
+    pub fn stmt(&self, id: StmtId) -> &StmtNode {
+        &self.stmts[id.index()]
+    }
+
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+
+    pub fn expr_ids(&self) -> impl Iterator<Item = ExprId> {
+        (0..self.exprs.len() as u32).map(ExprId)
+    }
+
+    pub fn stmt_ids(&self) -> impl Iterator<Item = StmtId> {
+        (0..self.stmts.len() as u32).map(StmtId)
+    }
+
+    fn lower_block(&mut self, statements: &[StmtWrapper]) -> Vec<StmtId> {
+        statements.iter().map(|s| self.lower_stmt(s)).collect()
+    }
+
+    fn lower_stmt(&mut self, wrapper: &StmtWrapper) -> StmtId {
+        let kind = match &wrapper.stmt {
+            Stmt::Block(statements) => StmtKind::Block(self.lower_block(statements)),
+            Stmt::Break => StmtKind::Break,
+            Stmt::Class(decl) => StmtKind::Class(ClassNode {
+                name: decl.name.clone(),
+                superclass: decl.superclass.clone(),
+                methods: decl
+                    .methods
+                    .iter()
+                    .map(|method| self.lower_function(method))
+                    .collect(),
+            }),
+            Stmt::Continue => StmtKind::Continue,
+            Stmt::Expression(expr) => StmtKind::Expression(self.lower_expr(expr)),
+            Stmt::ForIn(name, iterable, body) => StmtKind::ForIn(
+                name.clone(),
+                self.lower_expr(iterable),
+                self.lower_stmt(body),
+            ),
+            Stmt::Function(decl) => StmtKind::Function(self.lower_function(decl)),
+            Stmt::If(condition, then_branch, else_branch) => StmtKind::If(
+                self.lower_expr(condition),
+                self.lower_stmt(then_branch),
+                else_branch.as_ref().map(|b| self.lower_stmt(b)),
+            ),
+            Stmt::Print(expr) => StmtKind::Print(self.lower_expr(expr)),
+            Stmt::Return(value) => StmtKind::Return(value.as_ref().map(|v| self.lower_expr(v))),
+            Stmt::Throw(value) => StmtKind::Throw(self.lower_expr(value)),
+            Stmt::Try(stmt) => StmtKind::Try(TryNode {
+                body: self.lower_block(&stmt.body),
+                catch: stmt
+                    .catch
+                    .as_ref()
+                    .map(|(name, handler)| (name.clone(), self.lower_block(handler))),
+                finally: stmt.finally.as_ref().map(|f| self.lower_block(f)),
+            }),
+            Stmt::Var(name, initializer) => StmtKind::Var(
+                name.clone(),
+                initializer.as_ref().map(|i| self.lower_expr(i)),
+            ),
+            Stmt::While(condition, body, increment) => StmtKind::While(
+                self.lower_expr(condition),
+                self.lower_stmt(body),
+                increment.as_ref().map(|i| self.lower_expr(i)),
+            ),
+        };
+        self.alloc_stmt(wrapper.location_info.clone(), kind)
+    }
+
+    fn lower_function(&mut self, decl: &FunctionDecl) -> FunctionNode {
+        FunctionNode {
+            name: decl.name.clone(),
+            params: decl.params.clone(),
+            body: self.lower_block(&decl.body),
+        }
+    }
+
+    fn lower_expr(&mut self, expr: &Expr) -> ExprId {
+        let kind = match &expr.kind {
+            ast::ExprKind::Assign(name, value) => {
+                ExprKind::Assign(name.clone(), self.lower_expr(value))
+            }
+            ast::ExprKind::Binary(operator, left, right) => ExprKind::Binary(
+                operator.clone(),
+                self.lower_expr(left),
+                self.lower_expr(right),
+            ),
+            ast::ExprKind::Call(callee, args) => ExprKind::Call(
+                self.lower_expr(callee),
+                args.iter().map(|a| self.lower_expr(a)).collect(),
+            ),
+            ast::ExprKind::Comma(left, right) => {
+                ExprKind::Comma(self.lower_expr(left), self.lower_expr(right))
+            }
+            ast::ExprKind::Conditional(condition, then_expr, else_expr) => ExprKind::Conditional(
+                self.lower_expr(condition),
+                self.lower_expr(then_expr),
+                self.lower_expr(else_expr),
+            ),
+            ast::ExprKind::Get(object, name) => {
+                ExprKind::Get(self.lower_expr(object), name.clone())
+            }
+            ast::ExprKind::Grouping(expr) => ExprKind::Grouping(self.lower_expr(expr)),
+            ast::ExprKind::Index(object, index) => {
+                ExprKind::Index(self.lower_expr(object), self.lower_expr(index))
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                ExprKind::Interpolation(parts.iter().map(|e| self.lower_expr(e)).collect())
+            }
+            ast::ExprKind::List(elements) => {
+                ExprKind::List(elements.iter().map(|e| self.lower_expr(e)).collect())
+            }
+            ast::ExprKind::Map(entries) => ExprKind::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| (self.lower_expr(key), self.lower_expr(value)))
+                    .collect(),
+            ),
+            ast::ExprKind::Set(object, name, value) => ExprKind::Set(
+                self.lower_expr(object),
+                name.clone(),
+                self.lower_expr(value),
+            ),
+            ast::ExprKind::Super(method) => ExprKind::Super(method.clone()),
+            ast::ExprKind::This => ExprKind::This,
+            ast::ExprKind::SetIndex(object, index, value) => ExprKind::SetIndex(
+                self.lower_expr(object),
+                self.lower_expr(index),
+                self.lower_expr(value),
+            ),
+            ast::ExprKind::Lambda(params, body) => {
+                ExprKind::Lambda(params.clone(), self.lower_block(body))
+            }
+            ast::ExprKind::Literal(value) => ExprKind::Literal(value.clone()),
+            ast::ExprKind::Logical(operator, left, right) => ExprKind::Logical(
+                operator.clone(),
+                self.lower_expr(left),
+                self.lower_expr(right),
+            ),
+            ast::ExprKind::Unary(operator, right) => {
+                ExprKind::Unary(operator.clone(), self.lower_expr(right))
+            }
+            ast::ExprKind::Variable(name) => ExprKind::Variable(name.clone()),
+        };
+        self.alloc_expr(expr.location_info.clone(), kind)
+    }
+
+    fn raise_block(&self, ids: &[StmtId]) -> Vec<StmtWrapper> {
+        ids.iter().map(|&id| self.raise_stmt(id)).collect()
+    }
+
+    fn raise_stmt(&self, id: StmtId) -> StmtWrapper {
+        let node = self.stmt(id);
+        let stmt = match &node.kind {
+            StmtKind::Block(ids) => Stmt::Block(self.raise_block(ids)),
+            StmtKind::Break => Stmt::Break,
+            StmtKind::Class(class) => Stmt::Class(ClassDecl {
+                name: class.name.clone(),
+                superclass: class.superclass.clone(),
+                methods: class
+                    .methods
+                    .iter()
+                    .map(|method| self.raise_function(method))
+                    .collect(),
+            }),
+            StmtKind::Continue => Stmt::Continue,
+            StmtKind::Expression(expr) => Stmt::Expression(self.raise_expr(*expr)),
+            StmtKind::ForIn(name, iterable, body) => Stmt::ForIn(
+                name.clone(),
+                self.raise_expr(*iterable),
+                Box::new(self.raise_stmt(*body)),
+            ),
+            StmtKind::Function(function) => Stmt::Function(self.raise_function(function)),
+            StmtKind::If(condition, then_branch, else_branch) => Stmt::If(
+                self.raise_expr(*condition),
+                Box::new(self.raise_stmt(*then_branch)),
+                else_branch.map(|b| Box::new(self.raise_stmt(b))),
+            ),
+            StmtKind::Print(expr) => Stmt::Print(self.raise_expr(*expr)),
+            StmtKind::Return(value) => Stmt::Return(value.map(|v| self.raise_expr(v))),
+            StmtKind::Throw(value) => Stmt::Throw(self.raise_expr(*value)),
+            StmtKind::Try(node) => Stmt::Try(TryStmt {
+                body: self.raise_block(&node.body),
+                catch: node
+                    .catch
+                    .as_ref()
+                    .map(|(name, handler)| (name.clone(), self.raise_block(handler))),
+                finally: node.finally.as_ref().map(|f| self.raise_block(f)),
+            }),
+            StmtKind::Var(name, initializer) => {
+                Stmt::Var(name.clone(), initializer.map(|i| self.raise_expr(i)))
+            }
+            StmtKind::While(condition, body, increment) => Stmt::While(
+                self.raise_expr(*condition),
+                Box::new(self.raise_stmt(*body)),
+                increment.map(|i| self.raise_expr(i)),
+            ),
+        };
+        StmtWrapper {
+            location_info: node.location_info.clone(),
+            stmt,
+        }
+    }
+
+    fn raise_function(&self, function: &FunctionNode) -> FunctionDecl {
+        FunctionDecl {
+            name: function.name.clone(),
+            params: function.params.clone(),
+            body: self.raise_block(&function.body),
+        }
+    }
+
+    pub fn raise_expr(&self, id: ExprId) -> Expr {
+        let boxed = |id| Box::new(self.raise_expr(id));
+        let node = self.expr(id);
+        let kind = match &node.kind {
+            ExprKind::Assign(name, value) => ast::ExprKind::Assign(name.clone(), boxed(*value)),
+            ExprKind::Binary(operator, left, right) => {
+                ast::ExprKind::Binary(operator.clone(), boxed(*left), boxed(*right))
+            }
+            ExprKind::Call(callee, args) => ast::ExprKind::Call(
+                boxed(*callee),
+                args.iter().map(|&a| self.raise_expr(a)).collect(),
+            ),
+            ExprKind::Comma(left, right) => ast::ExprKind::Comma(boxed(*left), boxed(*right)),
+            ExprKind::Conditional(condition, then_expr, else_expr) => {
+                ast::ExprKind::Conditional(boxed(*condition), boxed(*then_expr), boxed(*else_expr))
+            }
+            ExprKind::Get(object, name) => ast::ExprKind::Get(boxed(*object), name.clone()),
+            ExprKind::Grouping(expr) => ast::ExprKind::Grouping(boxed(*expr)),
+            ExprKind::Index(object, index) => ast::ExprKind::Index(boxed(*object), boxed(*index)),
+            ExprKind::Interpolation(parts) => {
+                ast::ExprKind::Interpolation(parts.iter().map(|&e| self.raise_expr(e)).collect())
+            }
+            ExprKind::List(elements) => {
+                ast::ExprKind::List(elements.iter().map(|&e| self.raise_expr(e)).collect())
+            }
+            ExprKind::Map(entries) => ast::ExprKind::Map(
+                entries
+                    .iter()
+                    .map(|&(key, value)| (self.raise_expr(key), self.raise_expr(value)))
+                    .collect(),
+            ),
+            ExprKind::Set(object, name, value) => {
+                ast::ExprKind::Set(boxed(*object), name.clone(), boxed(*value))
+            }
+            ExprKind::Super(method) => ast::ExprKind::Super(method.clone()),
+            ExprKind::This => ast::ExprKind::This,
+            ExprKind::SetIndex(object, index, value) => {
+                ast::ExprKind::SetIndex(boxed(*object), boxed(*index), boxed(*value))
+            }
+            ExprKind::Lambda(params, body) => {
+                ast::ExprKind::Lambda(params.clone(), self.raise_block(body))
+            }
+            ExprKind::Literal(value) => ast::ExprKind::Literal(value.clone()),
+            ExprKind::Logical(operator, left, right) => {
+                ast::ExprKind::Logical(operator.clone(), boxed(*left), boxed(*right))
+            }
+            ExprKind::Unary(operator, right) => {
+                ast::ExprKind::Unary(operator.clone(), boxed(*right))
+            }
+            ExprKind::Variable(name) => ast::ExprKind::Variable(name.clone()),
+        };
+        Expr::new(node.location_info.clone(), kind)
+    }
+}
+
+/// Per-node data kept outside the tree, e.g. resolved scope depth or
+/// inferred types. Lookups are a direct index by id.
+#[derive(Debug, Clone)]
+pub struct SideTable<I: NodeId, T> {
+    values: Vec<Option<T>>,
+    id: PhantomData<I>,
+}
+
+impl<I: NodeId, T> Default for SideTable<I, T> {
+    fn default() -> Self {
+        SideTable {
+            values: vec![],
+            id: PhantomData,
+        }
+    }
+}
+
+impl<I: NodeId, T> SideTable<I, T> {
+    pub fn new() -> SideTable<I, T> {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, id: I, value: T) -> Option<T> {
+        let index = id.index();
+        if index >= self.values.len() {
+            self.values.resize_with(index + 1, || None);
+        }
+        self.values[index].replace(value)
+    }
+
+    pub fn get(&self, id: I) -> Option<&T> {
+        self.values.get(id.index()).and_then(|v| v.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: I) -> Option<&mut T> {
+        self.values.get_mut(id.index()).and_then(|v| v.as_mut())
+    }
+
+    pub fn remove(&mut self, id: I) -> Option<T> {
+        self.values.get_mut(id.index()).and_then(|v| v.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse;
+
+    const PROGRAM: &str = "var a = 1;
+        fun f(x, y) { if (x > y) return x; else { print -y; } }
+        for (var i = 0; i < 3; i = i + 1) a = f(a, i) or nil;
+        print a ? (1, 2) : 3;";
+
+    #[test]
+    fn test_lower_raise_round_trip() {
+        let program = parse(PROGRAM);
+        let ast = Ast::lower(&program);
+        assert_eq!(ast.roots.len(), program.len());
+        assert_eq!(format!("{:?}", ast.raise()), format!("{:?}", program));
+        // Cloning copies the flat vectors, not a tree of boxes
+        let copy = ast.clone();
+        assert_eq!(copy.expr_count(), ast.expr_count());
+        assert_eq!(copy.stmt(copy.roots[1]).location_info.line, 2);
+    }
+
+    #[test]
+    fn test_children_before_parents() {
+        let ast = Ast::lower(&parse(PROGRAM));
+        for id in ast.expr_ids() {
+            let children = match &ast.expr(id).kind {
+                ExprKind::Assign(_, c)
+                | ExprKind::Get(c, _)
+                | ExprKind::Grouping(c)
+                | ExprKind::Unary(_, c) => vec![*c],
+                ExprKind::Index(l, r) => vec![*l, *r],
+                ExprKind::Interpolation(parts) | ExprKind::List(parts) => parts.clone(),
+                ExprKind::Map(entries) => entries.iter().flat_map(|&(k, v)| [k, v]).collect(),
+                ExprKind::Set(o, _, v) => vec![*o, *v],
+                ExprKind::SetIndex(o, i, v) => vec![*o, *i, *v],
+                ExprKind::Binary(_, l, r) | ExprKind::Logical(_, l, r) | ExprKind::Comma(l, r) => {
+                    vec![*l, *r]
+                }
+                ExprKind::Conditional(c, t, e) => vec![*c, *t, *e],
+                ExprKind::Call(c, args) => std::iter::once(*c).chain(args.clone()).collect(),
+                ExprKind::Lambda(..)
+                | ExprKind::Literal(_)
+                | ExprKind::Super(_)
+                | ExprKind::This
+                | ExprKind::Variable(_) => vec![],
+            };
+            assert!(children.iter().all(|&c| c < id), "{:?}", ast.expr(id));
+        }
+    }
+
+    #[test]
+    fn test_side_table() {
+        let ast = Ast::lower(&parse(PROGRAM));
+        // Tag every variable reference with its name's length
+        let mut lengths = SideTable::new();
+        for id in ast.expr_ids() {
+            if let ExprKind::Variable(name) = &ast.expr(id).kind {
+                lengths.insert(id, name.len());
+            }
+        }
+        let variables: Vec<ExprId> = ast
+            .expr_ids()
+            .filter(|&id| lengths.get(id).is_some())
+            .collect();
+        assert_eq!(variables.len(), 10);
+        let first = variables[0];
+        assert_eq!(ast.expr(first).kind, ExprKind::Variable("x".to_owned()));
+        *lengths.get_mut(first).expect("Inserted above") += 1;
+        assert_eq!(lengths.remove(first), Some(2));
+        assert_eq!(lengths.get(first), None);
+        // Ids past the end of the table are simply absent
+        assert_eq!(lengths.get(ExprId(u32::MAX)), None);
+    }
+}
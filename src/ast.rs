@@ -1,8 +1,9 @@
-use crate::token::Token;
-use std::fmt;
+use crate::token::{LocationInfo, Token};
+use crate::visit::Visitor;
 use std::convert::TryFrom;
+use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operator {
     Plus,
     Minus,
@@ -16,6 +17,10 @@ pub enum Operator {
     GreaterEqual,
     Less,
     LessEqual,
+    And,
+    Or,
+    // Half open range of numbers, `start..end`
+    Range,
 }
 
 // TODO: Use token wrapper and custom error type
@@ -37,6 +42,10 @@ impl TryFrom<&Token> for Operator {
             Token::GreaterEqual => Ok(Operator::GreaterEqual),
             Token::Less => Ok(Operator::Less),
             Token::LessEqual => Ok(Operator::LessEqual),
+            Token::DotDot => Ok(Operator::Range),
+            // Keywords
+            Token::And => Ok(Operator::And),
+            Token::Or => Ok(Operator::Or),
             _ => Err(format!("Token variant is not operator: {:?}", token)),
         }
     }
@@ -57,12 +66,15 @@ impl fmt::Display for Operator {
             Operator::GreaterEqual => ">=",
             Operator::Less => "<",
             Operator::LessEqual => "<=",
+            Operator::And => "and",
+            Operator::Or => "or",
+            Operator::Range => "..",
         };
         write!(f, "{}", symbol)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Primitive {
     String(String),
     Number(f64),
@@ -70,13 +82,31 @@ pub enum Primitive {
     Nil,
 }
 
+impl Primitive {
+    // Only nil and false are falsey
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Primitive::Nil | Primitive::Boolean(false))
+    }
+
+    // The text `print` shows, unlike `Display` strings are not quoted
+    pub fn stringify(&self) -> String {
+        match self {
+            Primitive::String(s) => s.clone(),
+            p => format!("{}", p),
+        }
+    }
+}
+
 impl fmt::Display for Primitive {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self {
-            Primitive::String(s) => write!(f, "{}", s),
+            // Quoted so printed expressions can be read back
+            Primitive::String(s) => {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
             Primitive::Number(n) => write!(f, "{}", n),
             Primitive::Boolean(b) => write!(f, "{}", b),
-            Primitive::Nil => write!(f, "null"),
+            Primitive::Nil => write!(f, "nil"),
         }
     }
 }
@@ -96,23 +126,265 @@ impl TryFrom<&Token> for Primitive {
     }
 }
 
-#[derive(Debug)]
-pub enum Expr {
+/// An expression and the line it starts on, which runtime errors in the
+/// code compiled from it report.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub location_info: LocationInfo,
+    pub kind: ExprKind,
+}
+
+impl Expr {
+    pub fn new(location_info: LocationInfo, kind: ExprKind) -> Expr {
+        Expr {
+            location_info,
+            kind,
+        }
+    }
+}
+
+// Where an expression was written doesn't change what it is, so trees read
+// back from their printed form compare equal to the parsed ones
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        self.kind == other.kind
+    }
+}
+
+// Expressions not read from Lox source, like test fixtures, are on line 0
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Expr {
+        Expr::new(LocationInfo { line: 0, column: 0 }, kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Assign(String, Box<Expr>),
     Binary(Operator, Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    // Evaluates the left operand for its side effects, then the right
+    Comma(Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    // Property access, e.g. a method about to be called
+    Get(Box<Expr>, String),
     Grouping(Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    // String literal segments and embedded expressions, in source order
+    Interpolation(Vec<Expr>),
+    // Anonymous function, with its parameters and body
+    Lambda(Vec<String>, Vec<StmtWrapper>),
+    List(Vec<Expr>),
     Literal(Primitive),
+    Logical(Operator, Box<Expr>, Box<Expr>),
+    // Key and value expressions, in source order
+    Map(Vec<(Expr, Expr)>),
+    // Object, property name and the value to store
+    Set(Box<Expr>, String, Box<Expr>),
+    // Object, index and the value to store
+    SetIndex(Box<Expr>, Box<Expr>, Box<Expr>),
+    // Superclass method looked up by name
+    Super(String),
+    This,
     Unary(Operator, Box<Expr>),
+    Variable(String),
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self {
-            Expr::Binary(o, b1, b2) => write!(f, "({} {} {})", o, b1, b2),
-            Expr::Grouping(b) => write!(f, "(group {})", b),
-            Expr::Literal(p) => write!(f, "{}", p),
-            Expr::Unary(o, b) => write!(f, "({} {})", o, b),
+        write!(f, "{}", Printer.visit_expr(self))
+    }
+}
+
+// Lisp-style printer, `sexpr::parse` reads its output back
+struct Printer;
+
+impl Visitor for Printer {
+    type Output = String;
+
+    fn visit_assign(&mut self, name: &str, value: &Expr) -> String {
+        format!("(= {} {})", name, self.visit_expr(value))
+    }
+
+    fn visit_binary(&mut self, operator: &Operator, left: &Expr, right: &Expr) -> String {
+        format!(
+            "({} {} {})",
+            operator,
+            self.visit_expr(left),
+            self.visit_expr(right)
+        )
+    }
+
+    fn visit_call(&mut self, callee: &Expr, args: &[Expr]) -> String {
+        let mut output = format!("(call {}", self.visit_expr(callee));
+        for arg in args.iter() {
+            output.push(' ');
+            output.push_str(&self.visit_expr(arg));
         }
+        output.push(')');
+        output
     }
+
+    fn visit_comma(&mut self, left: &Expr, right: &Expr) -> String {
+        format!("(, {} {})", self.visit_expr(left), self.visit_expr(right))
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        then_expr: &Expr,
+        else_expr: &Expr,
+    ) -> String {
+        format!(
+            "(? {} {} {})",
+            self.visit_expr(condition),
+            self.visit_expr(then_expr),
+            self.visit_expr(else_expr)
+        )
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &str) -> String {
+        format!("(. {} {})", self.visit_expr(object), name)
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr) -> String {
+        format!(
+            "(index {} {})",
+            self.visit_expr(object),
+            self.visit_expr(index)
+        )
+    }
+
+    fn visit_interpolation(&mut self, parts: &[Expr]) -> String {
+        let mut output = "(interpolate".to_owned();
+        for part in parts.iter() {
+            output.push(' ');
+            output.push_str(&self.visit_expr(part));
+        }
+        output.push(')');
+        output
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> String {
+        let mut output = "(list".to_owned();
+        for element in elements.iter() {
+            output.push(' ');
+            output.push_str(&self.visit_expr(element));
+        }
+        output.push(')');
+        output
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> String {
+        let mut output = "(map".to_owned();
+        for (key, value) in entries.iter() {
+            output.push(' ');
+            output.push_str(&self.visit_expr(key));
+            output.push(' ');
+            output.push_str(&self.visit_expr(value));
+        }
+        output.push(')');
+        output
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &str, value: &Expr) -> String {
+        format!(
+            "(.= {} {} {})",
+            self.visit_expr(object),
+            name,
+            self.visit_expr(value)
+        )
+    }
+
+    fn visit_set_index(&mut self, object: &Expr, index: &Expr, value: &Expr) -> String {
+        format!(
+            "(index= {} {} {})",
+            self.visit_expr(object),
+            self.visit_expr(index),
+            self.visit_expr(value)
+        )
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> String {
+        format!("(group {})", self.visit_expr(expr))
+    }
+
+    // Statements have no printed form, so only the signature is shown
+    fn visit_lambda(&mut self, params: &[String], _body: &[StmtWrapper]) -> String {
+        format!("(fun ({}) ...)", params.join(" "))
+    }
+
+    fn visit_literal(&mut self, value: &Primitive) -> String {
+        format!("{}", value)
+    }
+
+    fn visit_logical(&mut self, operator: &Operator, left: &Expr, right: &Expr) -> String {
+        self.visit_binary(operator, left, right)
+    }
+
+    fn visit_unary(&mut self, operator: &Operator, right: &Expr) -> String {
+        format!("({} {})", operator, self.visit_expr(right))
+    }
+
+    fn visit_super(&mut self, method: &str) -> String {
+        format!("(super {})", method)
+    }
+
+    fn visit_this(&mut self) -> String {
+        "this".to_owned()
+    }
+
+    fn visit_variable(&mut self, name: &str) -> String {
+        name.to_owned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<StmtWrapper>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDecl {
+    pub name: String,
+    pub superclass: Option<String>,
+    pub methods: Vec<FunctionDecl>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryStmt {
+    pub body: Vec<StmtWrapper>,
+    // Name the caught value is bound to, and the handler's body
+    pub catch: Option<(String, Vec<StmtWrapper>)>,
+    pub finally: Option<Vec<StmtWrapper>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Block(Vec<StmtWrapper>),
+    Break,
+    Class(ClassDecl),
+    Continue,
+    Expression(Expr),
+    // Loop variable, the iterable and the body
+    ForIn(String, Expr, Box<StmtWrapper>),
+    Function(FunctionDecl),
+    If(Expr, Box<StmtWrapper>, Option<Box<StmtWrapper>>),
+    Print(Expr),
+    Return(Option<Expr>),
+    Throw(Expr),
+    Try(TryStmt),
+    Var(String, Option<Expr>),
+    // The increment of a desugared for loop runs after the body and on continue
+    While(Expr, Box<StmtWrapper>, Option<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StmtWrapper {
+    pub location_info: LocationInfo,
+    pub stmt: Stmt,
 }
 
 #[cfg(test)]
@@ -121,18 +393,14 @@ mod tests {
 
     #[test]
     fn test_ast_print() {
+        let number = |n| Box::new(Expr::from(ExprKind::Literal(Primitive::Number(n))));
         let pp = format!(
             "{}",
-            Expr::Binary(
+            Expr::from(ExprKind::Binary(
                 Operator::Star,
-                Box::new(Expr::Unary(
-                    Operator::Minus,
-                    Box::new(Expr::Literal(Primitive::Number(123.0)))
-                )),
-                Box::new(Expr::Grouping(Box::new(Expr::Literal(Primitive::Number(
-                    45.67
-                ))))),
-            )
+                Box::new(ExprKind::Unary(Operator::Minus, number(123.0)).into()),
+                Box::new(ExprKind::Grouping(number(45.67)).into()),
+            ))
         );
         assert_eq!(pp, "(* (- 123) (group 45.67))")
     }
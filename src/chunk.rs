@@ -0,0 +1,107 @@
+use crate::gc::{Trace, Tracer};
+use crate::value::Value;
+
+/// Instructions carry their operands inline. Jump offsets are relative to
+/// the instruction following the jump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(usize),
+    SetLocal(usize),
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    SetGlobal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Loop(usize),
+    Call(usize),
+    // Calls the named method on the receiver below the arguments
+    Invoke(usize, usize),
+    // Operand is the constant holding the property name
+    GetProperty(usize),
+    SetProperty(usize),
+    // Operand is the constant holding the class name
+    Class(usize),
+    // Adds the closure on top of the stack to the class below it
+    Method(usize),
+    // Copies the methods of the superclass below the class on top of the
+    // stack into it, then pops the class
+    Inherit,
+    // Replaces the receiver and superclass on top of the stack with the
+    // named superclass method bound to the receiver
+    GetSuper(usize),
+    // Collects the given number of values from the top of the stack
+    BuildList(usize),
+    // Collects the given number of key value pairs from the top of the stack
+    BuildMap(usize),
+    // Joins the given number of values from the top of the stack into a string
+    Interpolate(usize),
+    GetIndex,
+    SetIndex,
+    Range,
+    // Replaces the iterable on top of the stack with an iterator over it
+    GetIterator,
+    // Pushes the next item of the iterator in the local slot, or jumps when
+    // it is exhausted
+    ForIter(usize, usize),
+    // Errors raised until the matching PopHandler unwind to the handler at
+    // the given forward offset, with the error value pushed
+    PushHandler(usize),
+    PopHandler,
+    Throw,
+    // Raises the caught error on top of the stack again, keeping its trace
+    Rethrow,
+    // Operand is the constant holding the function to wrap
+    Closure(usize),
+    // Hoists the local on top of the stack into the heap before it is popped
+    CloseUpvalue,
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    // Source line and column of each instruction, for runtime errors
+    pub lines: Vec<usize>,
+    pub columns: Vec<usize>,
+    pub constants: Vec<Value>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, line: usize, column: usize) -> usize {
+        self.code.push(op);
+        self.lines.push(line);
+        self.columns.push(column);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        if let Some(index) = self.constants.iter().position(|c| *c == value) {
+            return index;
+        }
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+}
+
+impl Trace for Chunk {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.constants.trace(tracer);
+    }
+}
@@ -0,0 +1,880 @@
+use std::fmt;
+use std::mem;
+
+use crate::ast::{Expr, ExprKind, Operator, Primitive, Stmt, StmtWrapper, TryStmt};
+use crate::chunk::OpCode;
+use crate::gc::{Heap, ObjRef};
+use crate::object::{Function, Obj, UpvalueDescriptor};
+use crate::token::LocationInfo;
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+type CompileResult<T> = Result<T, CompileError>;
+
+// Name given to functions created by lambda expressions
+const ANONYMOUS: &str = "anonymous";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    // A class's init method, which always returns the instance
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    // None while the variable's initializer is being compiled
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+// Jumps out of a loop body, patched once the loop's end is known
+struct Loop {
+    // Locals declared deeper than this belong to the body
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// A try statement whose body or catch clause is being compiled
+struct Try {
+    // Locals and loops that were already open when the try started
+    locals: usize,
+    loops: usize,
+    // Handlers pushed by this try that are still installed
+    handlers: usize,
+    finally: Option<Vec<StmtWrapper>>,
+}
+
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // Loops being compiled, innermost last
+    loops: Vec<Loop>,
+    // Likewise for try statements
+    trys: Vec<Try>,
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> FunctionState {
+        FunctionState {
+            function: Function {
+                name,
+                ..Default::default()
+            },
+            kind,
+            // Slot zero holds the callee itself, or the receiver of a method
+            locals: vec![Local {
+                name: match kind {
+                    FunctionKind::Method | FunctionKind::Initializer => "this".to_owned(),
+                    _ => String::new(),
+                },
+                depth: Some(0),
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: vec![],
+            trys: vec![],
+        }
+    }
+}
+
+/// Lowers a parsed program into bytecode. Local variables are resolved to
+/// stack slots at compile time; variables of enclosing functions become
+/// upvalues, everything else is a global looked up by name.
+pub struct Compiler<'h> {
+    heap: &'h mut Heap,
+    // One entry per function being compiled, innermost last
+    states: Vec<FunctionState>,
+    // Class declarations being compiled, innermost last, with whether each
+    // has a superclass, for checking 'this' and 'super'
+    classes: Vec<bool>,
+    // Where the code being emitted was written
+    location_info: LocationInfo,
+}
+
+impl<'h> Compiler<'h> {
+    pub fn new(heap: &'h mut Heap) -> Compiler<'h> {
+        Compiler {
+            heap,
+            states: vec![],
+            classes: vec![],
+            location_info: LocationInfo { line: 0, column: 0 },
+        }
+    }
+
+    /// Compiles the top level script into a function object allocated on the heap.
+    pub fn compile(mut self, program: &[StmtWrapper]) -> CompileResult<ObjRef> {
+        self.states
+            .push(FunctionState::new(FunctionKind::Script, None));
+        for stmt in program.iter() {
+            self.statement(stmt)?;
+        }
+        let function = self.end_function();
+        Ok(self.heap.alloc(Obj::Function(function)))
+    }
+
+    fn statement(&mut self, wrapper: &StmtWrapper) -> CompileResult<()> {
+        let enclosing = mem::replace(&mut self.location_info, wrapper.location_info.clone());
+        match &wrapper.stmt {
+            Stmt::Block(statements) => self.block(statements)?,
+            Stmt::Break => {
+                let jump = self.loop_exit_jump("Can't use 'break' outside of a loop.")?;
+                self.innermost_loop().breaks.push(jump);
+            }
+            Stmt::Class(decl) => {
+                let name = self.identifier_constant(&decl.name);
+                let global = self.declare_variable(&decl.name)?;
+                self.emit(OpCode::Class(name));
+                self.define_variable(global);
+                self.classes.push(decl.superclass.is_some());
+                if let Some(superclass) = &decl.superclass {
+                    if *superclass == decl.name {
+                        return Err(self.error("A class can't inherit from itself."));
+                    }
+                    self.named_variable(superclass, None)?;
+                    // The superclass stays in a local that methods capture as 'super'
+                    self.begin_scope();
+                    self.declare_variable("super")?;
+                    self.define_variable(None);
+                    self.named_variable(&decl.name, None)?;
+                    self.emit(OpCode::Inherit);
+                }
+                // Load the class again so methods can be attached to it
+                self.named_variable(&decl.name, None)?;
+                for method in decl.methods.iter() {
+                    let kind = if method.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.function(kind, &method.name, &method.params, &method.body)?;
+                    let name = self.identifier_constant(&method.name);
+                    self.emit(OpCode::Method(name));
+                }
+                self.emit(OpCode::Pop);
+                if decl.superclass.is_some() {
+                    self.end_scope();
+                }
+                self.classes.pop();
+            }
+            Stmt::Continue => {
+                let jump = self.loop_exit_jump("Can't use 'continue' outside of a loop.")?;
+                self.innermost_loop().continues.push(jump);
+            }
+            Stmt::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop);
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                self.begin_scope();
+                self.expression(iterable)?;
+                self.emit(OpCode::GetIterator);
+                // The iterator lives in a local no name can refer to
+                let depth = self.state().scope_depth;
+                self.state_mut().locals.push(Local {
+                    name: String::new(),
+                    depth: Some(depth),
+                    is_captured: false,
+                });
+                let slot = self.state().locals.len() - 1;
+                let loop_start = self.code_len();
+                let exit_jump = self.emit(OpCode::ForIter(slot, 0));
+                self.state_mut().loops.push(Loop {
+                    scope_depth: depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                // A fresh variable per iteration, so closures capture one item each
+                self.begin_scope();
+                let global = self.declare_variable(name)?;
+                self.define_variable(global);
+                self.statement(body)?;
+                self.end_scope();
+                let Loop {
+                    breaks, continues, ..
+                } = self.state_mut().loops.pop().expect("Loop was pushed above");
+                for jump in continues {
+                    self.patch_jump(jump);
+                }
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                for jump in breaks {
+                    self.patch_jump(jump);
+                }
+                self.end_scope();
+            }
+            Stmt::Function(decl) => {
+                let global = self.declare_variable(&decl.name)?;
+                // Functions may refer to themselves
+                self.mark_initialized();
+                self.function(FunctionKind::Function, &decl.name, &decl.params, &decl.body)?;
+                self.define_variable(global);
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expression(condition)?;
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(then_branch)?;
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::Print(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Print);
+            }
+            Stmt::Return(value) => {
+                match (self.state().kind, value) {
+                    (FunctionKind::Script, _) => {
+                        return Err(self.error("Can't return from top-level code."))
+                    }
+                    (FunctionKind::Initializer, Some(_)) => {
+                        return Err(self.error("Can't return a value from an initializer."))
+                    }
+                    (_, Some(expr)) => self.expression(expr)?,
+                    (_, None) => self.emit_return_value(),
+                }
+                // The return value is held in a local while finally blocks run
+                let depth = self.state().scope_depth;
+                self.state_mut().locals.push(Local {
+                    name: String::new(),
+                    depth: Some(depth),
+                    is_captured: false,
+                });
+                self.exit_trys(0)?;
+                self.state_mut().locals.pop();
+                self.emit(OpCode::Return);
+            }
+            Stmt::Throw(value) => {
+                self.expression(value)?;
+                self.emit(OpCode::Throw);
+            }
+            Stmt::Try(stmt) => self.try_statement(stmt)?,
+            Stmt::Var(name, initializer) => {
+                let global = self.declare_variable(name)?;
+                match initializer {
+                    Some(expr) => self.expression(expr)?,
+                    None => {
+                        self.emit(OpCode::Nil);
+                    }
+                }
+                self.define_variable(global);
+            }
+            Stmt::While(condition, body, increment) => {
+                let loop_start = self.code_len();
+                self.expression(condition)?;
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                let scope_depth = self.state().scope_depth;
+                self.state_mut().loops.push(Loop {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.statement(body)?;
+                let Loop {
+                    breaks, continues, ..
+                } = self.state_mut().loops.pop().expect("Loop was pushed above");
+                for jump in continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+                // Breaks skip the pop of the condition, they jump from inside the body
+                for jump in breaks {
+                    self.patch_jump(jump);
+                }
+            }
+        }
+        self.location_info = enclosing;
+        Ok(())
+    }
+
+    // try { body } catch (e) { handler } finally { finally } compiles to:
+    //
+    //     PushHandler rethrow   (with a finally block)
+    //     PushHandler catch     (with a catch clause)
+    //     body
+    //     PopHandler
+    //     Jump end_catch
+    //   catch:
+    //     handler, with the error in the local e
+    //   end_catch:
+    //     PopHandler
+    //     finally
+    //     Jump end
+    //   rethrow:
+    //     finally, with the error in a hidden local
+    //     Rethrow
+    //   end:
+    //
+    // Jumps out of the body or the handler run a copy of the finally block.
+    fn try_statement(&mut self, stmt: &TryStmt) -> CompileResult<()> {
+        let (locals, loops) = (self.state().locals.len(), self.state().loops.len());
+        self.state_mut().trys.push(Try {
+            locals,
+            loops,
+            handlers: 0,
+            finally: stmt.finally.clone(),
+        });
+        let rethrow = stmt.finally.as_ref().map(|_| self.push_handler());
+        let catch = stmt.catch.as_ref().map(|_| self.push_handler());
+        self.block(&stmt.body)?;
+        if let (Some(catch), Some((name, handler))) = (catch, &stmt.catch) {
+            self.pop_handler();
+            let end_catch = self.emit(OpCode::Jump(0));
+            self.patch_jump(catch);
+            self.begin_scope();
+            let global = self.declare_variable(name)?;
+            self.define_variable(global);
+            for stmt in handler.iter() {
+                self.statement(stmt)?;
+            }
+            self.end_scope();
+            self.patch_jump(end_catch);
+        }
+        if rethrow.is_some() {
+            self.pop_handler();
+        }
+        self.state_mut().trys.pop();
+        if let (Some(rethrow), Some(finally)) = (rethrow, &stmt.finally) {
+            self.block(finally)?;
+            let end = self.emit(OpCode::Jump(0));
+            self.patch_jump(rethrow);
+            self.begin_scope();
+            let depth = self.state().scope_depth;
+            self.state_mut().locals.push(Local {
+                name: String::new(),
+                depth: Some(depth),
+                is_captured: false,
+            });
+            self.block(finally)?;
+            self.emit(OpCode::Rethrow);
+            // Nothing runs after the rethrow, so the error local needs no pop
+            self.state_mut().locals.pop();
+            self.state_mut().scope_depth -= 1;
+            self.patch_jump(end);
+        }
+        Ok(())
+    }
+
+    fn push_handler(&mut self) -> usize {
+        if let Some(innermost) = self.state_mut().trys.last_mut() {
+            innermost.handlers += 1;
+        }
+        self.emit(OpCode::PushHandler(0))
+    }
+
+    fn pop_handler(&mut self) {
+        if let Some(innermost) = self.state_mut().trys.last_mut() {
+            innermost.handlers -= 1;
+        }
+        self.emit(OpCode::PopHandler);
+    }
+
+    fn block(&mut self, statements: &[StmtWrapper]) -> CompileResult<()> {
+        self.begin_scope();
+        for stmt in statements.iter() {
+            self.statement(stmt)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    // Before jumping out of the try statements from index `first` on, removes
+    // their handlers and runs their finally blocks, innermost first
+    fn exit_trys(&mut self, first: usize) -> CompileResult<()> {
+        for index in (first..self.state().trys.len()).rev() {
+            for _ in 0..self.state().trys[index].handlers {
+                self.emit(OpCode::PopHandler);
+            }
+            let finally = match self.state().trys[index].finally.clone() {
+                Some(finally) => finally,
+                None => continue,
+            };
+            // The finally block only sees what was in scope at the try, and
+            // neither this try nor loops inside it enclose the copy
+            let state = self.state_mut();
+            let trys = state.trys.split_off(index);
+            let loops = state.loops.split_off(trys[0].loops);
+            let names: Vec<String> = state.locals[trys[0].locals..]
+                .iter_mut()
+                .map(|local| std::mem::take(&mut local.name))
+                .collect();
+            let result = self.block(&finally);
+            let state = self.state_mut();
+            for (local, name) in state.locals[trys[0].locals..].iter_mut().zip(names) {
+                local.name = name;
+            }
+            state.loops.extend(loops);
+            state.trys.extend(trys);
+            result?;
+        }
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        kind: FunctionKind,
+        name: &str,
+        params: &[String],
+        body: &[StmtWrapper],
+    ) -> CompileResult<()> {
+        let name = self.heap.intern(name);
+        self.states.push(FunctionState::new(kind, Some(name)));
+        self.begin_scope();
+        for param in params.iter() {
+            self.state_mut().function.arity += 1;
+            let global = self.declare_variable(param)?;
+            self.define_variable(global);
+        }
+        for stmt in body.iter() {
+            self.statement(stmt)?;
+        }
+        // No end_scope, the callee's frame is discarded wholesale on return
+        let function = self.end_function();
+        let function = self.heap.alloc(Obj::Function(function));
+        let constant = self.make_constant(Value::from(function));
+        self.emit(OpCode::Closure(constant));
+        Ok(())
+    }
+
+    fn end_function(&mut self) -> Function {
+        self.emit_return_value();
+        self.emit(OpCode::Return);
+        self.states
+            .pop()
+            .expect("Ended a function that was never started")
+            .function
+    }
+
+    // What a bare `return` gives back
+    fn emit_return_value(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> CompileResult<()> {
+        let enclosing = mem::replace(&mut self.location_info, expr.location_info.clone());
+        match &expr.kind {
+            ExprKind::Assign(name, value) => self.named_variable(name, Some(value))?,
+            ExprKind::Binary(operator, left, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                match operator {
+                    Operator::Plus => self.emit(OpCode::Add),
+                    Operator::Minus => self.emit(OpCode::Subtract),
+                    Operator::Star => self.emit(OpCode::Multiply),
+                    Operator::Divide => self.emit(OpCode::Divide),
+                    Operator::EqualEqual => self.emit(OpCode::Equal),
+                    Operator::BangEqual => {
+                        self.emit(OpCode::Equal);
+                        self.emit(OpCode::Not)
+                    }
+                    Operator::Greater => self.emit(OpCode::Greater),
+                    Operator::GreaterEqual => {
+                        self.emit(OpCode::Less);
+                        self.emit(OpCode::Not)
+                    }
+                    Operator::Less => self.emit(OpCode::Less),
+                    Operator::LessEqual => {
+                        self.emit(OpCode::Greater);
+                        self.emit(OpCode::Not)
+                    }
+                    Operator::Range => self.emit(OpCode::Range),
+                    _ => return Err(self.error(&format!("Invalid binary operator {}.", operator))),
+                };
+            }
+            ExprKind::Call(callee, args) => {
+                // Method calls skip materializing the method as a value
+                if let ExprKind::Get(object, name) = &callee.kind {
+                    self.expression(object)?;
+                    for arg in args.iter() {
+                        self.expression(arg)?;
+                    }
+                    let name = self.identifier_constant(name);
+                    self.emit(OpCode::Invoke(name, args.len()));
+                } else {
+                    self.expression(callee)?;
+                    for arg in args.iter() {
+                        self.expression(arg)?;
+                    }
+                    self.emit(OpCode::Call(args.len()));
+                }
+            }
+            ExprKind::Comma(left, right) => {
+                self.expression(left)?;
+                self.emit(OpCode::Pop);
+                self.expression(right)?;
+            }
+            ExprKind::Conditional(condition, then_expr, else_expr) => {
+                self.expression(condition)?;
+                let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.expression(then_expr)?;
+                let end_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop);
+                self.expression(else_expr)?;
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Get(object, name) => {
+                self.expression(object)?;
+                let name = self.identifier_constant(name);
+                self.emit(OpCode::GetProperty(name));
+            }
+            ExprKind::Grouping(expr) => self.expression(expr)?,
+            ExprKind::Index(object, index) => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.emit(OpCode::GetIndex);
+            }
+            ExprKind::Interpolation(parts) => {
+                for part in parts.iter() {
+                    self.expression(part)?;
+                }
+                self.emit(OpCode::Interpolate(parts.len()));
+            }
+            ExprKind::List(elements) => {
+                for element in elements.iter() {
+                    self.expression(element)?;
+                }
+                self.emit(OpCode::BuildList(elements.len()));
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries.iter() {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.emit(OpCode::BuildMap(entries.len()));
+            }
+            ExprKind::Set(object, name, value) => {
+                self.expression(object)?;
+                self.expression(value)?;
+                let name = self.identifier_constant(name);
+                self.emit(OpCode::SetProperty(name));
+            }
+            ExprKind::This => {
+                if self.classes.is_empty() {
+                    return Err(self.error("Can't use 'this' outside of a class."));
+                }
+                self.named_variable("this", None)?;
+            }
+            ExprKind::Super(method) => {
+                match self.classes.last() {
+                    None => return Err(self.error("Can't use 'super' outside of a class.")),
+                    Some(false) => {
+                        return Err(self.error("Can't use 'super' in a class with no superclass."))
+                    }
+                    Some(true) => {}
+                }
+                self.named_variable("this", None)?;
+                self.named_variable("super", None)?;
+                let name = self.identifier_constant(method);
+                self.emit(OpCode::GetSuper(name));
+            }
+            ExprKind::SetIndex(object, index, value) => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.emit(OpCode::SetIndex);
+            }
+            ExprKind::Lambda(params, body) => {
+                self.function(FunctionKind::Function, ANONYMOUS, params, body)?
+            }
+            ExprKind::Literal(primitive) => {
+                match primitive {
+                    Primitive::Number(n) => self.emit_constant(Value::from(*n)),
+                    Primitive::String(s) => {
+                        let s = self.heap.intern(s);
+                        self.emit_constant(Value::from(s))
+                    }
+                    Primitive::Boolean(true) => self.emit(OpCode::True),
+                    Primitive::Boolean(false) => self.emit(OpCode::False),
+                    Primitive::Nil => self.emit(OpCode::Nil),
+                };
+            }
+            ExprKind::Logical(Operator::And, left, right) => {
+                self.expression(left)?;
+                let end_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.expression(right)?;
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Logical(Operator::Or, left, right) => {
+                self.expression(left)?;
+                let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                let end_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop);
+                self.expression(right)?;
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Logical(operator, _, _) => {
+                return Err(self.error(&format!("Invalid logical operator {}.", operator)))
+            }
+            ExprKind::Unary(operator, expr) => {
+                self.expression(expr)?;
+                match operator {
+                    Operator::Minus => self.emit(OpCode::Negate),
+                    Operator::Bang => self.emit(OpCode::Not),
+                    _ => return Err(self.error(&format!("Invalid unary operator {}.", operator))),
+                };
+            }
+            ExprKind::Variable(name) => self.named_variable(name, None)?,
+        }
+        self.location_info = enclosing;
+        Ok(())
+    }
+
+    fn named_variable(&mut self, name: &str, assignment: Option<&Expr>) -> CompileResult<()> {
+        let current = self.states.len() - 1;
+        let (get, set) = if let Some(slot) = self.resolve_local(current, name)? {
+            (OpCode::GetLocal(slot), OpCode::SetLocal(slot))
+        } else if let Some(index) = self.resolve_upvalue(current, name)? {
+            (OpCode::GetUpvalue(index), OpCode::SetUpvalue(index))
+        } else {
+            let constant = self.identifier_constant(name);
+            (OpCode::GetGlobal(constant), OpCode::SetGlobal(constant))
+        };
+        match assignment {
+            Some(value) => {
+                self.expression(value)?;
+                self.emit(set);
+            }
+            None => {
+                self.emit(get);
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_local(&self, state: usize, name: &str) -> CompileResult<Option<usize>> {
+        for (slot, local) in self.states[state].locals.iter().enumerate().rev() {
+            if local.name == name {
+                if local.depth.is_none() {
+                    return Err(self.error("Can't read local variable in its own initializer."));
+                }
+                return Ok(Some(slot));
+            }
+        }
+        Ok(None)
+    }
+
+    fn resolve_upvalue(&mut self, state: usize, name: &str) -> CompileResult<Option<usize>> {
+        if state == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(state - 1, name)? {
+            self.states[state - 1].locals[slot].is_captured = true;
+            return Ok(Some(self.add_upvalue(state, true, slot)));
+        }
+        if let Some(index) = self.resolve_upvalue(state - 1, name)? {
+            return Ok(Some(self.add_upvalue(state, false, index)));
+        }
+        Ok(None)
+    }
+
+    fn add_upvalue(&mut self, state: usize, is_local: bool, index: usize) -> usize {
+        let upvalue = UpvalueDescriptor { is_local, index };
+        let upvalues = &mut self.states[state].function.upvalues;
+        // Closures capturing the same variable twice share one upvalue
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing;
+        }
+        upvalues.push(upvalue);
+        upvalues.len() - 1
+    }
+
+    // Returns the name constant for globals, locals live in the next stack slot
+    fn declare_variable(&mut self, name: &str) -> CompileResult<Option<usize>> {
+        if self.state().scope_depth == 0 {
+            return Ok(Some(self.identifier_constant(name)));
+        }
+        let state = self.state();
+        let duplicate = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|d| d >= state.scope_depth))
+            .any(|local| local.name == name);
+        if duplicate {
+            return Err(self.error("Already a variable with this name in this scope."));
+        }
+        self.state_mut().locals.push(Local {
+            name: name.to_owned(),
+            depth: None,
+            is_captured: false,
+        });
+        Ok(None)
+    }
+
+    fn define_variable(&mut self, global: Option<usize>) {
+        match global {
+            Some(constant) => {
+                self.emit(OpCode::DefineGlobal(constant));
+            }
+            None => self.mark_initialized(),
+        }
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state_mut().scope_depth -= 1;
+        loop {
+            let state = self.state();
+            let captured = match state.locals.last() {
+                Some(local) if local.depth.is_some_and(|d| d > state.scope_depth) => {
+                    local.is_captured
+                }
+                _ => break,
+            };
+            self.emit(if captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+            self.state_mut().locals.pop();
+        }
+    }
+
+    // Discards the loop body's locals and emits a jump to be patched by the loop
+    fn loop_exit_jump(&mut self, message: &str) -> CompileResult<usize> {
+        let depth = match self.state().loops.last() {
+            Some(innermost) => innermost.scope_depth,
+            None => return Err(self.error(message)),
+        };
+        // Try statements inside the loop body are exited too
+        let loops = self.state().loops.len();
+        let first = self
+            .state()
+            .trys
+            .iter()
+            .position(|t| t.loops >= loops)
+            .unwrap_or(self.state().trys.len());
+        self.exit_trys(first)?;
+        let ops: Vec<OpCode> = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_some_and(|d| d > depth))
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit(op);
+        }
+        Ok(self.emit(OpCode::Jump(0)))
+    }
+
+    fn innermost_loop(&mut self) -> &mut Loop {
+        self.state_mut()
+            .loops
+            .last_mut()
+            .expect("Checked by loop_exit_jump")
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> usize {
+        let name = self.heap.intern(name);
+        self.make_constant(Value::from(name))
+    }
+
+    fn make_constant(&mut self, value: Value) -> usize {
+        self.state_mut().function.chunk.add_constant(value)
+    }
+
+    fn emit_constant(&mut self, value: Value) -> usize {
+        let constant = self.make_constant(value);
+        self.emit(OpCode::Constant(constant))
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let LocationInfo { line, column } = self.location_info;
+        self.state_mut().function.chunk.write(op, line, column)
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        // +1 to also jump back over the Loop instruction itself
+        let offset = self.code_len() - loop_start + 1;
+        self.emit(OpCode::Loop(offset));
+    }
+
+    fn patch_jump(&mut self, at: usize) {
+        let offset = self.code_len() - at - 1;
+        let code = &mut self.state_mut().function.chunk.code;
+        code[at] = match code[at] {
+            OpCode::Jump(_) => OpCode::Jump(offset),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(offset),
+            OpCode::ForIter(slot, _) => OpCode::ForIter(slot, offset),
+            OpCode::PushHandler(_) => OpCode::PushHandler(offset),
+            op => panic!("Tried to patch non jump instruction {:?}", op),
+        };
+    }
+
+    fn code_len(&self) -> usize {
+        self.state().function.chunk.code.len()
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states.last().expect("No function being compiled")
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("No function being compiled")
+    }
+
+    fn error(&self, message: &str) -> CompileError {
+        CompileError {
+            line: self.location_info.line,
+            message: message.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+use crate::object::Obj;
+use crate::value::Value;
+
+// After a collection the next one is scheduled once the live heap has grown by this factor
+pub const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+/// Handle to an object owned by a `Heap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(u32);
+
+impl ObjRef {
+    pub fn from_index(index: usize) -> ObjRef {
+        ObjRef(index as u32)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Implemented by everything that can hold references into the heap: the
+/// objects themselves and whatever an evaluator backend uses as its roots
+/// (value stack, globals, open upvalues, call frames, ...).
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+impl Trace for Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark_value(*self);
+    }
+}
+
+impl Trace for ObjRef {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(*self);
+    }
+}
+
+impl<T: Trace> Trace for [T] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in self.iter() {
+            item.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.as_slice().trace(tracer);
+    }
+}
+
+impl<K: Trace, V: Trace> Trace for HashMap<K, V> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (k, v) in self.iter() {
+            k.trace(tracer);
+            v.trace(tracer);
+        }
+    }
+}
+
+/// Worklist of objects reached during the mark phase.
+#[derive(Default)]
+pub struct Tracer {
+    gray: Vec<ObjRef>,
+}
+
+impl Tracer {
+    pub fn mark(&mut self, r: ObjRef) {
+        self.gray.push(r);
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Some(r) = value.as_obj() {
+            self.mark(r);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcConfig {
+    // Collect before every allocation, used to shake out missing roots
+    pub stress: bool,
+    // Record every collection, for the host to report with the summary
+    pub log: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    pub objects_allocated: usize,
+    pub objects_freed: usize,
+    pub bytes_freed: usize,
+    pub peak_bytes: usize,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "gc: {} collections, {} objects allocated, {} objects freed, {} bytes freed, peak {} bytes",
+            self.collections,
+            self.objects_allocated,
+            self.objects_freed,
+            self.bytes_freed,
+            self.peak_bytes
+        )
+    }
+}
+
+/// What a single collection did.
+#[derive(Debug, Clone, Copy)]
+pub struct Collection {
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+    pub objects_freed: usize,
+    pub next_gc: usize,
+}
+
+impl fmt::Display for Collection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "gc: collected {} bytes in {} objects (from {} to {}) next at {}",
+            self.bytes_before - self.bytes_after,
+            self.objects_freed,
+            self.bytes_before,
+            self.bytes_after,
+            self.next_gc
+        )
+    }
+}
+
+struct HeapEntry {
+    marked: bool,
+    size: usize,
+    obj: Obj,
+}
+
+/// Owner of every runtime object, reclaimed by a tracing mark-and-sweep
+/// collector. The heap never collects on its own: the backend checks
+/// `should_collect` before allocating and calls `collect` with its roots,
+/// so objects it is still working with are never swept from under it.
+pub struct Heap {
+    entries: Vec<Option<HeapEntry>>,
+    free_slots: Vec<u32>,
+    // Interned strings, weak: entries are dropped when the string is swept
+    strings: HashMap<String, ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+    stats: GcStats,
+    // Collections not yet taken by the host, only kept when logging
+    log: Vec<Collection>,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Heap {
+        Heap {
+            entries: vec![],
+            free_slots: vec![],
+            strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            config,
+            stats: Default::default(),
+            log: vec![],
+        }
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        let size = obj.size();
+        self.bytes_allocated += size;
+        self.stats.objects_allocated += 1;
+        if self.bytes_allocated > self.stats.peak_bytes {
+            self.stats.peak_bytes = self.bytes_allocated;
+        }
+        let entry = HeapEntry {
+            marked: false,
+            size,
+            obj,
+        };
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.entries[slot as usize] = Some(entry);
+                ObjRef(slot)
+            }
+            None => {
+                self.entries.push(Some(entry));
+                ObjRef((self.entries.len() - 1) as u32)
+            }
+        }
+    }
+
+    pub fn intern(&mut self, s: &str) -> ObjRef {
+        if let Some(r) = self.strings.get(s) {
+            return *r;
+        }
+        let r = self.alloc(Obj::String(s.to_owned()));
+        self.strings.insert(s.to_owned(), r);
+        r
+    }
+
+    /// The interned string `s`, without interning it if it isn't yet.
+    pub fn interned(&self, s: &str) -> Option<ObjRef> {
+        self.strings.get(s).copied()
+    }
+
+    pub fn get(&self, r: ObjRef) -> &Obj {
+        &self.entries[r.index()]
+            .as_ref()
+            .expect("Dangling reference into the heap")
+            .obj
+    }
+
+    pub fn get_mut(&mut self, r: ObjRef) -> &mut Obj {
+        &mut self.entries[r.index()]
+            .as_mut()
+            .expect("Dangling reference into the heap")
+            .obj
+    }
+
+    /// Recounts an object that grew or shrank in place, like a list after a
+    /// push, so it weighs on the next collection and the heap limit.
+    pub fn resize(&mut self, r: ObjRef) {
+        let entry = self.entries[r.index()]
+            .as_mut()
+            .expect("Dangling reference into the heap");
+        let size = entry.obj.size();
+        self.bytes_allocated = self.bytes_allocated - entry.size + size;
+        entry.size = size;
+        if self.bytes_allocated > self.stats.peak_bytes {
+            self.stats.peak_bytes = self.bytes_allocated;
+        }
+    }
+
+    pub fn is_live(&self, r: ObjRef) -> bool {
+        matches!(self.entries.get(r.index()), Some(Some(_)))
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.entries.len() - self.free_slots.len()
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn config(&self) -> GcConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// The collections made since the last call, if logging is on.
+    pub fn take_log(&mut self) -> Vec<Collection> {
+        mem::take(&mut self.log)
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Frees every object not reachable from `roots`, returning the number of bytes freed.
+    pub fn collect(&mut self, roots: &dyn Trace) -> usize {
+        let before = self.bytes_allocated;
+        self.mark(roots);
+        let entries = &self.entries;
+        self.strings.retain(|_, r| match &entries[r.index()] {
+            Some(entry) => entry.marked,
+            None => false,
+        });
+        let freed_objects = self.sweep();
+
+        let freed = before - self.bytes_allocated;
+        self.next_gc = std::cmp::max(
+            self.bytes_allocated * GC_HEAP_GROW_FACTOR,
+            GC_INITIAL_THRESHOLD,
+        );
+        self.stats.collections += 1;
+        self.stats.objects_freed += freed_objects;
+        self.stats.bytes_freed += freed;
+        if self.config.log {
+            self.log.push(Collection {
+                bytes_before: before,
+                bytes_after: self.bytes_allocated,
+                objects_freed: freed_objects,
+                next_gc: self.next_gc,
+            });
+        }
+        freed
+    }
+
+    fn mark(&mut self, roots: &dyn Trace) {
+        let mut tracer = Tracer::default();
+        roots.trace(&mut tracer);
+        while let Some(r) = tracer.gray.pop() {
+            let entry = self.entries[r.index()]
+                .as_mut()
+                .expect("Traced a dangling reference");
+            if entry.marked {
+                continue;
+            }
+            entry.marked = true;
+            entry.obj.trace(&mut tracer);
+        }
+    }
+
+    fn sweep(&mut self) -> usize {
+        let mut freed_objects = 0;
+        for (i, slot) in self.entries.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.marked => entry.marked = false,
+                Some(entry) => {
+                    self.bytes_allocated -= entry.size;
+                    freed_objects += 1;
+                    *slot = None;
+                    self.free_slots.push(i as u32);
+                }
+                None => {}
+            }
+        }
+        freed_objects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Class, Closure, Function, Instance, Upvalue};
+
+    fn instance_closure_cycle(heap: &mut Heap) -> ObjRef {
+        let name = heap.intern("Point");
+        let class = heap.alloc(Obj::Class(Class::new(name)));
+        let instance = heap.alloc(Obj::Instance(Instance::new(class)));
+        let upvalue = heap.alloc(Obj::Upvalue(Upvalue::Closed(Value::from(instance))));
+        let function = heap.alloc(Obj::Function(Function::default()));
+        let closure = heap.alloc(Obj::Closure(Closure {
+            function,
+            upvalues: vec![upvalue],
+        }));
+        let field = heap.intern("callback");
+        if let Obj::Instance(i) = heap.get_mut(instance) {
+            i.fields.insert(field, Value::from(closure));
+        }
+        instance
+    }
+
+    #[test]
+    fn test_collect_unreachable_cycle() {
+        let mut heap = Heap::new(Default::default());
+        instance_closure_cycle(&mut heap);
+        assert_eq!(heap.object_count(), 7);
+
+        let roots: Vec<Value> = vec![];
+        heap.collect(&roots);
+        assert_eq!(heap.object_count(), 0);
+        assert_eq!(heap.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn test_collect_keeps_rooted_objects() {
+        let mut heap = Heap::new(Default::default());
+        let instance = instance_closure_cycle(&mut heap);
+        let garbage = heap.alloc(Obj::String("garbage".to_owned()));
+
+        let roots = vec![Value::from(instance), Value::from(1.0)];
+        heap.collect(&roots);
+        assert_eq!(heap.object_count(), 7);
+        assert!(heap.is_live(instance));
+        assert!(!heap.is_live(garbage));
+        assert_eq!(heap.stats().objects_freed, 1);
+    }
+
+    #[test]
+    fn test_interned_strings_are_weak() {
+        let mut heap = Heap::new(Default::default());
+        let kept = heap.intern("kept");
+        heap.intern("dropped");
+        assert_eq!(heap.intern("kept"), kept);
+        assert_eq!(heap.interned("kept"), Some(kept));
+        assert_eq!(heap.interned("missing"), None);
+
+        heap.collect(&vec![kept]);
+        assert_eq!(heap.interned("dropped"), None);
+        assert_eq!(heap.object_count(), 1);
+        let dropped = heap.intern("dropped");
+        assert!(matches!(heap.get(dropped), Obj::String(s) if s == "dropped"));
+        assert_eq!(heap.intern("kept"), kept);
+    }
+
+    #[test]
+    fn test_should_collect() {
+        let mut heap = Heap::new(Default::default());
+        heap.alloc(Obj::String("small".to_owned()));
+        assert!(!heap.should_collect());
+
+        heap.alloc(Obj::String("x".repeat(GC_INITIAL_THRESHOLD)));
+        assert!(heap.should_collect());
+        heap.collect(&Vec::<Value>::new());
+        assert!(!heap.should_collect());
+
+        let stressed = Heap::new(GcConfig {
+            stress: true,
+            log: false,
+        });
+        assert!(stressed.should_collect());
+    }
+
+    #[test]
+    fn test_log() {
+        let mut heap = Heap::new(Default::default());
+        heap.collect(&Vec::<Value>::new());
+        assert!(heap.take_log().is_empty());
+
+        let mut heap = Heap::new(GcConfig {
+            stress: false,
+            log: true,
+        });
+        let kept = heap.intern("kept");
+        heap.intern("dropped");
+        heap.collect(&vec![kept]);
+        heap.collect(&vec![kept]);
+        let log = heap.take_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].objects_freed, 1);
+        assert_eq!(log[0].bytes_after, heap.bytes_allocated());
+        assert_eq!(log[1].objects_freed, 0);
+        assert!(heap.take_log().is_empty());
+    }
+}
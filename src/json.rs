@@ -0,0 +1,674 @@
+use std::fmt;
+
+use crate::gc::ObjRef;
+use crate::native::{self, Arity, NativeClass};
+use crate::object::Obj;
+use crate::value::{Value, ValueKind};
+use crate::vm::{RuntimeError, Vm};
+
+// Deeper documents are refused rather than overflowing the Rust stack
+const MAX_DEPTH: usize = 512;
+// The most spaces per level JSON.stringify accepts, as in JavaScript
+const MAX_INDENT: usize = 10;
+
+/// A parsed JSON document, before it becomes Lox values with
+/// `Vm::alloc_json`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Members in document order, a repeated key keeps its last value
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub line: usize,
+    // Counted in characters, from 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}.",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+pub fn parse(source: &str) -> Result<Json, JsonError> {
+    let mut parser = JsonParser {
+        chars: source.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let json = parser.value()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        Some(c) => Err(parser.error(&format!("Unexpected '{}' after the value", c))),
+        None => Ok(json),
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+    // Arrays and objects currently open
+    depth: usize,
+}
+
+impl JsonParser {
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.nested(JsonParser::object),
+            Some('[') => self.nested(JsonParser::array),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.expected("a value")),
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Json, JsonError>
+    where
+        F: Fn(&mut JsonParser) -> Result<Json, JsonError>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Too deeply nested"));
+        }
+        self.depth += 1;
+        let json = parse(self)?;
+        self.depth -= 1;
+        Ok(json)
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.pos += 1;
+        let mut members = vec![];
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.expected("a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(':') {
+                return Err(self.expected("':'"));
+            }
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(',') {
+                return Err(self.expected("',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.pos += 1;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(',') {
+                return Err(self.expected("',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some('\\') => s.push(self.escape()?),
+                Some(c) if c < ' ' => return Err(self.error("Control character in string")),
+                Some(c) => {
+                    self.pos += 1;
+                    s.push(c);
+                }
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let start = self.pos;
+        self.pos += 1;
+        let c = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.pos += 1;
+                return self.unicode_escape(start);
+            }
+            _ => {
+                self.pos = start;
+                return Err(self.error("Invalid escape sequence"));
+            }
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    // The digits of \uXXXX, surrogate pairs arrive as two escapes
+    fn unicode_escape(&mut self, start: usize) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xd800..=0xdbff if self.chars[self.pos..].starts_with(&['\\', 'u']) => {
+                self.pos += 2;
+                match self.hex4()? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => 0xd800,
+                }
+            }
+            _ => high,
+        };
+        std::char::from_u32(code).ok_or_else(|| {
+            self.pos = start;
+            self.error("Unpaired surrogate in unicode escape")
+        })
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .peek()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.expected("a hex digit"))?;
+            code = code * 16 + digit;
+            self.pos += 1;
+        }
+        Ok(code)
+    }
+
+    // -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        self.eat('-');
+        if !self.eat('0') {
+            self.digits()?;
+        }
+        if self.eat('.') {
+            self.digits()?;
+        }
+        if self.eat('e') || self.eat('E') {
+            if !self.eat('+') {
+                self.eat('-');
+            }
+            self.digits()?;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        let n = text.parse().expect("Number was scanned by the grammar");
+        Ok(Json::Number(n))
+    }
+
+    fn digits(&mut self) -> Result<(), JsonError> {
+        if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            return Err(self.expected("a digit"));
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Result<Json, JsonError> {
+        for expected in word.chars() {
+            if !self.eat(expected) {
+                return Err(self.expected(&format!("'{}'", word)));
+            }
+        }
+        Ok(json)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn expected(&self, what: &str) -> JsonError {
+        match self.peek() {
+            Some(c) => self.error(&format!("Expected {} but found '{}'", what, c)),
+            None => self.error(&format!("Expected {} but found the end", what)),
+        }
+    }
+
+    // At the current character
+    fn error(&self, message: &str) -> JsonError {
+        let before = &self.chars[..self.pos];
+        let line_start = before.iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+        JsonError {
+            line: 1 + before.iter().filter(|&&c| c == '\n').count(),
+            column: 1 + self.pos - line_start,
+            message: message.to_owned(),
+        }
+    }
+}
+
+/// Serializes lists, maps with string keys, strings, numbers, booleans and
+/// nil. Nested values go on their own lines when `indent` is nonzero.
+pub fn stringify(vm: &Vm, value: Value, indent: usize) -> Result<String, RuntimeError> {
+    let mut writer = JsonWriter {
+        vm,
+        indent,
+        out: String::new(),
+        open: vec![],
+    };
+    writer.value(value)?;
+    Ok(writer.out)
+}
+
+struct JsonWriter<'a> {
+    vm: &'a Vm,
+    indent: usize,
+    out: String,
+    // Lists and maps being written, to catch cycles
+    open: Vec<ObjRef>,
+}
+
+impl JsonWriter<'_> {
+    fn value(&mut self, value: Value) -> Result<(), RuntimeError> {
+        let vm = self.vm;
+        match value.kind() {
+            ValueKind::Nil => self.out.push_str("null"),
+            ValueKind::Bool(b) => self.out.push_str(if b { "true" } else { "false" }),
+            ValueKind::Number(n) if n.is_finite() => self.out.push_str(&n.to_string()),
+            ValueKind::Number(_) => {
+                return Err(vm.error("Can't convert NaN or infinity to JSON."));
+            }
+            ValueKind::Obj(r) => match vm.heap().get(r) {
+                Obj::String(s) => self.string(s),
+                Obj::List(items) => {
+                    self.open(r)?;
+                    self.out.push('[');
+                    for (i, item) in items.iter().enumerate() {
+                        self.separator(i);
+                        self.value(*item)?;
+                    }
+                    self.close(!items.is_empty(), ']');
+                }
+                Obj::Map(map) => {
+                    self.open(r)?;
+                    self.out.push('{');
+                    for (i, (key, value)) in map.entries().iter().enumerate() {
+                        let key = vm
+                            .as_string(*key)
+                            .ok_or_else(|| vm.error("JSON object keys must be strings."))?;
+                        self.separator(i);
+                        self.string(key);
+                        self.out.push_str(if self.indent > 0 { ": " } else { ":" });
+                        self.value(*value)?;
+                    }
+                    self.close(!map.is_empty(), '}');
+                }
+                _ => {
+                    let message = format!(
+                        "Can't convert a value of type '{}' to JSON.",
+                        native::type_name(vm, value)
+                    );
+                    return Err(vm.error(&message));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, container: ObjRef) -> Result<(), RuntimeError> {
+        if self.open.contains(&container) {
+            return Err(self.vm.error("Can't convert a cyclic structure to JSON."));
+        }
+        if self.open.len() == MAX_DEPTH {
+            return Err(self.vm.error("Too deeply nested to convert to JSON."));
+        }
+        self.open.push(container);
+        Ok(())
+    }
+
+    fn close(&mut self, has_items: bool, bracket: char) {
+        self.open.pop();
+        if has_items {
+            self.newline();
+        }
+        self.out.push(bracket);
+    }
+
+    // Before the item at index i of the innermost open container
+    fn separator(&mut self, i: usize) {
+        if i > 0 {
+            self.out.push(',');
+        }
+        self.newline();
+    }
+
+    fn newline(&mut self) {
+        if self.indent > 0 {
+            self.out.push('\n');
+            self.out
+                .push_str(&" ".repeat(self.indent * self.open.len()));
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if c < ' ' => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+/// The `JSON` namespace.
+pub fn class() -> NativeClass {
+    NativeClass::new("JSON")
+        .method("parse", Arity::Fixed(1), |vm, _, args| {
+            let source = vm
+                .as_string(args[0])
+                .ok_or_else(|| vm.error("Argument must be a string."))?;
+            let json = parse(source).map_err(|err| vm.error(&err.to_string()))?;
+            Ok(vm.alloc_json(&json))
+        })
+        // stringify(value) or stringify(value, indent)
+        .method("stringify", Arity::Variadic, |vm, _, args| {
+            let (value, indent) = match args {
+                [value] => (*value, 0),
+                [value, indent] if matches!(indent.kind(), ValueKind::Nil) => (*value, 0),
+                [value, indent] => match indent.as_number() {
+                    Some(n) if n >= 0.0 && n <= MAX_INDENT as f64 && n.fract() == 0.0 => {
+                        (*value, n as usize)
+                    }
+                    _ => {
+                        return Err(vm.error(&format!(
+                            "Indent must be an integer from 0 to {}.",
+                            MAX_INDENT
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(vm.error(&format!(
+                        "Expected 1 or 2 arguments but got {}.",
+                        args.len()
+                    )))
+                }
+            };
+            let json = stringify(vm, value, indent)?;
+            Ok(vm.new_string(&json))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{gc_configs, global, interpret};
+    use crate::vm::InterpretError;
+
+    #[test]
+    fn test_parse() {
+        let test_table = vec![
+            ("null", Json::Null),
+            (" true ", Json::Bool(true)),
+            ("-0.5e2", Json::Number(-50.0)),
+            ("10E-1", Json::Number(1.0)),
+            (
+                "\"a\\\"\\\\\\/\\n\\u00e9\\ud83d\\ude00\"",
+                Json::String("a\"\\/\né😀".to_owned()),
+            ),
+            ("[]", Json::Array(vec![])),
+            (
+                "[1, [false], {}]",
+                Json::Array(vec![
+                    Json::Number(1.0),
+                    Json::Array(vec![Json::Bool(false)]),
+                    Json::Object(vec![]),
+                ]),
+            ),
+            (
+                "{\"b\": 1,\n \"a\": null}",
+                Json::Object(vec![
+                    ("b".to_owned(), Json::Number(1.0)),
+                    ("a".to_owned(), Json::Null),
+                ]),
+            ),
+        ];
+        for (source, expected) in test_table {
+            assert_eq!(parse(source), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let nested = "[".repeat(MAX_DEPTH + 1);
+        let test_table = vec![
+            (
+                "",
+                "Expected a value but found the end at line 1, column 1.",
+            ),
+            (
+                "[1, 2",
+                "Expected ',' or ']' but found the end at line 1, column 6.",
+            ),
+            (
+                "[1 2]",
+                "Expected ',' or ']' but found '2' at line 1, column 4.",
+            ),
+            (
+                "[1,]",
+                "Expected a value but found ']' at line 1, column 4.",
+            ),
+            (
+                "{\n  \"a\" 1}",
+                "Expected ':' but found '1' at line 2, column 7.",
+            ),
+            (
+                "{\"a\": 1,}",
+                "Expected a string key but found '}' at line 1, column 9.",
+            ),
+            (
+                "{a: 1}",
+                "Expected a string key but found 'a' at line 1, column 2.",
+            ),
+            (
+                "tru",
+                "Expected 'true' but found the end at line 1, column 4.",
+            ),
+            ("nul!", "Expected 'null' but found '!' at line 1, column 4."),
+            (
+                "-",
+                "Expected a digit but found the end at line 1, column 2.",
+            ),
+            (
+                "1.",
+                "Expected a digit but found the end at line 1, column 3.",
+            ),
+            ("01", "Unexpected '1' after the value at line 1, column 2."),
+            ("\"abc", "Unterminated string at line 1, column 5."),
+            (
+                "\"a\nb\"",
+                "Control character in string at line 1, column 3.",
+            ),
+            ("\n\"\\x\"", "Invalid escape sequence at line 2, column 2."),
+            (
+                "\"\\u12g4\"",
+                "Expected a hex digit but found 'g' at line 1, column 6.",
+            ),
+            (
+                "\"\\udc00\"",
+                "Unpaired surrogate in unicode escape at line 1, column 2.",
+            ),
+            (
+                "\"é\\ud800x\"",
+                "Unpaired surrogate in unicode escape at line 1, column 3.",
+            ),
+            (
+                "{} {}",
+                "Unexpected '{' after the value at line 1, column 4.",
+            ),
+            (&nested, "Too deeply nested at line 1, column 513."),
+        ];
+        for (source, message) in test_table {
+            match parse(source) {
+                Err(err) => assert_eq!(err.to_string(), message, "{}", source),
+                Ok(json) => panic!("Expected an error for {}, got {:?}", source, json),
+            }
+        }
+    }
+
+    // Lox strings have no escapes, the host passes in `source` and `text`
+    const JSON_SCRIPT: &str = "
+        var data = JSON.parse(source);
+        var name = data[\"name\"];
+        var tags = data[\"tags\"];
+        var compact = JSON.stringify(data);
+        var pretty = JSON.stringify(data, 2);
+        var again = JSON.stringify(JSON.parse(pretty)) == compact;
+        var scalars = [JSON.stringify(nil), JSON.stringify(text), JSON.stringify(-3), JSON.stringify({})];
+        var shared = [1];
+        var dag = JSON.stringify([shared, shared], nil);
+    ";
+
+    #[test]
+    fn test_json_namespace() {
+        for config in gc_configs() {
+            let mut vm = Vm::new(config);
+            let source = vm.new_string(
+                r#"{"name": "lox", "tags": [1, 2.5, true, null], "nested": {"empty": []}}"#,
+            );
+            vm.set_global("source", source);
+            let text = vm.new_string("a\"b\n");
+            vm.set_global("text", text);
+            interpret(&mut vm, JSON_SCRIPT).expect("Script failed");
+            assert_eq!(global(&vm, "name"), "lox");
+            assert_eq!(global(&vm, "tags"), "[1, 2.5, true, nil]");
+            assert_eq!(
+                global(&vm, "compact"),
+                r#"{"name":"lox","tags":[1,2.5,true,null],"nested":{"empty":[]}}"#
+            );
+            assert_eq!(
+                global(&vm, "pretty"),
+                "{\n  \"name\": \"lox\",\n  \"tags\": [\n    1,\n    2.5,\n    true,\n    null\n  ],\n  \"nested\": {\n    \"empty\": []\n  }\n}"
+            );
+            assert_eq!(global(&vm, "again"), "true");
+            assert_eq!(
+                global(&vm, "scalars"),
+                r#"["null", ""a\"b\n"", "-3", "{}"]"#
+            );
+            assert_eq!(global(&vm, "dag"), "[[1],[1]]");
+        }
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let test_table = vec![
+            ("JSON.parse(1);", "Argument must be a string."),
+            (
+                "JSON.parse(\"[1,\");",
+                "Expected a value but found the end at line 1, column 4.",
+            ),
+            ("JSON.stringify();", "Expected 1 or 2 arguments but got 0."),
+            (
+                "JSON.stringify(1, -1);",
+                "Indent must be an integer from 0 to 10.",
+            ),
+            (
+                "JSON.stringify(1, \"  \");",
+                "Indent must be an integer from 0 to 10.",
+            ),
+            (
+                "JSON.stringify(1, 1.5);",
+                "Indent must be an integer from 0 to 10.",
+            ),
+            (
+                "JSON.stringify([[1]], 11);",
+                "Indent must be an integer from 0 to 10.",
+            ),
+            (
+                "JSON.stringify([[1]], 1000000000000000000);",
+                "Indent must be an integer from 0 to 10.",
+            ),
+            (
+                "var l = [1]; l.push(l); JSON.stringify(l);",
+                "Can't convert a cyclic structure to JSON.",
+            ),
+            (
+                "var m = {}; m[\"m\"] = [m]; JSON.stringify(m);",
+                "Can't convert a cyclic structure to JSON.",
+            ),
+            (
+                "JSON.stringify({1: 2});",
+                "JSON object keys must be strings.",
+            ),
+            (
+                "JSON.stringify([0 / 0]);",
+                "Can't convert NaN or infinity to JSON.",
+            ),
+            (
+                "JSON.stringify(clock);",
+                "Can't convert a value of type 'function' to JSON.",
+            ),
+            (
+                "class A {} JSON.stringify(A());",
+                "Can't convert a value of type 'instance' to JSON.",
+            ),
+        ];
+        for (source, message) in test_table {
+            match interpret(&mut Vm::new(Default::default()), source) {
+                Err(InterpretError::Runtime(err)) => assert_eq!(err.message, message, "{}", source),
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! Lox as an embeddable scripting language. `Lox` runs source text on a
+//! bytecode VM whose globals persist between calls, so a host can load a
+//! script once and then call into it.
+//!
+//! ```no_run
+//! use craft_interpreter::Lox;
+//!
+//! let mut lox = Lox::new(Default::default());
+//! lox.eval_str("fun greet(name) { return \"Hello, ${name}!\"; }").unwrap();
+//! let name = lox.new_string("host");
+//! let greeting = lox.call_function("greet", &[name]).unwrap();
+//! assert_eq!(lox.stringify(greeting), "Hello, host!");
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+pub mod arena;
+pub mod ast;
+pub mod chunk;
+pub mod compiler;
+pub mod gc;
+pub mod json;
+pub mod math;
+pub mod native;
+pub mod object;
+pub mod optimizer;
+pub mod os;
+pub mod parser;
+pub mod rpn;
+pub mod scanner;
+pub mod sexpr;
+pub mod token;
+pub mod utils;
+pub mod value;
+pub mod visit;
+pub mod vm;
+
+use crate::optimizer::optimize;
+use crate::parser::{ParseError, Parser};
+use crate::scanner::{Scanner, ScannerError};
+
+pub use crate::gc::GcConfig;
+pub use crate::native::{Arity, NativeClass, NativeFn, NativeFunction};
+pub use crate::optimizer::OptLevel;
+pub use crate::value::{Value, ValueKind};
+pub use crate::vm::{InterpretError, Limit, Limits, RuntimeError, TraceFrame, Vm};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Config {
+    pub gc: GcConfig,
+    pub opt_level: OptLevel,
+    pub limits: Limits,
+}
+
+#[derive(Debug)]
+pub enum LoxError {
+    Io(io::Error),
+    Scanner(Vec<ScannerError>),
+    Parse(Vec<ParseError>),
+    Interpret(InterpretError),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoxError::Io(ref err) => write!(f, "{}", err),
+            LoxError::Scanner(ref errs) => {
+                let messages: Vec<String> = errs.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            LoxError::Parse(ref errs) => {
+                let messages: Vec<String> = errs.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            LoxError::Interpret(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(err: RuntimeError) -> LoxError {
+        LoxError::Interpret(InterpretError::from(err))
+    }
+}
+
+/// An interpreter instance. Values handed to the host are only kept alive
+/// by the interpreter while something in Lox still refers to them, so
+/// store them in a global if they must outlive the next call.
+pub struct Lox {
+    vm: Vm,
+    opt_level: OptLevel,
+    // Where errors and GC statistics are reported
+    err: Box<dyn Write>,
+}
+
+impl Lox {
+    /// An interpreter on the process's stdin, stdout and stderr.
+    pub fn new(config: Config) -> Lox {
+        Lox::with_io(
+            config,
+            Box::new(BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+            Box::new(io::stderr()),
+        )
+    }
+
+    /// An interpreter reading `input()` from `input`, printing to `out`
+    /// and reporting to `err`.
+    pub fn with_io(
+        config: Config,
+        input: Box<dyn BufRead>,
+        out: Box<dyn Write>,
+        err: Box<dyn Write>,
+    ) -> Lox {
+        let mut vm = Vm::with_io(config.gc, input, out);
+        vm.set_limits(config.limits);
+        Lox {
+            vm,
+            opt_level: config.opt_level,
+            err,
+        }
+    }
+
+    pub fn eval_str(&mut self, source: &str) -> Result<(), LoxError> {
+        let tokens = Scanner::new(source.to_owned())
+            .scan_tokens()
+            .map_err(LoxError::Scanner)?;
+        let program = Parser::new(tokens).parse().map_err(LoxError::Parse)?;
+        // Code the optimizer prunes must still pass the compiler's checks
+        if self.opt_level != OptLevel::O0 {
+            self.vm.check(&program).map_err(LoxError::Interpret)?;
+        }
+        let program = optimize(program, self.opt_level);
+        self.vm.interpret(&program).map_err(LoxError::Interpret)
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoxError> {
+        let source = fs::read_to_string(path).map_err(LoxError::Io)?;
+        self.eval_str(&source)
+    }
+
+    /// Calls the global function, class or native `name` with `args`.
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, LoxError> {
+        let callee = self
+            .vm
+            .get_global(name)
+            .ok_or_else(|| self.vm.error(&format!("Undefined variable '{}'.", name)))?;
+        self.vm
+            .call_function(callee, args)
+            .map_err(LoxError::Interpret)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.vm.get_global(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.vm.set_global(name, value)
+    }
+
+    /// Sets the command line arguments scripts read from `os.args`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.vm.set_args(args)
+    }
+
+    pub fn define_native(&mut self, native: NativeFunction) {
+        self.vm.define_native(native)
+    }
+
+    pub fn define_class(&mut self, class: NativeClass) {
+        self.vm.define_class(class)
+    }
+
+    pub fn new_string(&mut self, s: &str) -> Value {
+        self.vm.new_string(s)
+    }
+
+    pub fn as_string(&self, value: Value) -> Option<&str> {
+        self.vm.as_string(value)
+    }
+
+    /// Formats a value the way `print` shows it.
+    pub fn stringify(&self, value: Value) -> String {
+        self.vm.stringify(value)
+    }
+
+    /// Reads a line from the input `input()` reads from, without the line
+    /// ending. None at the end of the input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        self.vm.read_line()
+    }
+
+    /// Writes an error to the error writer.
+    pub fn report(&mut self, err: &LoxError) {
+        // Nowhere left to report a failing error writer to
+        let _ = writeln!(self.err, "{}", err);
+    }
+
+    /// Writes the collections made since the last report, if the GC log is
+    /// on, and the collector's statistics to the error writer.
+    pub fn report_gc_stats(&mut self) {
+        for collection in self.vm.take_gc_log() {
+            let _ = writeln!(self.err, "{}", collection);
+        }
+        let _ = writeln!(self.err, "{}", self.vm.heap().stats());
+    }
+
+    pub fn vm(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::SharedBuffer;
+    use std::io::Cursor;
+
+    fn lox() -> (Lox, SharedBuffer, SharedBuffer) {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let lox = Lox::with_io(
+            Default::default(),
+            Box::new(Cursor::new("from input\n")),
+            Box::new(out.clone()),
+            Box::new(err.clone()),
+        );
+        (lox, out, err)
+    }
+
+    #[test]
+    fn test_eval() {
+        let (mut lox, out, err) = lox();
+        lox.eval_str("var greeting = \"hi\"; print input();")
+            .expect("Script failed");
+        // Globals persist between evaluations
+        lox.eval_str("print greeting + \"!\";")
+            .expect("Script failed");
+        assert_eq!(out.contents(), "from input\nhi!\n");
+
+        let error = lox.eval_str("print missing;").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Undefined variable 'missing'.\n[line 1, column 7] in script"
+        );
+        let error = lox.eval_str("print ;").unwrap_err();
+        assert!(matches!(error, LoxError::Parse(_)), "{:?}", error);
+        lox.report(&error);
+        assert_eq!(err.contents(), format!("{}\n", error));
+        // A failed evaluation leaves the interpreter usable
+        lox.eval_str("print greeting;").expect("Script failed");
+        assert_eq!(out.contents(), "from input\nhi!\nhi\n");
+
+        let error = lox.eval_file("no/such/script.lox").unwrap_err();
+        assert!(matches!(error, LoxError::Io(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn test_read_line() {
+        let out = SharedBuffer::default();
+        let mut lox = Lox::with_io(
+            Default::default(),
+            Box::new(Cursor::new("print input();\nfrom input\nprint 1;")),
+            Box::new(out.clone()),
+            Box::new(io::sink()),
+        );
+        // Lines read for the host and for input() come from one buffer
+        while let Some(line) = lox.read_line().expect("Could not read line") {
+            lox.eval_str(&line).expect("Script failed");
+        }
+        assert_eq!(out.contents(), "from input\n1\n");
+    }
+
+    #[test]
+    fn test_gc_log() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let config = Config {
+            gc: GcConfig {
+                stress: true,
+                log: true,
+            },
+            ..Default::default()
+        };
+        let mut lox = Lox::with_io(
+            config,
+            Box::new(io::empty()),
+            Box::new(out.clone()),
+            Box::new(err.clone()),
+        );
+        lox.eval_str("var s = \"a\" + \"b\"; print s;")
+            .expect("Script failed");
+        // Nothing is logged until the host asks for the report
+        assert_eq!(err.contents(), "");
+        lox.report_gc_stats();
+        let report = err.contents();
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines.len() > 1, "{}", report);
+        let (summary, collections) = lines.split_last().unwrap();
+        assert!(summary.starts_with("gc: ") && summary.contains("collections"));
+        assert!(collections
+            .iter()
+            .all(|line| line.starts_with("gc: collected")));
+        assert_eq!(out.contents(), "ab\n");
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let (mut lox, out, _) = lox();
+        let nested = |depth| format!("print {}1{};", "-(".repeat(depth), ")".repeat(depth));
+        // Compiling and folding recurse as deep as parsing did
+        lox.eval_str(&nested(60)).expect("Script failed");
+        assert_eq!(out.contents(), "1\n");
+        let error = lox.eval_str(&nested(200_000)).unwrap_err();
+        assert!(matches!(error, LoxError::Parse(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn test_host_calls() {
+        let (mut lox, out, _) = lox();
+        lox.eval_str(
+            "fun add(a, b) { return a + b; }
+            fun fail() { throw \"failed\"; }
+            class Point { init(x) { this.x = x; } }",
+        )
+        .expect("Script failed");
+
+        let sum = lox
+            .call_function("add", &[Value::from(1.0), Value::from(2.0)])
+            .expect("Call failed");
+        assert_eq!(sum.as_number(), Some(3.0));
+        let name = lox.new_string("host");
+        let greeting = lox
+            .call_function("add", &[name, name])
+            .expect("Call failed");
+        assert_eq!(lox.as_string(greeting), Some("hosthost"));
+        let point = lox
+            .call_function("Point", &[Value::from(4.0)])
+            .expect("Call failed");
+        assert_eq!(lox.stringify(point), "Point instance");
+        let length = lox.call_function("len", &[name]).expect("Call failed");
+        assert_eq!(length.as_number(), Some(4.0));
+
+        let test_table = vec![
+            ("add", vec![Value::nil()], "Expected 2 arguments but got 1."),
+            ("fail", vec![], "failed"),
+            ("missing", vec![], "Undefined variable 'missing'."),
+            (
+                "len",
+                vec![Value::nil()],
+                "Can only take the length of strings, lists and maps.",
+            ),
+        ];
+        for (name, args, message) in test_table {
+            match lox.call_function(name, &args) {
+                Err(LoxError::Interpret(InterpretError::Runtime(err))) => {
+                    assert_eq!(err.message, message, "{}", name)
+                }
+                other => panic!("Expected runtime error for {}, got {:?}", name, other),
+            }
+        }
+
+        lox.set_global("origin", point);
+        lox.set_global("scale", Value::from(2.5));
+        lox.eval_str("print origin.x * scale;")
+            .expect("Script failed");
+        assert_eq!(out.contents(), "10\n");
+        assert_eq!(
+            lox.get_global("scale").and_then(|v| v.as_number()),
+            Some(2.5)
+        );
+        assert!(lox.get_global("missing").is_none());
+    }
+}
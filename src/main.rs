@@ -1,64 +1,91 @@
 use std::env;
-use std::fs;
-use std::io;
+use std::process;
 
-mod scanner;
-use crate::scanner::{Scanner, ScannerError};
+use craft_interpreter::{Config, InterpretError, Lox, LoxError, OptLevel};
 
-mod ast;
-mod parser;
-mod rpn;
-mod token;
-mod utils;
+const USAGE: &str = "Usage: jlox [-O0|-O1] [--gc-stress] [--gc-log] [script [args...]]";
 
 fn main() {
-    if env::args().len() > 2 {
-        println!("Usage: jlox [script]");
-    } else if env::args().len() == 2 {
-        run_script(&env::args().last().expect("Expected 2nd argument"));
-    } else {
+    let mut config = Config::default();
+    let mut script = None;
+    let mut script_args = vec![];
+    for arg in env::args().skip(1) {
+        // Everything after the script path belongs to the script
+        if script.is_some() {
+            script_args.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "-O0" => config.opt_level = OptLevel::O0,
+            "-O1" => config.opt_level = OptLevel::O1,
+            "--gc-stress" => config.gc.stress = true,
+            "--gc-log" => config.gc.log = true,
+            _ if arg.starts_with('-') => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => script = Some(arg),
+        }
+    }
+
+    match script {
+        Some(file_path) => run_script(&file_path, script_args, config),
         // TODO: Add sigterm handler
-        run_prompt();
+        None => run_prompt(config),
     }
 }
 
-fn run_script(file_path: &String) {
-    println!("Running script: {}", file_path);
+fn run_script(file_path: &str, args: Vec<String>, config: Config) {
+    let mut lox = Lox::new(config);
+    lox.set_args(args);
+    let status = match lox.eval_file(file_path) {
+        Ok(()) => 0,
+        Err(LoxError::Interpret(InterpretError::Exit(status))) => status,
+        Err(err) => {
+            lox.report(&err);
+            exit_status(&err)
+        }
+    };
+    if config.gc.log {
+        lox.report_gc_stats();
+    }
+    if status != 0 {
+        process::exit(status);
+    }
+}
 
-    let source =
-        fs::read_to_string(file_path).expect(&format!("Cannot read file at {}", file_path));
-    run(source).expect("Failed to run source");
+// The sysexits.h codes, as clox uses them
+fn exit_status(err: &LoxError) -> i32 {
+    match err {
+        LoxError::Scanner(_) | LoxError::Parse(_) => 65,
+        LoxError::Interpret(InterpretError::Compile(_)) => 65,
+        LoxError::Interpret(InterpretError::Exit(status)) => *status,
+        LoxError::Interpret(_) => 70,
+        LoxError::Io(_) => 74,
+    }
 }
 
-fn run_prompt() {
-    let stdin = io::stdin();
-    let input = &mut String::new();
-    let mut source_acc: Vec<String> = Vec::new();
+fn run_prompt(config: Config) {
+    // Globals persist between lines
+    let mut lox = Lox::new(config);
 
+    // Lines come through the interpreter, so input() shares their buffer
     loop {
-        input.clear();
-        stdin.read_line(input).expect("Could not read line");
-        source_acc.push(input.to_owned());
-        match run(source_acc.join("\n")) {
-            Ok(r) => println!("{}", r),
-            Err(scanner_err) => {
-                println!("{:?}", scanner_err);
-                source_acc.pop().expect("Could not pop last input");
+        let line = match lox.read_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                lox.report(&LoxError::Io(err));
+                break;
             }
+        };
+        match lox.eval_str(&line) {
+            Ok(()) => {}
+            Err(LoxError::Interpret(InterpretError::Exit(status))) => process::exit(status),
+            Err(err) => lox.report(&err),
         }
-    }
-}
-
-fn run(source: String) -> Result<String, ScannerError> {
-    let mut scanner = Scanner::new(source);
-    match scanner.scan_tokens() {
-        Ok(wrappers) => {
-            let mut output = String::new();
-            for wrapper in wrappers {
-                output.push_str(&format!("{:?}\n", wrapper));
-            }
-            return Ok(output.to_string());
+        if config.gc.log {
+            lox.report_gc_stats();
         }
-        Err(vec_scanner_errs) => Err(vec_scanner_errs.get(0).expect("No error in vector").clone()),
     }
 }
@@ -0,0 +1,226 @@
+use std::f64::consts;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::native::{Arity, NativeClass};
+use crate::value::Value;
+use crate::vm::{RuntimeError, Vm};
+
+/// SplitMix64, small and fast, and the same sequence for the same seed
+/// on every platform.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    // Seeded from the clock, for scripts that never call Math.seed
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Rng::new(nanos)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), from the top 53 bits
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+type UnaryFn = fn(f64) -> f64;
+
+/// The `Math` namespace. Its instance holds the `Rng` behind `random`.
+pub fn class() -> NativeClass {
+    let class = NativeClass::new("Math")
+        .getter("PI", |_, _| Ok(Value::from(consts::PI)))
+        .getter("E", |_, _| Ok(Value::from(consts::E)))
+        .getter("INF", |_, _| Ok(Value::from(f64::INFINITY)))
+        .getter("NAN", |_, _| Ok(Value::from(f64::NAN)));
+    let unary: [(&str, UnaryFn); 16] = [
+        ("abs", f64::abs),
+        ("ceil", f64::ceil),
+        ("floor", f64::floor),
+        ("round", f64::round),
+        ("sqrt", f64::sqrt),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("asin", f64::asin),
+        ("acos", f64::acos),
+        ("atan", f64::atan),
+        ("exp", f64::exp),
+        ("log", f64::ln),
+        ("log2", f64::log2),
+        ("log10", f64::log10),
+        ("sign", sign),
+    ];
+    let class = unary.iter().fold(class, |class, &(name, f)| {
+        class.method(name, Arity::Fixed(1), move |vm, _, args| {
+            Ok(Value::from(f(number(vm, args[0])?)))
+        })
+    });
+    class
+        .method("pow", Arity::Fixed(2), |vm, _, args| {
+            Ok(Value::from(number(vm, args[0])?.powf(number(vm, args[1])?)))
+        })
+        .method("atan2", Arity::Fixed(2), |vm, _, args| {
+            Ok(Value::from(
+                number(vm, args[0])?.atan2(number(vm, args[1])?),
+            ))
+        })
+        .method("min", Arity::Variadic, |vm, _, args| {
+            fold(vm, args, f64::min)
+        })
+        .method("max", Arity::Variadic, |vm, _, args| {
+            fold(vm, args, f64::max)
+        })
+        .method("seed", Arity::Fixed(1), |vm, this, args| {
+            let seed = number(vm, args[0])?;
+            if let Some(rng) = vm.host_data_mut::<Rng>(this) {
+                *rng = Rng::new(seed.to_bits());
+            }
+            Ok(Value::nil())
+        })
+        .method("random", Arity::Fixed(0), |vm, this, _| {
+            let n = vm.host_data_mut::<Rng>(this).map(|rng| rng.next_f64());
+            n.map(Value::from)
+                .ok_or_else(|| vm.error("Math has no random number generator."))
+        })
+}
+
+// Like f64::signum, but zero for zero
+fn sign(n: f64) -> f64 {
+    if n == 0.0 {
+        n
+    } else {
+        n.signum()
+    }
+}
+
+fn number(vm: &Vm, value: Value) -> Result<f64, RuntimeError> {
+    value
+        .as_number()
+        .ok_or_else(|| vm.error("Argument must be a number."))
+}
+
+fn fold(vm: &Vm, args: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, RuntimeError> {
+    let (first, rest) = match args.split_first() {
+        Some(split) => split,
+        None => return Err(vm.error("Expected at least 1 argument but got 0.")),
+    };
+    let mut result = number(vm, *first)?;
+    for arg in rest.iter() {
+        result = f(result, number(vm, *arg)?);
+    }
+    Ok(Value::from(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{global, interpret};
+    use crate::vm::InterpretError;
+
+    fn eval(source: &str) -> String {
+        let mut vm = Vm::new(Default::default());
+        interpret(&mut vm, &format!("var result = {};", source)).expect("Script failed");
+        global(&vm, "result")
+    }
+
+    #[test]
+    fn test_functions() {
+        let test_table = vec![
+            (
+                "[Math.floor(-1.5), Math.ceil(1.2), Math.round(2.5), Math.abs(-3)]",
+                "[-2, 2, 3, 3]",
+            ),
+            (
+                "[Math.sqrt(16), Math.pow(2, 10), Math.sign(-4), Math.sign(0)]",
+                "[4, 1024, -1, 0]",
+            ),
+            (
+                "[Math.min(3, 1, 2), Math.max(3), Math.max(-1, 5)]",
+                "[1, 3, 5]",
+            ),
+            (
+                "[Math.log(Math.E), Math.log2(8), Math.log10(1000), Math.exp(0)]",
+                "[1, 3, 3, 1]",
+            ),
+            (
+                "[Math.sin(0), Math.cos(0), Math.atan2(0, 1), Math.acos(1)]",
+                "[0, 1, 0, 0]",
+            ),
+            ("Math.round(Math.tan(Math.PI / 4) * 1000) / 1000", "1"),
+            (
+                "[Math.INF, -Math.INF, Math.NAN == Math.NAN]",
+                "[inf, -inf, false]",
+            ),
+            (
+                "[type(Math), type(Math.floor), Math.PI > 3.14]",
+                "[\"instance\", \"function\", true]",
+            ),
+        ];
+        for (source, expected) in test_table {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_random() {
+        let mut vm = Vm::new(Default::default());
+        interpret(
+            &mut vm,
+            "fun draw(n) {
+                var xs = [];
+                for (var i in 0..n) xs.push(Math.random());
+                return xs;
+            }
+            Math.seed(42);
+            var first = draw(100);
+            Math.seed(42);
+            var again = draw(100);
+            Math.seed(43);
+            var other = draw(100);
+            var inRange = first.filter(fun (x) { return x < 0 or x >= 1; }).len() == 0;",
+        )
+        .expect("Script failed");
+        assert_eq!(global(&vm, "first"), global(&vm, "again"));
+        assert_ne!(global(&vm, "first"), global(&vm, "other"));
+        assert_eq!(global(&vm, "inRange"), "true");
+
+        // The sequence for a seed is fixed
+        let mut rng = Rng::new(1);
+        let draws: Vec<f64> = (0..3).map(|_| rng.next_f64()).collect();
+        let mut rng = Rng::new(1);
+        assert_eq!(draws, (0..3).map(|_| rng.next_f64()).collect::<Vec<f64>>());
+        assert!(draws.iter().all(|x| (0.0..1.0).contains(x)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let test_table = vec![
+            ("Math.sqrt(\"4\");", "Argument must be a number."),
+            ("Math.pow(2);", "Expected 2 arguments but got 1."),
+            ("Math.min();", "Expected at least 1 argument but got 0."),
+            ("Math.max(1, nil);", "Argument must be a number."),
+            ("Math.PI = 3;", "Property 'PI' is read-only."),
+            ("Math.cube(2);", "Undefined property 'cube'."),
+        ];
+        for (source, message) in test_table {
+            match interpret(&mut Vm::new(Default::default()), source) {
+                Err(InterpretError::Runtime(err)) => assert_eq!(err.message, message, "{}", source),
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use std::fmt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::object::Obj;
+use crate::value::{Value, ValueKind};
+use crate::vm::{RuntimeError, Vm};
+
+pub type NativeFn = fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Fixed(usize),
+    // Any number of arguments, the function checks them itself
+    Variadic,
+}
+
+/// A Rust function callable from Lox. Arguments stay rooted on the VM's
+/// stack for the duration of the call, so the function may allocate freely.
+#[derive(Debug, Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Arity,
+    pub function: NativeFn,
+    // Reaches outside the interpreter, refused in a sandboxed VM
+    pub privileged: bool,
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, arity: Arity, function: NativeFn) -> NativeFunction {
+        NativeFunction {
+            name: name.to_owned(),
+            arity,
+            function,
+            privileged: false,
+        }
+    }
+
+    pub fn privileged(self) -> NativeFunction {
+        NativeFunction {
+            privileged: true,
+            ..self
+        }
+    }
+}
+
+pub type MethodFn = Rc<dyn Fn(&mut Vm, Value, &[Value]) -> Result<Value, RuntimeError>>;
+
+/// A Rust closure called as a method, with the receiver passed as `this`.
+#[derive(Clone)]
+pub struct NativeMethod {
+    pub name: String,
+    pub arity: Arity,
+    pub function: MethodFn,
+    // Copied from the class when it is defined
+    pub privileged: bool,
+}
+
+impl fmt::Debug for NativeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeMethod({}, {:?})", self.name, self.arity)
+    }
+}
+
+/// A Lox class implemented in Rust, registered with `Vm::define_class`.
+/// Instances carry host data, attached by `init` with `Vm::set_host_data`
+/// and read back by the other methods with `Vm::host_data`. Lox classes
+/// may inherit from it like from any other class.
+#[derive(Debug, Clone)]
+pub struct NativeClass {
+    pub name: String,
+    pub methods: Vec<NativeMethod>,
+    pub getters: Vec<NativeMethod>,
+    pub setters: Vec<NativeMethod>,
+    // Reaches outside the interpreter, its methods are refused in a sandboxed VM
+    pub privileged: bool,
+}
+
+impl NativeClass {
+    pub fn new(name: &str) -> NativeClass {
+        NativeClass {
+            name: name.to_owned(),
+            methods: vec![],
+            getters: vec![],
+            setters: vec![],
+            privileged: false,
+        }
+    }
+
+    pub fn privileged(self) -> NativeClass {
+        NativeClass {
+            privileged: true,
+            ..self
+        }
+    }
+
+    /// Runs when the class is called, or through `super.init` in a subclass.
+    pub fn init<F>(self, arity: Arity, init: F) -> NativeClass
+    where
+        F: Fn(&mut Vm, Value, &[Value]) -> Result<(), RuntimeError> + 'static,
+    {
+        // Like a Lox initializer, it returns the instance
+        self.method("init", arity, move |vm, this, args| {
+            init(vm, this, args)?;
+            Ok(this)
+        })
+    }
+
+    pub fn method<F>(mut self, name: &str, arity: Arity, method: F) -> NativeClass
+    where
+        F: Fn(&mut Vm, Value, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        self.methods.push(NativeMethod {
+            name: name.to_owned(),
+            arity,
+            function: Rc::new(method),
+            privileged: false,
+        });
+        self
+    }
+
+    /// Computes the property `name` when it is read.
+    pub fn getter<F>(mut self, name: &str, getter: F) -> NativeClass
+    where
+        F: Fn(&mut Vm, Value) -> Result<Value, RuntimeError> + 'static,
+    {
+        self.getters.push(NativeMethod {
+            name: name.to_owned(),
+            arity: Arity::Fixed(0),
+            function: Rc::new(move |vm, this, _| getter(vm, this)),
+            privileged: false,
+        });
+        self
+    }
+
+    /// Handles assignments to the property `name`. Properties with a getter
+    /// but no setter are read-only.
+    pub fn setter<F>(mut self, name: &str, setter: F) -> NativeClass
+    where
+        F: Fn(&mut Vm, Value, Value) -> Result<(), RuntimeError> + 'static,
+    {
+        self.setters.push(NativeMethod {
+            name: name.to_owned(),
+            arity: Arity::Fixed(1),
+            // An assignment evaluates to the assigned value
+            function: Rc::new(move |vm, this, args| {
+                setter(vm, this, args[0])?;
+                Ok(args[0])
+            }),
+            privileged: false,
+        });
+        self
+    }
+}
+
+/// Built-ins every VM starts with.
+pub fn core() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("assert", Arity::Variadic, assert),
+        NativeFunction::new("clock", Arity::Fixed(0), clock).privileged(),
+        NativeFunction::new("input", Arity::Fixed(0), input).privileged(),
+        NativeFunction::new("len", Arity::Fixed(1), len),
+        NativeFunction::new("num", Arity::Fixed(1), num),
+        NativeFunction::new("str", Arity::Fixed(1), str),
+        NativeFunction::new("type", Arity::Fixed(1), type_of),
+    ]
+}
+
+/// Name of a value's type, as returned by `type()`.
+pub fn type_name(vm: &Vm, value: Value) -> &'static str {
+    match value.kind() {
+        ValueKind::Nil => "nil",
+        ValueKind::Bool(_) => "bool",
+        ValueKind::Number(_) => "number",
+        ValueKind::Obj(r) => match vm.heap().get(r) {
+            Obj::String(_) => "string",
+            Obj::Function(_)
+            | Obj::Closure(_)
+            | Obj::BoundMethod(_)
+            | Obj::Native(_)
+            | Obj::NativeMethod(_) => "function",
+            Obj::Class(_) => "class",
+            Obj::Instance(_) => "instance",
+            Obj::List(_) => "list",
+            Obj::Map(_) => "map",
+            Obj::Range(..) => "range",
+            Obj::Iter(_) => "iterator",
+            Obj::Upvalue(_) => unreachable!("Upvalues are not values"),
+        },
+    }
+}
+
+// assert(condition) or assert(condition, message)
+fn assert(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (condition, message) = match args {
+        [condition] => (*condition, None),
+        [condition, message] => (*condition, Some(*message)),
+        _ => {
+            return Err(vm.error(&format!(
+                "Expected 1 or 2 arguments but got {}.",
+                args.len()
+            )))
+        }
+    };
+    if !condition.is_falsey() {
+        return Ok(Value::nil());
+    }
+    Err(match message {
+        Some(message) => vm.error(&vm.stringify(message)),
+        None => vm.error("Assertion failed."),
+    })
+}
+
+// Seconds since the Unix epoch
+fn clock(_vm: &mut Vm, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default();
+    Ok(Value::from(now))
+}
+
+// The next line of input without its line ending, nil at the end of input
+fn input(vm: &mut Vm, _args: &[Value]) -> Result<Value, RuntimeError> {
+    match vm.read_line() {
+        Ok(Some(line)) => Ok(vm.new_string(&line)),
+        Ok(None) => Ok(Value::nil()),
+        Err(err) => Err(vm.error(&format!("Could not read input: {}", err))),
+    }
+}
+
+fn len(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match args[0].as_obj().map(|r| vm.heap().get(r)) {
+        Some(Obj::String(s)) => s.chars().count(),
+        Some(Obj::List(items)) => items.len(),
+        Some(Obj::Map(map)) => map.len(),
+        _ => return Err(vm.error("Can only take the length of strings, lists and maps.")),
+    };
+    Ok(Value::from(len as f64))
+}
+
+// Parses a number, nil when the text is not one
+fn num(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    if args[0].as_number().is_some() {
+        return Ok(args[0]);
+    }
+    match args[0].as_obj().map(|r| vm.heap().get(r)) {
+        Some(Obj::String(s)) => Ok(s.trim().parse::<f64>().map(Value::from).unwrap_or_default()),
+        _ => Err(vm.error("Can only convert strings and numbers to numbers.")),
+    }
+}
+
+fn str(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let text = vm.stringify(args[0]);
+    Ok(vm.new_string(&text))
+}
+
+fn type_of(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name = type_name(vm, args[0]);
+    Ok(vm.new_string(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::GcConfig;
+    use crate::utils::{gc_configs, global, interpret, SharedBuffer};
+    use crate::vm::InterpretError;
+    use std::io::Cursor;
+
+    // Runs the source with the given input, returning what it printed
+    fn run(source: &str, input: &str) -> Result<String, InterpretError> {
+        let out = SharedBuffer::default();
+        let mut vm = Vm::with_io(
+            Default::default(),
+            Box::new(Cursor::new(input.to_owned())),
+            Box::new(out.clone()),
+        );
+        interpret(&mut vm, source)?;
+        Ok(out.contents())
+    }
+
+    #[test]
+    fn test_core_natives() {
+        let output = run(
+            "print type(nil) + type(1) + type(true) + type(\"\") + type([]) + type({});
+            class A { m() {} }
+            print [type(A), type(A()), type(A().m), type(clock), type(fun () {}), type(0..1)];
+            print clock() > 0;
+            print str(1.5) + str(nil) + str([1, \"a\"]);
+            print [num(\"42\"), num(\" -1.5 \"), num(\"forty\"), num(7)];
+            print [len(\"héllo\"), len([1, 2]), len({\"a\": 1})];
+            print [input(), input(), input()];
+            print assert(1 < 2, \"unused\");
+            print len;",
+            "first line\r\nsecond\n",
+        );
+        assert_eq!(
+            output.expect("Script failed"),
+            "nilnumberboolstringlistmap\n\
+             [\"class\", \"instance\", \"function\", \"function\", \"function\", \"range\"]\n\
+             true\n\
+             1.5nil[1, \"a\"]\n\
+             [42, -1.5, nil, 7]\n\
+             [5, 2, 1]\n\
+             [\"first line\", \"second\", nil]\n\
+             nil\n\
+             <native fn len>\n"
+        );
+    }
+
+    #[test]
+    fn test_native_errors() {
+        let test_table = vec![
+            ("assert(false);", "Assertion failed."),
+            ("assert(nil, \"x is ${1 + 1}\");", "x is 2"),
+            ("assert();", "Expected 1 or 2 arguments but got 0."),
+            ("len(1, 2);", "Expected 1 arguments but got 2."),
+            (
+                "len(nil);",
+                "Can only take the length of strings, lists and maps.",
+            ),
+            (
+                "num([]);",
+                "Can only convert strings and numbers to numbers.",
+            ),
+        ];
+        for (source, message) in test_table {
+            match run(source, "") {
+                Err(InterpretError::Runtime(err)) => assert_eq!(err.message, message, "{}", source),
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+        // Native errors can be caught like any other
+        let output = run(
+            "try { assert(false, \"caught\"); } catch (e) { print e.message; }",
+            "",
+        );
+        assert_eq!(output.expect("Script failed"), "caught\n");
+    }
+
+    struct Config {
+        name: String,
+        retries: f64,
+    }
+
+    fn config_class() -> NativeClass {
+        NativeClass::new("Config")
+            .init(Arity::Fixed(1), |vm, this, args| {
+                let name = vm
+                    .as_string(args[0])
+                    .ok_or_else(|| vm.error("Expected a name."))?
+                    .to_owned();
+                vm.set_host_data(this, Config { name, retries: 3.0 })
+            })
+            .method("describe", Arity::Fixed(0), |vm, this, _| {
+                let text = match vm.host_data::<Config>(this) {
+                    Some(config) => format!("{} ({} retries)", config.name, config.retries),
+                    None => return Err(vm.error("Config is not initialized.")),
+                };
+                Ok(vm.new_string(&text))
+            })
+            .getter("name", |vm, this| {
+                let name = vm.host_data::<Config>(this).map(|c| c.name.clone());
+                Ok(name.map(|name| vm.new_string(&name)).unwrap_or_default())
+            })
+            .getter("retries", |vm, this| {
+                Ok(Value::from(
+                    vm.host_data::<Config>(this).map_or(0.0, |c| c.retries),
+                ))
+            })
+            .setter("retries", |vm, this, value| {
+                let retries = value
+                    .as_number()
+                    .ok_or_else(|| vm.error("Retries must be a number."))?;
+                if let Some(config) = vm.host_data_mut::<Config>(this) {
+                    config.retries = retries;
+                }
+                Ok(())
+            })
+    }
+
+    fn run_with_config(source: &str, gc_config: GcConfig) -> Result<String, InterpretError> {
+        let out = SharedBuffer::default();
+        let mut vm = Vm::with_output(gc_config, Box::new(out.clone()));
+        vm.define_class(config_class());
+        interpret(&mut vm, source)?;
+        Ok(out.contents())
+    }
+
+    const NATIVE_CLASS: &str = "
+        var c = Config(\"db\");
+        print c.retries = c.retries + 1;
+        print [c.name, type(c), c, c.describe, Config];
+        print c.describe();
+        var describe = c.describe;
+        print describe();
+        class Named < Config {
+            init(name) { super.init(name); this.extra = \"!\"; }
+            describe() { return \"named \" + super.describe() + this.extra; }
+        }
+        class Inherited < Config {}
+        print Named(\"cache\").describe();
+        print Inherited(\"plain\").name;
+        var names = [];
+        for (var i in 0..3) names.push(Named(str(i)).name);
+        print names;
+    ";
+
+    #[test]
+    fn test_native_class() {
+        let expected = "4\n\
+                        [\"db\", \"instance\", Config instance, <native fn describe>, Config]\n\
+                        db (4 retries)\n\
+                        db (4 retries)\n\
+                        named cache (3 retries)!\n\
+                        plain\n\
+                        [\"0\", \"1\", \"2\"]\n";
+        for config in gc_configs() {
+            let output = run_with_config(NATIVE_CLASS, config);
+            assert_eq!(output.expect("Script failed"), expected, "{:?}", config);
+        }
+
+        let test_table = vec![
+            (
+                "Config(\"a\").name = \"b\";",
+                "Property 'name' is read-only.",
+            ),
+            ("Config(\"a\").retries = nil;", "Retries must be a number."),
+            (
+                "Config(\"a\").retries();",
+                "Can only call functions and classes.",
+            ),
+            ("Config(1);", "Expected a name."),
+            ("Config();", "Expected 1 arguments but got 0."),
+            (
+                "class Broken < Config { init() {} }\nBroken().describe();",
+                "Config is not initialized.",
+            ),
+        ];
+        for (source, message) in test_table {
+            match run_with_config(source, Default::default()) {
+                Err(InterpretError::Runtime(err)) => assert_eq!(err.message, message, "{}", source),
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_define_native() {
+        fn double(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+            match args[0].as_number() {
+                Some(n) => Ok(Value::from(n * 2.0)),
+                None => Err(vm.error("Expected a number.")),
+            }
+        }
+        let mut vm = Vm::new(Default::default());
+        vm.define_native(NativeFunction::new("double", Arity::Fixed(1), double));
+        interpret(&mut vm, "var x = [1, 2].map(double);").expect("Script failed");
+        assert_eq!(global(&vm, "x"), "[2, 4]");
+    }
+}
@@ -0,0 +1,249 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::mem;
+
+use crate::chunk::{Chunk, OpCode};
+use crate::gc::{ObjRef, Trace, Tracer};
+use crate::native::{NativeFunction, NativeMethod};
+use crate::value::{HashKey, Value};
+
+#[derive(Debug)]
+pub enum Obj {
+    String(String),
+    Function(Function),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    List(Vec<Value>),
+    Map(Map),
+    // Half open range of numbers
+    Range(f64, f64),
+    Iter(Iter),
+    Native(NativeFunction),
+    NativeMethod(NativeMethod),
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    // Interned name, None for the top level script
+    pub name: Option<ObjRef>,
+    pub arity: usize,
+    pub chunk: Chunk,
+    // Where each captured variable lives when the closure is created
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueDescriptor {
+    // True when capturing a local slot of the enclosing function,
+    // false when re-capturing one of the enclosing function's upvalues
+    pub is_local: bool,
+    pub index: usize,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+#[derive(Debug)]
+pub enum Upvalue {
+    // Index of the captured variable's slot on the value stack
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: ObjRef,
+    pub methods: HashMap<ObjRef, Value>,
+    // Native methods run when a property is read or assigned
+    pub getters: HashMap<ObjRef, ObjRef>,
+    pub setters: HashMap<ObjRef, ObjRef>,
+}
+
+impl Class {
+    pub fn new(name: ObjRef) -> Class {
+        Class {
+            name,
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: ObjRef,
+    pub fields: HashMap<ObjRef, Value>,
+    // Rust data attached by a native class, invisible to the collector
+    pub host: Option<Box<dyn Any>>,
+}
+
+impl Instance {
+    pub fn new(class: ObjRef) -> Instance {
+        Instance {
+            class,
+            fields: HashMap::new(),
+            host: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    // Closure to call with `receiver` as `this`
+    pub method: ObjRef,
+}
+
+/// Position within a built in iterable, as walked by a for-in loop.
+#[derive(Debug, Clone, Copy)]
+pub enum Iter {
+    // The list and the index of its next item
+    List(ObjRef, usize),
+    // The string and the byte offset of its next character
+    String(ObjRef, usize),
+    // Next number and the end of the range
+    Range(f64, f64),
+}
+
+/// Hash map that remembers insertion order, so keys iterate and print in
+/// the order they were first added.
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    // Position of each key in `entries`
+    indices: HashMap<HashKey, usize>,
+}
+
+impl Map {
+    pub fn get(&self, key: HashKey) -> Option<Value> {
+        self.indices.get(&key).map(|&i| self.entries[i].1)
+    }
+
+    pub fn contains(&self, key: HashKey) -> bool {
+        self.indices.contains_key(&key)
+    }
+
+    pub fn insert(&mut self, key: HashKey, value: Value) {
+        match self.indices.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.indices.insert(key, self.entries.len());
+                self.entries.push((key.value(), value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: HashKey) -> Option<Value> {
+        let i = self.indices.remove(&key)?;
+        let (_, value) = self.entries.remove(i);
+        for index in self.indices.values_mut() {
+            if *index > i {
+                *index -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+}
+
+impl Obj {
+    /// Rough number of bytes owned by this object, used to pace collections.
+    pub fn size(&self) -> usize {
+        let owned = match self {
+            Obj::String(s) => s.capacity(),
+            Obj::Function(f) => {
+                f.chunk.code.capacity() * mem::size_of::<OpCode>()
+                    + f.chunk.constants.capacity() * mem::size_of::<Value>()
+            }
+            Obj::Closure(c) => c.upvalues.capacity() * mem::size_of::<ObjRef>(),
+            Obj::Upvalue(_) => 0,
+            Obj::Class(c) => {
+                c.methods.capacity() * mem::size_of::<(ObjRef, Value)>()
+                    + (c.getters.capacity() + c.setters.capacity())
+                        * mem::size_of::<(ObjRef, ObjRef)>()
+            }
+            Obj::Instance(i) => i.fields.capacity() * mem::size_of::<(ObjRef, Value)>(),
+            Obj::BoundMethod(_) | Obj::Range(..) | Obj::Iter(_) => 0,
+            Obj::Native(n) => n.name.capacity(),
+            Obj::NativeMethod(m) => m.name.capacity(),
+            Obj::List(items) => items.capacity() * mem::size_of::<Value>(),
+            Obj::Map(m) => {
+                m.entries.capacity() * mem::size_of::<(Value, Value)>()
+                    + m.indices.capacity() * mem::size_of::<(HashKey, usize)>()
+            }
+        };
+        mem::size_of::<Obj>() + owned
+    }
+}
+
+impl Trace for Obj {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Obj::String(_) => {}
+            Obj::Function(f) => {
+                if let Some(name) = f.name {
+                    tracer.mark(name);
+                }
+                f.chunk.trace(tracer);
+            }
+            Obj::Closure(c) => {
+                tracer.mark(c.function);
+                for upvalue in c.upvalues.iter() {
+                    tracer.mark(*upvalue);
+                }
+            }
+            Obj::Upvalue(Upvalue::Open(_)) => {}
+            Obj::Upvalue(Upvalue::Closed(v)) => tracer.mark_value(*v),
+            Obj::Class(c) => {
+                tracer.mark(c.name);
+                for (name, method) in c.methods.iter() {
+                    tracer.mark(*name);
+                    tracer.mark_value(*method);
+                }
+                for (name, accessor) in c.getters.iter().chain(c.setters.iter()) {
+                    tracer.mark(*name);
+                    tracer.mark(*accessor);
+                }
+            }
+            Obj::Instance(i) => {
+                tracer.mark(i.class);
+                for (name, field) in i.fields.iter() {
+                    tracer.mark(*name);
+                    tracer.mark_value(*field);
+                }
+            }
+            Obj::BoundMethod(b) => {
+                tracer.mark_value(b.receiver);
+                tracer.mark(b.method);
+            }
+            Obj::Range(..) | Obj::Iter(Iter::Range(..)) | Obj::Native(_) | Obj::NativeMethod(_) => {
+            }
+            Obj::Iter(Iter::List(r, _)) | Obj::Iter(Iter::String(r, _)) => tracer.mark(*r),
+            Obj::List(items) => items.trace(tracer),
+            Obj::Map(m) => {
+                for (key, value) in m.entries.iter() {
+                    tracer.mark_value(*key);
+                    tracer.mark_value(*value);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use crate::ast::{Expr, ExprKind, Operator, Primitive, Stmt, StmtWrapper};
+use crate::token::LocationInfo;
+use crate::visit::{walk_fold_stmt, Folder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptLevel {
+    // Run the program exactly as parsed
+    O0,
+    // Fold constants and drop code that can never run
+    #[default]
+    O1,
+}
+
+/// Rewrites a parsed program before it is compiled. Nodes that survive keep
+/// their location, and a folded expression takes the location of the one it
+/// replaced.
+pub fn optimize(program: Vec<StmtWrapper>, level: OptLevel) -> Vec<StmtWrapper> {
+    match level {
+        OptLevel::O0 => program,
+        OptLevel::O1 => ConstantFolder.fold_block(program),
+    }
+}
+
+pub fn fold_expr(expr: Expr) -> Expr {
+    ConstantFolder.fold_expr(expr)
+}
+
+struct ConstantFolder;
+
+impl Folder for ConstantFolder {
+    fn fold_binary(
+        &mut self,
+        location_info: LocationInfo,
+        operator: Operator,
+        left: Expr,
+        right: Expr,
+    ) -> Expr {
+        let left = self.fold_expr(left);
+        let right = self.fold_expr(right);
+        if let (ExprKind::Literal(a), ExprKind::Literal(b)) = (&left.kind, &right.kind) {
+            if let Some(p) = fold_binary(&operator, a, b) {
+                return Expr::new(location_info, ExprKind::Literal(p));
+            }
+        }
+        Expr::new(
+            location_info,
+            ExprKind::Binary(operator, Box::new(left), Box::new(right)),
+        )
+    }
+
+    fn fold_comma(&mut self, location_info: LocationInfo, left: Expr, right: Expr) -> Expr {
+        let left = self.fold_expr(left);
+        let right = self.fold_expr(right);
+        // A literal on the left has no side effects to keep
+        if let ExprKind::Literal(_) = left.kind {
+            return right;
+        }
+        Expr::new(
+            location_info,
+            ExprKind::Comma(Box::new(left), Box::new(right)),
+        )
+    }
+
+    fn fold_conditional(
+        &mut self,
+        location_info: LocationInfo,
+        condition: Expr,
+        then_expr: Expr,
+        else_expr: Expr,
+    ) -> Expr {
+        let condition = self.fold_expr(condition);
+        if let ExprKind::Literal(p) = &condition.kind {
+            return if p.is_truthy() {
+                self.fold_expr(then_expr)
+            } else {
+                self.fold_expr(else_expr)
+            };
+        }
+        Expr::new(
+            location_info,
+            ExprKind::Conditional(
+                Box::new(condition),
+                Box::new(self.fold_expr(then_expr)),
+                Box::new(self.fold_expr(else_expr)),
+            ),
+        )
+    }
+
+    // Grouping only matters to the parser
+    fn fold_grouping(&mut self, _location_info: LocationInfo, expr: Expr) -> Expr {
+        self.fold_expr(expr)
+    }
+
+    // Adjacent literal parts are joined, so a fully literal string becomes one
+    fn fold_interpolation(&mut self, location_info: LocationInfo, parts: Vec<Expr>) -> Expr {
+        let mut folded: Vec<Expr> = vec![];
+        for part in parts.into_iter().map(|e| self.fold_expr(e)) {
+            match (folded.last_mut().map(|e| &mut e.kind), &part.kind) {
+                (Some(ExprKind::Literal(a)), ExprKind::Literal(b)) => {
+                    *a = Primitive::String(a.stringify() + &b.stringify())
+                }
+                _ => folded.push(part),
+            }
+        }
+        let kind = match folded.as_slice() {
+            [Expr {
+                kind: ExprKind::Literal(p),
+                ..
+            }] => ExprKind::Literal(Primitive::String(p.stringify())),
+            _ => ExprKind::Interpolation(folded),
+        };
+        Expr::new(location_info, kind)
+    }
+
+    fn fold_logical(
+        &mut self,
+        location_info: LocationInfo,
+        operator: Operator,
+        left: Expr,
+        right: Expr,
+    ) -> Expr {
+        let left = self.fold_expr(left);
+        if let ExprKind::Literal(p) = &left.kind {
+            // The result is whichever operand decided the outcome
+            return match (&operator, p.is_truthy()) {
+                (Operator::Or, true) | (Operator::And, false) => left,
+                _ => self.fold_expr(right),
+            };
+        }
+        let right = self.fold_expr(right);
+        Expr::new(
+            location_info,
+            ExprKind::Logical(operator, Box::new(left), Box::new(right)),
+        )
+    }
+
+    fn fold_unary(&mut self, location_info: LocationInfo, operator: Operator, right: Expr) -> Expr {
+        let right = self.fold_expr(right);
+        let kind = match (&operator, &right.kind) {
+            (Operator::Minus, ExprKind::Literal(Primitive::Number(n))) => {
+                ExprKind::Literal(Primitive::Number(-n))
+            }
+            (Operator::Bang, ExprKind::Literal(p)) => {
+                ExprKind::Literal(Primitive::Boolean(!p.is_truthy()))
+            }
+            _ => ExprKind::Unary(operator, Box::new(right)),
+        };
+        Expr::new(location_info, kind)
+    }
+
+    fn fold_stmt(&mut self, wrapper: StmtWrapper) -> StmtWrapper {
+        let location_info = wrapper.location_info.clone();
+        match wrapper.stmt {
+            Stmt::If(condition, then_branch, else_branch) => {
+                let condition = self.fold_expr(condition);
+                if let ExprKind::Literal(p) = &condition.kind {
+                    return if p.is_truthy() {
+                        self.fold_stmt(*then_branch)
+                    } else {
+                        match else_branch {
+                            Some(b) => self.fold_stmt(*b),
+                            None => empty_block(location_info),
+                        }
+                    };
+                }
+                let stmt = self.fold_if(condition, *then_branch, else_branch.map(|b| *b));
+                StmtWrapper {
+                    location_info,
+                    stmt,
+                }
+            }
+            Stmt::While(condition, body, increment) => {
+                let condition = self.fold_expr(condition);
+                if let ExprKind::Literal(p) = &condition.kind {
+                    if !p.is_truthy() {
+                        return empty_block(location_info);
+                    }
+                }
+                let stmt = self.fold_while(condition, *body, increment);
+                StmtWrapper {
+                    location_info,
+                    stmt,
+                }
+            }
+            stmt => walk_fold_stmt(
+                self,
+                StmtWrapper {
+                    location_info,
+                    stmt,
+                },
+            ),
+        }
+    }
+
+    fn fold_block(&mut self, statements: Vec<StmtWrapper>) -> Vec<StmtWrapper> {
+        let mut folded = vec![];
+        for stmt in statements {
+            let stmt = self.fold_stmt(stmt);
+            // Branches and loops that were folded away leave an empty block behind
+            if matches!(&stmt.stmt, Stmt::Block(b) if b.is_empty()) {
+                continue;
+            }
+            let exits = always_exits(&stmt.stmt);
+            folded.push(stmt);
+            // Nothing after this statement can run
+            if exits {
+                break;
+            }
+        }
+        folded
+    }
+}
+
+fn empty_block(location_info: LocationInfo) -> StmtWrapper {
+    StmtWrapper {
+        location_info,
+        stmt: Stmt::Block(vec![]),
+    }
+}
+
+fn always_exits(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return(_) | Stmt::Break | Stmt::Continue | Stmt::Throw(_) => true,
+        Stmt::Block(statements) => statements.last().is_some_and(|s| always_exits(&s.stmt)),
+        Stmt::If(_, then_branch, Some(else_branch)) => {
+            always_exits(&then_branch.stmt) && always_exits(&else_branch.stmt)
+        }
+        _ => false,
+    }
+}
+
+// Mirrors the VM's semantics; operands it would reject are left for it to report
+pub fn fold_binary(operator: &Operator, a: &Primitive, b: &Primitive) -> Option<Primitive> {
+    use Primitive::{Boolean, Number};
+    let p = match (operator, a, b) {
+        (Operator::Plus, Number(x), Number(y)) => Number(x + y),
+        (Operator::Plus, Primitive::String(x), Primitive::String(y)) => {
+            Primitive::String(format!("{}{}", x, y))
+        }
+        (Operator::Minus, Number(x), Number(y)) => Number(x - y),
+        (Operator::Star, Number(x), Number(y)) => Number(x * y),
+        (Operator::Divide, Number(x), Number(y)) => Number(x / y),
+        (Operator::Greater, Number(x), Number(y)) => Boolean(x > y),
+        (Operator::GreaterEqual, Number(x), Number(y)) => Boolean(x >= y),
+        (Operator::Less, Number(x), Number(y)) => Boolean(x < y),
+        (Operator::LessEqual, Number(x), Number(y)) => Boolean(x <= y),
+        (Operator::EqualEqual, a, b) => Boolean(a == b),
+        (Operator::BangEqual, a, b) => Boolean(a != b),
+        _ => return None,
+    };
+    Some(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{parse, SharedBuffer};
+    use crate::{Config, Lox};
+    use std::io;
+
+    fn run(source: &str, level: OptLevel) -> String {
+        let out = SharedBuffer::default();
+        let config = Config {
+            opt_level: level,
+            ..Default::default()
+        };
+        let mut lox = Lox::with_io(
+            config,
+            Box::new(io::empty()),
+            Box::new(out.clone()),
+            Box::new(io::sink()),
+        );
+        if let Err(err) = lox.eval_str(source) {
+            return format!("{}{}", out.contents(), err);
+        }
+        out.contents()
+    }
+
+    fn fold(source: &str) -> String {
+        match optimize(parse(&format!("{};", source)), OptLevel::O1).pop() {
+            Some(StmtWrapper {
+                stmt: Stmt::Expression(expr),
+                ..
+            }) => format!("{}", expr),
+            other => panic!("Expected expression statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fold_expressions() {
+        let test_table = vec![
+            ("1 + 2 * 3", "7"),
+            ("(1 + 2) * x", "(* 3 x)"),
+            ("-(4 - 6)", "2"),
+            ("\"foo\" + \"bar\"", "\"foobar\""),
+            ("1 < 2 == !nil", "true"),
+            ("1 == \"1\"", "false"),
+            ("nil or x", "x"),
+            ("false and x", "false"),
+            ("f((2), 3 / 4)", "(call f 2 0.75)"),
+            ("-\"str\"", "(- \"str\")"),
+            ("1 + nil", "(+ 1 nil)"),
+            ("nil ? a : 1 + 1", "2"),
+            ("x ? 1 < 2 : b", "(? x true b)"),
+            ("(1, f(), 2 + 3)", "(, (call f) 5)"),
+            ("\"${1 + 1} and ${nil}\"", "\"2 and nil\""),
+            (
+                "\"a ${x} ${-1}${true}\"",
+                "(interpolate \"a \" x \" -1true\")",
+            ),
+        ];
+        for (input, expected) in test_table {
+            assert_eq!(fold(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_dead_code_removed() {
+        let program = optimize(
+            parse(
+                "if (false) print 1; else print 2;
+                while (nil) print 3;
+                fun f() { return 1; print 4; }
+                fun g(x) { if (x) { return 1; } else return 2; print 5; }
+                fun h() { throw 1; print 6; }",
+            ),
+            OptLevel::O1,
+        );
+        assert_eq!(program.len(), 4);
+        assert!(
+            matches!(&program[0].stmt, Stmt::Print(e) if e.kind == ExprKind::Literal(Primitive::Number(2.0)))
+        );
+        for function in program[1..].iter() {
+            match &function.stmt {
+                Stmt::Function(decl) => assert_eq!(decl.body.len(), 1, "{}", decl.name),
+                other => panic!("Expected function, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_locations_preserved() {
+        let program = optimize(
+            parse("var a = 1;\nif (true)\n  print 1 + 2;\nprint -nil;"),
+            OptLevel::O1,
+        );
+        let lines: Vec<usize> = program.iter().map(|s| s.location_info.line).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+        // Runtime errors still point at the right line and column, also
+        // inside a statement that spans several
+        let test_table = vec![
+            ("var a = 1;\nif (true)\n  print -nil;", 3, 9),
+            ("var a = 1 +\n  2 +\n  \"three\" * 4;", 3, 11),
+            ("print true and\n  -nil;", 2, 3),
+            ("print nil ?\n  1 :\n  (2, -\"x\");", 3, 7),
+            ("print \"${1 + 1}\" +\n  \"${\n  -nil}\";", 3, 3),
+        ];
+        for (source, line, column) in test_table {
+            for level in [OptLevel::O0, OptLevel::O1] {
+                let output = run(source, level);
+                assert!(
+                    output.ends_with(&format!("[line {}, column {}] in script", line, column)),
+                    "{:?} {}",
+                    level,
+                    output
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimized_output_matches() {
+        let programs = vec![
+            "print 1 + 2 * 3 - 4 / 8; print -(1 - 3); print 0 / 0 == 0 / 0;",
+            "print \"con\" + \"cat\" + \"enation\"; print \"a\" == \"a\"; print 1 == true;",
+            "print nil or \"default\"; print 0 and \"zero is truthy\"; print !nil; print false or nil;",
+            "var x = 10; if (1 > 2) print \"no\"; else if (x) print \"yes\"; while (false) print x;",
+            "fun f(n) { if (n > 0) return \"pos\"; else return \"neg\"; print \"unreachable\"; }
+             print f(1); print f(-1);",
+            "fun count(n) { var total = 0; for (var i = 0; i < n; i = i + 1) total = total + (i * 2); return total; }
+             print count(10);",
+            "print (1 + 2) * \"three\";",
+            "for (var i = 0; i < 5; i = i + 1) { if (i == 1) continue; if (false) break; print i; if (i == 3) { break; print \"dead\"; } }",
+            "var a = 0; print (a = a + 1, a = a * 10, a); print true ? \"t\" : \"f\"; print nil ? 1 : 0 ? 2 : 3;",
+            "var n = 2; print \"${1 + 1} ${n} ${\"${nil}${true}\"} ${-0}\"; print \"${\"a\" + \"b\"}\" == \"ab\";",
+            "fun f() { try { if (true) throw 1 + 1; print \"dead\"; } catch (e) { print e; return e; } finally { print \"done\"; } }
+             print f(); try { throw \"x\"; print 1; } catch (e) { print e + \"!\"; }",
+            "for (var x in 0..2 + 3) { if (true) continue; print x; } for (var c in \"ab\") { print c; break; }",
+            // Pruned code is still checked
+            "if (false) { break; }",
+            "if (false) return 1;",
+            "fun f() { return 1; return this; }",
+            "while (false) { var a = 1; var a = 2; }",
+        ];
+        for program in programs {
+            assert_eq!(
+                run(program, OptLevel::O0),
+                run(program, OptLevel::O1),
+                "{}",
+                program
+            );
+        }
+    }
+}
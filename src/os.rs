@@ -0,0 +1,205 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::native::{Arity, NativeClass};
+use crate::value::Value;
+use crate::vm::{RuntimeError, Vm};
+
+/// The `io` namespace, files and directories. Privileged, so a sandboxed VM
+/// refuses every method.
+pub fn io_class() -> NativeClass {
+    NativeClass::new("io")
+        .method("readFile", Arity::Fixed(1), |vm, _, args| {
+            let path = text(vm, args[0])?;
+            let contents = fs::read_to_string(&path)
+                .map_err(|err| vm.error(&format!("Could not read '{}': {}.", path, err)))?;
+            Ok(vm.new_string(&contents))
+        })
+        .method("writeFile", Arity::Fixed(2), |vm, _, args| {
+            let (path, contents) = (text(vm, args[0])?, text(vm, args[1])?);
+            fs::write(&path, contents)
+                .map_err(|err| vm.error(&format!("Could not write '{}': {}.", path, err)))?;
+            Ok(Value::nil())
+        })
+        .method("appendFile", Arity::Fixed(2), |vm, _, args| {
+            let (path, contents) = (text(vm, args[0])?, text(vm, args[1])?);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                .map_err(|err| vm.error(&format!("Could not write '{}': {}.", path, err)))?;
+            Ok(Value::nil())
+        })
+        .method("exists", Arity::Fixed(1), |vm, _, args| {
+            Ok(Value::from(Path::new(&text(vm, args[0])?).exists()))
+        })
+        .method("listDir", Arity::Fixed(1), |vm, _, args| {
+            let path = text(vm, args[0])?;
+            let mut names = fs::read_dir(&path)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                        .collect::<Result<Vec<String>, _>>()
+                })
+                .map_err(|err| vm.error(&format!("Could not list '{}': {}.", path, err)))?;
+            // The platform's order is arbitrary
+            names.sort();
+            Ok(vm.string_list(names.into_iter()))
+        })
+        .privileged()
+}
+
+/// The `os` namespace, the process the script runs in. Privileged like `io`.
+pub fn os_class() -> NativeClass {
+    NativeClass::new("os")
+        .getter("args", |vm, _| {
+            let args = vm.args().to_vec();
+            Ok(vm.string_list(args.into_iter()))
+        })
+        .method("env", Arity::Fixed(1), |vm, _, args| {
+            let name = text(vm, args[0])?;
+            match env::var(&name) {
+                Ok(value) => Ok(vm.new_string(&value)),
+                Err(_) => Ok(Value::nil()),
+            }
+        })
+        .method("exit", Arity::Fixed(1), |vm, _, args| {
+            let status = args[0]
+                .as_number()
+                .filter(|n| n.fract() == 0.0 && *n >= i32::MIN as f64 && *n <= i32::MAX as f64)
+                .ok_or_else(|| vm.error("Exit status must be an integer."))?;
+            Err(vm.exit_error(status as i32))
+        })
+        .privileged()
+}
+
+fn text(vm: &Vm, value: Value) -> Result<String, RuntimeError> {
+    vm.as_string(value)
+        .map(str::to_owned)
+        .ok_or_else(|| vm.error("Argument must be a string."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{global, interpret};
+    use crate::vm::{InterpretError, Limits};
+
+    #[test]
+    fn test_io() {
+        let dir = env::temp_dir().join(format!("craft_interpreter_io_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Could not create the test directory");
+        let mut vm = Vm::new(Default::default());
+        let path = vm.new_string(&dir.to_string_lossy());
+        vm.set_global("dir", path);
+        interpret(
+            &mut vm,
+            "var file = dir + \"/notes.txt\";
+            var before = io.exists(file);
+            io.writeFile(file, \"one\\n\");
+            io.appendFile(file, \"two\\n\");
+            io.writeFile(dir + \"/a.txt\", \"\");
+            var lines = io.readFile(file).split(\"\\n\");
+            var after = io.exists(file);
+            var names = io.listDir(dir);",
+        )
+        .expect("Script failed");
+        fs::remove_dir_all(&dir).expect("Could not remove the test directory");
+        assert_eq!(global(&vm, "before"), "false");
+        assert_eq!(global(&vm, "after"), "true");
+        assert_eq!(global(&vm, "lines"), "[\"one\", \"two\", \"\"]");
+        assert_eq!(global(&vm, "names"), "[\"a.txt\", \"notes.txt\"]");
+
+        let test_table = vec![
+            ("io.readFile(dir + \"/missing.txt\");", "Could not read '"),
+            ("io.listDir(dir + \"/missing\");", "Could not list '"),
+            (
+                "io.writeFile(dir + \"/missing/a.txt\", \"\");",
+                "Could not write '",
+            ),
+            ("io.writeFile(\"a.txt\", 1);", "Argument must be a string."),
+            ("io.exists(nil);", "Argument must be a string."),
+        ];
+        for (source, message) in test_table {
+            match interpret(&mut vm, source) {
+                Err(InterpretError::Runtime(err)) => {
+                    assert!(err.message.starts_with(message), "{}", err.message)
+                }
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_os() {
+        env::set_var("CRAFT_INTERPRETER_TEST", "set");
+        let mut vm = Vm::new(Default::default());
+        vm.set_args(vec!["first".to_owned(), "--flag".to_owned()]);
+        interpret(
+            &mut vm,
+            "var args = os.args;
+            var set = os.env(\"CRAFT_INTERPRETER_TEST\");
+            var unset = os.env(\"CRAFT_INTERPRETER_TEST_UNSET\");",
+        )
+        .expect("Script failed");
+        assert_eq!(global(&vm, "args"), "[\"first\", \"--flag\"]");
+        assert_eq!(global(&vm, "set"), "set");
+        assert_eq!(global(&vm, "unset"), "nil");
+
+        // Exiting skips handlers and whatever follows
+        let result = interpret(
+            &mut vm,
+            "var reached = false;
+            try { os.exit(3); } catch (e) { reached = true; }
+            reached = true;",
+        );
+        assert!(
+            matches!(result, Err(InterpretError::Exit(3))),
+            "{:?}",
+            result
+        );
+        assert_eq!(global(&vm, "reached"), "false");
+        interpret(&mut vm, "var ok = 1;").expect("VM not reset after exit");
+
+        for source in ["os.exit(1.5);", "os.exit(\"1\");", "os.exit(10000000000);"].iter() {
+            match interpret(&mut vm, source) {
+                Err(InterpretError::Runtime(err)) => {
+                    assert_eq!(err.message, "Exit status must be an integer.", "{}", source)
+                }
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let mut vm = Vm::new(Default::default());
+        vm.set_limits(Limits {
+            sandbox: true,
+            ..Default::default()
+        });
+        let test_table = vec![
+            (
+                "io.readFile(\"a.txt\");",
+                "'readFile' is not available in the sandbox.",
+            ),
+            (
+                "io.exists(\"a.txt\");",
+                "'exists' is not available in the sandbox.",
+            ),
+            ("os.args;", "'args' is not available in the sandbox."),
+            ("os.exit(0);", "'exit' is not available in the sandbox."),
+        ];
+        for (source, message) in test_table {
+            match interpret(&mut vm, source) {
+                Err(InterpretError::Runtime(err)) => assert_eq!(err.message, message, "{}", source),
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+        // Native classes are unprivileged unless marked
+        interpret(&mut vm, "var x = Math.floor(1.5);").expect("Script failed");
+    }
+}
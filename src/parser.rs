@@ -1,95 +1,672 @@
-use crate::ast::{Expr, Operator, Primitive};
-use crate::token::{Token, TokenWrapper};
+use crate::ast::{
+    ClassDecl, Expr, ExprKind, FunctionDecl, Operator, Primitive, Stmt, StmtWrapper, TryStmt,
+};
+use crate::token::{LocationInfo, Token, TokenWrapper};
 use std::convert::TryFrom;
+use std::fmt;
+
+// Deeper programs are refused rather than overflowing the Rust stack while
+// they are parsed, compiled or dropped
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    // Token the parser was looking at when it gave up
+    pub token: Token,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.token {
+            Token::Eof => write!(f, "[line {}] Error at end: {}", self.line, self.message),
+            ref token => write!(
+                f,
+                "[line {}] Error at {:?}: {}",
+                self.line, token, self.message
+            ),
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+// Binding power of infix operators, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Comma,
+    Assignment,
+    Conditional,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Range,
+    Term,
+    Factor,
+    Unary,
+    Call,
+}
+
+impl Precedence {
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Comma,
+            Precedence::Comma => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Conditional,
+            Precedence::Conditional => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Range,
+            Precedence::Range => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary | Precedence::Call => Precedence::Call,
+        }
+    }
+}
+
+// Called with the rule's token already consumed, the expression is located
+// at that token
+type PrefixFn = fn(&mut Parser) -> ParseResult<ExprKind>;
+// Called with the left operand and the operator token already consumed
+type InfixFn = fn(&mut Parser, Expr) -> ParseResult<ExprKind>;
+
+struct ParseRule {
+    prefix: Option<PrefixFn>,
+    infix: Option<InfixFn>,
+    precedence: Precedence,
+}
+
+// The expression grammar, one row per token. `precedence` is how tightly the
+// token binds as an infix operator.
+fn rule(token: &Token) -> ParseRule {
+    let (prefix, infix, precedence): (Option<PrefixFn>, Option<InfixFn>, _) = match token {
+        Token::LeftParen => (Some(Parser::grouping), Some(Parser::call), Precedence::Call),
+        Token::LeftBracket => (Some(Parser::list), Some(Parser::index), Precedence::Call),
+        Token::Comma => (None, Some(Parser::comma), Precedence::Comma),
+        Token::LeftBrace => (Some(Parser::map), None, Precedence::None),
+        Token::Dot => (None, Some(Parser::get), Precedence::Call),
+        Token::DotDot => (None, Some(Parser::binary), Precedence::Range),
+        Token::Minus => (Some(Parser::unary), Some(Parser::binary), Precedence::Term),
+        Token::Plus => (None, Some(Parser::binary), Precedence::Term),
+        Token::Slash => (None, Some(Parser::binary), Precedence::Factor),
+        Token::Star => (None, Some(Parser::binary), Precedence::Factor),
+        Token::Question => (None, Some(Parser::conditional), Precedence::Conditional),
+        Token::Bang => (Some(Parser::unary), None, Precedence::None),
+        Token::BangEqual => (None, Some(Parser::binary), Precedence::Equality),
+        Token::Equal => (None, Some(Parser::assignment), Precedence::Assignment),
+        Token::EqualEqual => (None, Some(Parser::binary), Precedence::Equality),
+        Token::Greater => (None, Some(Parser::binary), Precedence::Comparison),
+        Token::GreaterEqual => (None, Some(Parser::binary), Precedence::Comparison),
+        Token::Less => (None, Some(Parser::binary), Precedence::Comparison),
+        Token::LessEqual => (None, Some(Parser::binary), Precedence::Comparison),
+        Token::Identifier(_) => (Some(Parser::variable), None, Precedence::None),
+        Token::String(_) => (Some(Parser::literal), None, Precedence::None),
+        Token::Interpolation(_) => (Some(Parser::interpolation), None, Precedence::None),
+        Token::Number(_) => (Some(Parser::literal), None, Precedence::None),
+        Token::And => (None, Some(Parser::binary), Precedence::And),
+        Token::Fun => (Some(Parser::lambda), None, Precedence::None),
+        Token::Or => (None, Some(Parser::binary), Precedence::Or),
+        Token::Super => (Some(Parser::super_), None, Precedence::None),
+        Token::This => (Some(Parser::this), None, Precedence::None),
+        Token::False | Token::True | Token::Nil => (Some(Parser::literal), None, Precedence::None),
+        _ => (None, None, Precedence::None),
+    };
+    ParseRule {
+        prefix,
+        infix,
+        precedence,
+    }
+}
 
 pub struct Parser {
     tokens: Vec<TokenWrapper>,
     current: usize,
+    // Levels of the tree currently being built, see `nested`
+    depth: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<TokenWrapper>) -> Parser {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            depth: 0,
+        }
     }
 
-    fn expression(&mut self) -> Expr {
-        self.equality()
+    pub fn parse(&mut self) -> Result<Vec<StmtWrapper>, Vec<ParseError>> {
+        let mut statements = vec![];
+        let mut errors = vec![];
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => {
+                    errors.push(err);
+                    self.synchronize();
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(statements)
     }
 
-    fn equality(&mut self) -> Expr {
-        let mut expr = self.comparison();
+    fn declaration(&mut self) -> ParseResult<StmtWrapper> {
+        let location_info = self.location();
+        // 'fun' followed by '(' starts a lambda in an expression statement
+        let stmt = if self._match(&[Token::Class]) {
+            self.class_declaration()?
+        } else if self.check(&Token::Fun) && !self.check_next(&Token::LeftParen) {
+            self.advance();
+            Stmt::Function(self.function()?)
+        } else if self._match(&[Token::Var]) {
+            self.var_declaration()?
+        } else {
+            return self.statement();
+        };
+        Ok(StmtWrapper {
+            location_info,
+            stmt,
+        })
+    }
 
-        while self._match(&vec![Token::BangEqual, Token::EqualEqual]) {
-            let operator: Operator =
-                Operator::try_from(&self.previous().token).expect("Expected operator");
-            let right = self.comparison();
-            expr = Expr::Binary(operator, Box::new(expr), Box::new(right));
+    fn class_declaration(&mut self) -> ParseResult<Stmt> {
+        let name = self.consume_identifier("Expect class name.")?;
+        let superclass = if self._match(&[Token::Less]) {
+            Some(self.consume_identifier("Expect superclass name.")?)
+        } else {
+            None
+        };
+        self.consume(Token::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = vec![];
+        while !self.check(&Token::RightBrace) && !self.is_at_end() {
+            methods.push(self.function()?);
         }
+        self.consume(Token::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class(ClassDecl {
+            name,
+            superclass,
+            methods,
+        }))
+    }
 
-        expr
+    fn function(&mut self) -> ParseResult<FunctionDecl> {
+        let name = self.consume_identifier("Expect function name.")?;
+        self.consume(Token::LeftParen, "Expect '(' after function name.")?;
+        let (params, body) = self.function_body()?;
+        Ok(FunctionDecl { name, params, body })
     }
-    fn comparison(&mut self) -> Expr {
-        let mut expr = self.addition();
 
-        while self._match(&vec![
-            Token::Greater,
-            Token::GreaterEqual,
-            Token::Less,
-            Token::LessEqual,
-        ]) {
-            let operator: Operator =
-                Operator::try_from(&self.previous().token).expect("Expected operator");
-            let right = self.addition();
-            expr = Expr::Binary(operator, Box::new(expr), Box::new(right));
+    // Parameters and body of a function, after the opening '('
+    fn function_body(&mut self) -> ParseResult<(Vec<String>, Vec<StmtWrapper>)> {
+        let mut params = vec![];
+        if !self.check(&Token::RightParen) {
+            loop {
+                params.push(self.consume_identifier("Expect parameter name.")?);
+                if !self._match(&[Token::Comma]) {
+                    break;
+                }
+            }
         }
+        self.consume(Token::RightParen, "Expect ')' after parameters.")?;
+        self.consume(Token::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.block()?;
+        Ok((params, body))
+    }
 
-        expr
+    fn var_declaration(&mut self) -> ParseResult<Stmt> {
+        let name = self.consume_identifier("Expect variable name.")?;
+        let initializer = if self._match(&[Token::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(Token::Semicolon, "Expect ';' after variable declaration.")?;
+        Ok(Stmt::Var(name, initializer))
     }
 
-    fn addition(&mut self) -> Expr {
-        let mut expr = self.multiplication();
+    fn statement(&mut self) -> ParseResult<StmtWrapper> {
+        let location_info = self.location();
+        let stmt = if self._match(&[Token::For]) {
+            return self.for_statement(location_info);
+        } else if self._match(&[Token::Break]) {
+            self.consume(Token::Semicolon, "Expect ';' after 'break'.")?;
+            Stmt::Break
+        } else if self._match(&[Token::Continue]) {
+            self.consume(Token::Semicolon, "Expect ';' after 'continue'.")?;
+            Stmt::Continue
+        } else if self._match(&[Token::If]) {
+            self.if_statement()?
+        } else if self._match(&[Token::Print]) {
+            let value = self.expression()?;
+            self.consume(Token::Semicolon, "Expect ';' after value.")?;
+            Stmt::Print(value)
+        } else if self._match(&[Token::Return]) {
+            let value = if self.check(&Token::Semicolon) {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.consume(Token::Semicolon, "Expect ';' after return value.")?;
+            Stmt::Return(value)
+        } else if self._match(&[Token::Throw]) {
+            let value = self.expression()?;
+            self.consume(Token::Semicolon, "Expect ';' after thrown value.")?;
+            Stmt::Throw(value)
+        } else if self._match(&[Token::Try]) {
+            self.try_statement()?
+        } else if self._match(&[Token::While]) {
+            self.consume(Token::LeftParen, "Expect '(' after 'while'.")?;
+            let condition = self.expression()?;
+            self.consume(Token::RightParen, "Expect ')' after condition.")?;
+            Stmt::While(condition, Box::new(self.nested(Parser::statement)?), None)
+        } else if !self.check_map_literal() && self._match(&[Token::LeftBrace]) {
+            Stmt::Block(self.block()?)
+        } else {
+            let expr = self.expression()?;
+            self.consume(Token::Semicolon, "Expect ';' after expression.")?;
+            Stmt::Expression(expr)
+        };
+        Ok(StmtWrapper {
+            location_info,
+            stmt,
+        })
+    }
 
-        while self._match(&vec![Token::Minus, Token::Plus]) {
-            let operator: Operator =
-                Operator::try_from(&self.previous().token).expect("Expected operator");
-            let right = self.multiplication();
-            expr = Expr::Binary(operator, Box::new(expr), Box::new(right));
+    fn try_statement(&mut self) -> ParseResult<Stmt> {
+        self.consume(Token::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+        let catch = if self._match(&[Token::Catch]) {
+            self.consume(Token::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume_identifier("Expect catch variable name.")?;
+            self.consume(Token::RightParen, "Expect ')' after catch variable.")?;
+            self.consume(Token::LeftBrace, "Expect '{' before catch body.")?;
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+        let finally = if self._match(&[Token::Finally]) {
+            self.consume(Token::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error("Expect 'catch' or 'finally' after try block."));
         }
+        Ok(Stmt::Try(TryStmt {
+            body,
+            catch,
+            finally,
+        }))
+    }
 
-        expr
+    // There is no for node, the loop is desugared into a while loop
+    fn for_statement(&mut self, location_info: LocationInfo) -> ParseResult<StmtWrapper> {
+        let wrap = |stmt| StmtWrapper {
+            location_info: location_info.clone(),
+            stmt,
+        };
+        self.consume(Token::LeftParen, "Expect '(' after 'for'.")?;
+        if self.check(&Token::Var) && self.check_in(2) {
+            self.advance();
+            let name = self.consume_identifier("Expect variable name.")?;
+            self.advance();
+            let iterable = self.expression()?;
+            self.consume(Token::RightParen, "Expect ')' after for clauses.")?;
+            let body = self.nested(Parser::statement)?;
+            return Ok(wrap(Stmt::ForIn(name, iterable, Box::new(body))));
+        }
+        let initializer = if self._match(&[Token::Semicolon]) {
+            None
+        } else if self._match(&[Token::Var]) {
+            Some(wrap(self.var_declaration()?))
+        } else {
+            let expr = self.expression()?;
+            self.consume(Token::Semicolon, "Expect ';' after expression.")?;
+            Some(wrap(Stmt::Expression(expr)))
+        };
+        let condition = if self.check(&Token::Semicolon) {
+            Expr::new(
+                location_info.clone(),
+                ExprKind::Literal(Primitive::Boolean(true)),
+            )
+        } else {
+            self.expression()?
+        };
+        self.consume(Token::Semicolon, "Expect ';' after loop condition.")?;
+        let increment = if self.check(&Token::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(Token::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.nested(Parser::statement)?;
+        let mut body = wrap(Stmt::While(condition, Box::new(body), increment));
+        if let Some(initializer) = initializer {
+            body = wrap(Stmt::Block(vec![initializer, body]));
+        }
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> ParseResult<Stmt> {
+        self.consume(Token::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(Token::RightParen, "Expect ')' after if condition.")?;
+        let then_branch = Box::new(self.nested(Parser::statement)?);
+        let else_branch = if self._match(&[Token::Else]) {
+            Some(Box::new(self.nested(Parser::statement)?))
+        } else {
+            None
+        };
+        Ok(Stmt::If(condition, then_branch, else_branch))
+    }
+
+    // Assumes the opening '{' has been consumed
+    fn block(&mut self) -> ParseResult<Vec<StmtWrapper>> {
+        self.nested(|parser| {
+            let mut statements = vec![];
+            while !parser.check(&Token::RightBrace) && !parser.is_at_end() {
+                statements.push(parser.declaration()?);
+            }
+            parser.consume(Token::RightBrace, "Expect '}' after block.")?;
+            Ok(statements)
+        })
+    }
+
+    fn expression(&mut self) -> ParseResult<Expr> {
+        self.parse_precedence(Precedence::Comma)
     }
 
-    fn multiplication(&mut self) -> Expr {
-        let mut expr = self.unary();
+    // Parses anything that binds at least as tightly as `precedence`
+    fn parse_precedence(&mut self, precedence: Precedence) -> ParseResult<Expr> {
+        self.nested(|parser| parser.operators(precedence))
+    }
+
+    fn operators(&mut self, precedence: Precedence) -> ParseResult<Expr> {
+        let prefix = match rule(self.peek()).prefix {
+            Some(prefix) => prefix,
+            None => return Err(self.error("Expect expression.")),
+        };
+        let location_info = self.location();
+        self.advance();
+        let mut expr = Expr::new(location_info, prefix(self)?);
+
+        loop {
+            let ParseRule {
+                infix,
+                precedence: next,
+                ..
+            } = rule(self.peek());
+            match infix {
+                Some(infix) if precedence <= next => {
+                    // Chained operators deepen the tree without recursing here
+                    self.deepen()?;
+                    let location_info = self.location();
+                    self.advance();
+                    expr = Expr::new(location_info, infix(self, expr)?);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
 
-        while self._match(&vec![Token::Star, Token::Slash]) {
-            let operator: Operator =
-                Operator::try_from(&self.previous().token).expect("Expected operator");
-            let right = self.unary();
-            expr = Expr::Binary(operator, Box::new(expr), Box::new(right));
+    fn assignment(&mut self, target: Expr) -> ParseResult<ExprKind> {
+        // Right associative, so the value may itself be an assignment
+        let value = self.parse_precedence(Precedence::Assignment)?;
+        match target.kind {
+            ExprKind::Variable(name) => Ok(ExprKind::Assign(name, Box::new(value))),
+            ExprKind::Get(object, name) => Ok(ExprKind::Set(object, name, Box::new(value))),
+            ExprKind::Index(object, index) => {
+                Ok(ExprKind::SetIndex(object, index, Box::new(value)))
+            }
+            _ => Err(self.error_at_previous("Invalid assignment target.")),
         }
+    }
 
-        expr
+    fn comma(&mut self, left: Expr) -> ParseResult<ExprKind> {
+        let right = self.parse_precedence(Precedence::Assignment)?;
+        Ok(ExprKind::Comma(Box::new(left), Box::new(right)))
     }
 
-    fn unary(&mut self) -> Expr {
-        if self._match(&vec![Token::Bang, Token::Minus]) {
-            let operator: Operator =
-                Operator::try_from(&self.previous().token).expect("Expected operator");
-            let right = self.unary();
-            Expr::Unary(operator, Box::new(right))
-        } else {
-            self.primary()
+    fn conditional(&mut self, condition: Expr) -> ParseResult<ExprKind> {
+        // Like C, anything goes between '?' and ':'
+        let then_expr = self.expression()?;
+        self.consume(Token::Colon, "Expect ':' after then branch of conditional.")?;
+        // Right associative, so the else branch may itself be a conditional
+        let else_expr = self.parse_precedence(Precedence::Conditional)?;
+        Ok(ExprKind::Conditional(
+            Box::new(condition),
+            Box::new(then_expr),
+            Box::new(else_expr),
+        ))
+    }
+
+    fn binary(&mut self, left: Expr) -> ParseResult<ExprKind> {
+        let token = self.previous().token.clone();
+        let operator = Operator::try_from(&token).expect("Expected operator");
+        // Left associative, the right operand only takes tighter operators
+        let right = self.parse_precedence(rule(&token).precedence.next())?;
+        let expr = match operator {
+            Operator::And | Operator::Or => {
+                ExprKind::Logical(operator, Box::new(left), Box::new(right))
+            }
+            _ => ExprKind::Binary(operator, Box::new(left), Box::new(right)),
+        };
+        Ok(expr)
+    }
+
+    fn call(&mut self, callee: Expr) -> ParseResult<ExprKind> {
+        let mut args = vec![];
+        if !self.check(&Token::RightParen) {
+            loop {
+                // Commas here separate arguments, not comma expressions
+                args.push(self.parse_precedence(Precedence::Assignment)?);
+                if !self._match(&[Token::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(Token::RightParen, "Expect ')' after arguments.")?;
+        Ok(ExprKind::Call(Box::new(callee), args))
+    }
+
+    fn index(&mut self, object: Expr) -> ParseResult<ExprKind> {
+        let index = self.expression()?;
+        self.consume(Token::RightBracket, "Expect ']' after index.")?;
+        Ok(ExprKind::Index(Box::new(object), Box::new(index)))
+    }
+
+    fn get(&mut self, object: Expr) -> ParseResult<ExprKind> {
+        let name = self.consume_identifier("Expect property name after '.'.")?;
+        Ok(ExprKind::Get(Box::new(object), name))
+    }
+
+    fn unary(&mut self) -> ParseResult<ExprKind> {
+        let operator = Operator::try_from(&self.previous().token).expect("Expected operator");
+        let right = self.parse_precedence(Precedence::Unary)?;
+        Ok(ExprKind::Unary(operator, Box::new(right)))
+    }
+
+    fn lambda(&mut self) -> ParseResult<ExprKind> {
+        self.consume(Token::LeftParen, "Expect '(' after 'fun'.")?;
+        let (params, body) = self.function_body()?;
+        Ok(ExprKind::Lambda(params, body))
+    }
+
+    fn list(&mut self) -> ParseResult<ExprKind> {
+        let mut elements = vec![];
+        if !self.check(&Token::RightBracket) {
+            loop {
+                elements.push(self.parse_precedence(Precedence::Assignment)?);
+                if !self._match(&[Token::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(Token::RightBracket, "Expect ']' after list elements.")?;
+        Ok(ExprKind::List(elements))
+    }
+
+    fn map(&mut self) -> ParseResult<ExprKind> {
+        let mut entries = vec![];
+        if !self.check(&Token::RightBrace) {
+            loop {
+                let key = self.parse_precedence(Precedence::Assignment)?;
+                self.consume(Token::Colon, "Expect ':' after map key.")?;
+                let value = self.parse_precedence(Precedence::Assignment)?;
+                entries.push((key, value));
+                if !self._match(&[Token::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(Token::RightBrace, "Expect '}' after map entries.")?;
+        Ok(ExprKind::Map(entries))
+    }
+
+    fn grouping(&mut self) -> ParseResult<ExprKind> {
+        let expr = self.expression()?;
+        self.consume(Token::RightParen, "Expect ')' after expression.")?;
+        Ok(ExprKind::Grouping(Box::new(expr)))
+    }
+
+    fn literal(&mut self) -> ParseResult<ExprKind> {
+        let p = Primitive::try_from(&self.previous().token).expect("Expected literal");
+        Ok(ExprKind::Literal(p))
+    }
+
+    // The scanner emits a segment before every embedded expression and a
+    // closing string segment after the last one
+    fn interpolation(&mut self) -> ParseResult<ExprKind> {
+        let mut parts = vec![];
+        let mut segment = self.previous().token.clone();
+        loop {
+            match segment {
+                Token::Interpolation(s) | Token::String(s) if !s.is_empty() => {
+                    let location_info = self.previous().location_info.clone();
+                    parts.push(Expr::new(
+                        location_info,
+                        ExprKind::Literal(Primitive::String(s)),
+                    ))
+                }
+                _ => {}
+            }
+            if let Token::String(_) = self.previous().token {
+                break;
+            }
+            parts.push(self.expression()?);
+            segment = match self.peek() {
+                Token::Interpolation(_) | Token::String(_) => self.advance().clone(),
+                _ => return Err(self.error("Expect '}' after interpolated expression.")),
+            };
+        }
+        Ok(ExprKind::Interpolation(parts))
+    }
+
+    fn super_(&mut self) -> ParseResult<ExprKind> {
+        self.consume(Token::Dot, "Expect '.' after 'super'.")?;
+        let method = self.consume_identifier("Expect superclass method name.")?;
+        Ok(ExprKind::Super(method))
+    }
+
+    fn this(&mut self) -> ParseResult<ExprKind> {
+        Ok(ExprKind::This)
+    }
+
+    fn variable(&mut self) -> ParseResult<ExprKind> {
+        match &self.previous().token {
+            Token::Identifier(name) => Ok(ExprKind::Variable(name.clone())),
+            token => panic!("Expected identifier, got {:?}", token),
+        }
+    }
+
+    // Runs `parse` a level deeper, levels it adds stop counting once it returns
+    fn nested<T, F>(&mut self, parse: F) -> ParseResult<T>
+    where
+        F: FnOnce(&mut Parser) -> ParseResult<T>,
+    {
+        let depth = self.depth;
+        let result = self.deepen().and_then(|()| parse(self));
+        self.depth = depth;
+        result
+    }
+
+    fn deepen(&mut self) -> ParseResult<()> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Too deeply nested."));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Skip to the start of the next statement so one mistake reports one error
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().token == Token::Semicolon {
+                return;
+            }
+            match self.peek() {
+                Token::Class
+                | Token::Fun
+                | Token::Var
+                | Token::For
+                | Token::If
+                | Token::While
+                | Token::Print
+                | Token::Return
+                | Token::Throw
+                | Token::Try => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, needle: Token, message: &str) -> ParseResult<()> {
+        if self.check(&needle) {
+            self.advance();
+            return Ok(());
+        }
+        Err(self.error(message))
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> ParseResult<String> {
+        if let Token::Identifier(name) = self.peek() {
+            let name = name.clone();
+            self.advance();
+            return Ok(name);
+        }
+        Err(self.error(message))
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            line: self.location().line,
+            token: self.peek().clone(),
+            message: message.to_owned(),
         }
     }
 
-    fn primary(&mut self) -> Expr {
-        let p = Primitive::try_from(self.advance()).expect("Not a primitive");
-        // TODO: Try to match grouping if primitive match fails
-        Expr::Literal(p)
+    fn error_at_previous(&self, message: &str) -> ParseError {
+        let wrapper = self.previous();
+        ParseError {
+            line: wrapper.location_info.line,
+            token: wrapper.token.clone(),
+            message: message.to_owned(),
+        }
     }
 
-    fn _match(&mut self, needles: &Vec<Token>) -> bool {
+    fn _match(&mut self, needles: &[Token]) -> bool {
         for needle in needles.iter() {
             if self.check(needle) {
                 self.advance();
@@ -99,41 +676,306 @@ impl Parser {
         false
     }
 
+    // A statement starting with `{` is a block unless it opens with a
+    // single token key and a colon, which no statement can
+    fn check_map_literal(&self) -> bool {
+        let token = |offset: usize| self.tokens.get(self.current + offset).map(|w| &w.token);
+        self.check(&Token::LeftBrace)
+            && matches!(
+                token(1),
+                Some(Token::String(_))
+                    | Some(Token::Number(_))
+                    | Some(Token::Identifier(_))
+                    | Some(Token::True)
+                    | Some(Token::False)
+                    | Some(Token::Nil)
+            )
+            && token(2) == Some(&Token::Colon)
+    }
+
+    // `in` is only a keyword inside for clauses, it stays usable as a name
+    fn check_in(&self, offset: usize) -> bool {
+        matches!(
+            self.tokens.get(self.current + offset).map(|w| &w.token),
+            Some(Token::Identifier(name)) if name == "in"
+        )
+    }
+
+    fn check_next(&self, needle: &Token) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|wrapper| &wrapper.token == needle)
+    }
+
     fn check(&self, needle: &Token) -> bool {
         if self.is_at_end() {
             return false;
         }
-        return self.peek() == needle;
+        self.peek() == needle
     }
 
     fn advance(&mut self) -> &Token {
-        if (!self.is_at_end()) {
+        if !self.is_at_end() {
             self.current += 1;
         }
-        return &self.previous().token;
+        &self.previous().token
     }
 
     fn is_at_end(&self) -> bool {
-        return self.peek() == &Token::Eof;
+        self.peek() == &Token::Eof
+    }
+
+    fn location(&self) -> LocationInfo {
+        self.peek_wrapper().location_info.clone()
     }
 
     fn peek(&self) -> &Token {
-        &self
-            .tokens
-            .get(self.current)
-            .expect(&format!(
+        &self.peek_wrapper().token
+    }
+
+    fn peek_wrapper(&self) -> &TokenWrapper {
+        self.tokens.get(self.current).unwrap_or_else(|| {
+            panic!(
                 "Tried to peek when 'current' index out of bounds: {} {}",
                 self.current,
                 self.tokens.len()
-            ))
-            .token
+            )
+        })
     }
 
     fn previous(&self) -> &TokenWrapper {
-        return self.tokens.get(self.current - 1).expect(&format!(
-            "Tried to previous when 'previous' index out of bounds: {} {}",
-            self.current - 1,
-            self.tokens.len()
+        self.tokens.get(self.current - 1).unwrap_or_else(|| {
+            panic!(
+                "Tried to previous when 'previous' index out of bounds: {} {}",
+                self.current - 1,
+                self.tokens.len()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+    use crate::sexpr;
+
+    fn parse(source: &str) -> Result<Vec<StmtWrapper>, Vec<ParseError>> {
+        let tokens = Scanner::new(source.to_owned())
+            .scan_tokens()
+            .expect("Source had scanner errors");
+        Parser::new(tokens).parse()
+    }
+
+    fn parse_expr(source: &str) -> Expr {
+        let mut statements = parse(&format!("{};", source)).expect("Source had parse errors");
+        match statements.pop().map(|w| w.stmt) {
+            Some(Stmt::Expression(expr)) => expr,
+            other => panic!("Expected expression statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_expressions() {
+        let test_table = vec![
+            ("-123 * (45.67)", "(* (- 123) (group 45.67))"),
+            ("1 + 2 * 3 - 4", "(- (+ 1 (* 2 3)) 4)"),
+            ("!a == b < c", "(== (! a) (< b c))"),
+            ("a or b and c", "(or a (and b c))"),
+            ("a = b = 1", "(= a (= b 1))"),
+            ("f(1, g(2))(3)", "(call (call f 1 (call g 2)) 3)"),
+            ("f(\"a b\", nil)", "(call f \"a b\" nil)"),
+            ("a - b - c / d / e", "(- (- a b) (/ (/ c d) e))"),
+            ("- -a < !!b or c", "(or (< (- (- a)) (! (! b))) c)"),
+            ("a = b or c and d == e", "(= a (or b (and c (== d e))))"),
+            ("-f(a)(b)", "(- (call (call f a) b))"),
+            ("a ? b : c ? d : e", "(? a b (? c d e))"),
+            ("x = a or b ? c, d : e", "(= x (? (or a b) (, c d) e))"),
+            ("a, b = 1, c", "(, (, a (= b 1)) c)"),
+            ("f((a, b), c ? d : e)", "(call f (group (, a b)) (? c d e))"),
+            ("[1, [a], []]", "(list 1 (list a) (list))"),
+            ("-xs[i + 1][0]", "(- (index (index xs (+ i 1)) 0))"),
+            ("xs[0] = ys[-1] = 2", "(index= xs 0 (index= ys (- 1) 2))"),
+            ("xs.map(f).len()", "(call (. (call (. xs map) f) len))"),
+            ("x = {}", "(= x (map))"),
+            ("a < 0..n + 1", "(< a (.. 0 (+ n 1)))"),
+            ("this.x = this.y.z = in", "(.= this x (.= (. this y) z in))"),
+            ("\"${a}\"", "(interpolate a)"),
+            (
+                "\"Hi ${name}, ${\"${n + 1}\" + {}.len} items\"",
+                "(interpolate \"Hi \" name \", \" (+ (interpolate (+ n 1)) (. (map) len)) \" items\")",
+            ),
+            (
+                "{\"a\": 1, b ? 2 : 3: {}}[nil]",
+                "(index (map \"a\" 1 (? b 2 3) (map)) nil)",
+            ),
+        ];
+        for (input, expected) in test_table {
+            let expected = sexpr::parse(expected).expect("Malformed fixture");
+            assert_eq!(parse_expr(input), expected, "{}", input);
+        }
+        // Lambda bodies have no S-expression form, so compare the printed output
+        let lambda = parse_expr("f(fun (a, b) { return a; }, 1)");
+        assert_eq!(format!("{}", lambda), "(call f (fun (a b) ...) 1)");
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let statements = parse(
+            "var a = 1;
+            fun add(x, y) { return x + y; }
+            for (var i = 0; i < 3; i = i + 1) print i;
+            if (a) print a; else { print nil; }",
+        )
+        .expect("Source had parse errors");
+        assert_eq!(statements.len(), 4);
+        assert!(matches!(statements[1].stmt, Stmt::Function(ref f) if f.params.len() == 2));
+        // The for loop is desugared into a block holding the initializer and a while loop
+        match &statements[2].stmt {
+            Stmt::Block(inner) => assert!(matches!(inner[1].stmt, Stmt::While(_, _, Some(_)))),
+            other => panic!("Expected desugared for loop, got {:?}", other),
+        }
+        assert_eq!(statements[3].location_info.line, 4);
+
+        // A lambda at the start of a statement is an expression, not a declaration
+        let statements =
+            parse("fun () {}; fun (x) { print x; }(1);").expect("Source had parse errors");
+        assert_eq!(statements.len(), 2);
+        assert!(
+            matches!(&statements[0].stmt, Stmt::Expression(Expr { kind: ExprKind::Lambda(p, _), .. }) if p.is_empty())
+        );
+        assert!(matches!(
+            &statements[1].stmt,
+            Stmt::Expression(Expr {
+                kind: ExprKind::Call(..),
+                ..
+            })
+        ));
+
+        let statements = parse(
+            "class Point { init(x) { this.x = x; } norm() { return this.x; } }
+            for (var x in xs) print x;
+            for (var in = 0; in < 1;) {}
+            class Origin < Point { init() { super.init(0); } }",
+        )
+        .expect("Source had parse errors");
+        match &statements[0].stmt {
+            Stmt::Class(decl) => {
+                let names: Vec<&str> = decl.methods.iter().map(|m| m.name.as_str()).collect();
+                assert_eq!(names, vec!["init", "norm"]);
+                assert!(decl.superclass.is_none());
+            }
+            other => panic!("Expected class, got {:?}", other),
+        }
+        match &statements[3].stmt {
+            Stmt::Class(decl) => {
+                assert_eq!(decl.superclass.as_deref(), Some("Point"));
+                assert_eq!(
+                    decl.methods[0].body[0].stmt,
+                    Stmt::Expression(
+                        ExprKind::Call(
+                            Box::new(ExprKind::Super("init".to_owned()).into()),
+                            vec![ExprKind::Literal(Primitive::Number(0.0)).into()]
+                        )
+                        .into()
+                    )
+                );
+            }
+            other => panic!("Expected class, got {:?}", other),
+        }
+        assert!(matches!(&statements[1].stmt, Stmt::ForIn(name, _, _) if name == "x"));
+        assert!(matches!(&statements[2].stmt, Stmt::Block(_)));
+
+        // Likewise a brace followed by a key and a colon opens a map, not a block
+        let statements = parse("{\"a\": 1}.len(); {a;} {}").expect("Source had parse errors");
+        assert!(matches!(
+            &statements[0].stmt,
+            Stmt::Expression(Expr {
+                kind: ExprKind::Call(..),
+                ..
+            })
         ));
+        assert!(matches!(&statements[1].stmt, Stmt::Block(b) if b.len() == 1));
+        assert!(matches!(&statements[2].stmt, Stmt::Block(b) if b.is_empty()));
+
+        let statements = parse(
+            "try { throw 1; } catch (e) { print e; }
+            try { f(); } finally { print 2; }
+            try {} catch (e) {} finally {}",
+        )
+        .expect("Source had parse errors");
+        match &statements[0].stmt {
+            Stmt::Try(stmt) => {
+                assert!(matches!(stmt.body[0].stmt, Stmt::Throw(_)));
+                assert!(
+                    matches!(&stmt.catch, Some((name, handler)) if name == "e" && handler.len() == 1)
+                );
+                assert!(stmt.finally.is_none());
+            }
+            other => panic!("Expected try, got {:?}", other),
+        }
+        assert!(
+            matches!(&statements[1].stmt, Stmt::Try(s) if s.catch.is_none() && s.finally.is_some())
+        );
+        assert!(
+            matches!(&statements[2].stmt, Stmt::Try(s) if s.catch.is_some() && s.finally.is_some())
+        );
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        let expr = |open: &str, close: &str, depth| {
+            format!("print {}1{};", open.repeat(depth), close.repeat(depth))
+        };
+        let stmt = |open: &str, close: &str, depth| {
+            format!("{}print 1;{}", open.repeat(depth), close.repeat(depth))
+        };
+        let test_table = vec![
+            expr("(", ")", 200_000),
+            expr("-", "", 200_000),
+            expr("1 + ", "", 200_000),
+            expr("[", "]", 200_000),
+            expr("fun () { return ", "; }", 10_000),
+            stmt("{ ", " }", 200_000),
+            stmt("if (true) ", "", 10_000),
+        ];
+        for source in test_table {
+            let errors = parse(&source).expect_err("Expected errors");
+            assert_eq!(errors[0].message, "Too deeply nested.", "{:.20}", source);
+        }
+        for source in [
+            expr("(", ")", 100),
+            expr("1 + ", "", 100),
+            stmt("{ ", " }", 100),
+        ] {
+            parse(&source).expect("Source had parse errors");
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors =
+            parse("var = 1; print (1 + 2; 1 = 2; a + b = c; print -; a ? b; [1, 2; a.1; class { } print \"${a b}\"; try {} print 1; try {} catch e {} print 3; class B < {} print super;")
+                .expect_err("Expected errors");
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Expect variable name.",
+                "Expect ')' after expression.",
+                "Invalid assignment target.",
+                "Invalid assignment target.",
+                "Expect expression.",
+                "Expect ':' after then branch of conditional.",
+                "Expect ']' after list elements.",
+                "Expect property name after '.'.",
+                "Expect class name.",
+                "Expect '}' after interpolated expression.",
+                "Expect 'catch' or 'finally' after try block.",
+                "Expect '(' after 'catch'.",
+                "Expect superclass name.",
+                "Expect '.' after 'super'.",
+            ]
+        );
     }
 }
@@ -1,34 +1,670 @@
-use crate::ast::Expr;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
 
-fn rpn(expr: &Expr) -> String {
-    match expr {
-        Expr::Binary(o, b1, b2) => format!("{} {} {}", rpn(b1), rpn(b2), o),
-        Expr::Grouping(b) => format!("{}", rpn(b)),
-        Expr::Literal(p) => format!("{}", p),
-        Expr::Unary(_o, _b) => unimplemented!(),
+use crate::ast::{Expr, ExprKind, Operator, Primitive, StmtWrapper};
+use crate::optimizer::fold_binary;
+use crate::visit::Visitor;
+
+// Unary minus, spelled differently from binary minus so the text stays unambiguous
+const NEG: &str = "neg";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpnError {
+    // Operator found fewer operands on the stack than it needs
+    StackUnderflow(String),
+    // Input did not reduce to exactly one expression
+    LeftoverOperands(usize),
+    UnterminatedString,
+    InvalidAssignmentTarget,
+    UnboundVariable(String),
+    InvalidOperands(String),
+    // The evaluator only handles expressions without side effects outside `env`
+    Unsupported(String),
+}
+
+impl fmt::Display for RpnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpnError::StackUnderflow(token) => write!(f, "Not enough operands for '{}'", token),
+            RpnError::LeftoverOperands(n) => {
+                write!(f, "Expected a single expression, {} left on the stack", n)
+            }
+            RpnError::UnterminatedString => write!(f, "String not terminated"),
+            RpnError::InvalidAssignmentTarget => write!(f, "Invalid assignment target"),
+            RpnError::UnboundVariable(name) => write!(f, "Undefined variable '{}'", name),
+            RpnError::InvalidOperands(token) => write!(f, "Invalid operands for '{}'", token),
+            RpnError::Unsupported(token) => write!(f, "Cannot evaluate '{}'", token),
+        }
+    }
+}
+
+/// Renders an expression in reverse Polish notation. Groupings vanish since
+/// the order of the operators already encodes them.
+pub fn rpn(expr: &Expr) -> String {
+    RpnPrinter.visit_expr(expr)
+}
+
+struct RpnPrinter;
+
+impl Visitor for RpnPrinter {
+    type Output = String;
+
+    fn visit_assign(&mut self, name: &str, value: &Expr) -> String {
+        format!("{} {} =", variable(name), self.visit_expr(value))
+    }
+
+    fn visit_binary(&mut self, operator: &Operator, left: &Expr, right: &Expr) -> String {
+        format!(
+            "{} {} {}",
+            self.visit_expr(left),
+            self.visit_expr(right),
+            operator
+        )
+    }
+
+    fn visit_call(&mut self, callee: &Expr, args: &[Expr]) -> String {
+        let mut output = self.visit_expr(callee);
+        for arg in args.iter() {
+            output.push(' ');
+            output.push_str(&self.visit_expr(arg));
+        }
+        format!("{} call/{}", output, args.len())
+    }
+
+    fn visit_comma(&mut self, left: &Expr, right: &Expr) -> String {
+        format!("{} {} ,", self.visit_expr(left), self.visit_expr(right))
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        then_expr: &Expr,
+        else_expr: &Expr,
+    ) -> String {
+        format!(
+            "{} {} {} ?:",
+            self.visit_expr(condition),
+            self.visit_expr(then_expr),
+            self.visit_expr(else_expr)
+        )
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &str) -> String {
+        format!("{} .{}", self.visit_expr(object), name)
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr) -> String {
+        format!("{} {} []", self.visit_expr(object), self.visit_expr(index))
+    }
+
+    fn visit_interpolation(&mut self, parts: &[Expr]) -> String {
+        let mut output = String::new();
+        for part in parts.iter() {
+            output.push_str(&self.visit_expr(part));
+            output.push(' ');
+        }
+        format!("{}str/{}", output, parts.len())
+    }
+
+    // A function body has no RPN form, the reader rejects this token
+    fn visit_lambda(&mut self, params: &[String], _body: &[StmtWrapper]) -> String {
+        format!("fun/{}", params.len())
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> String {
+        let mut output = String::new();
+        for element in elements.iter() {
+            output.push_str(&self.visit_expr(element));
+            output.push(' ');
+        }
+        format!("{}list/{}", output, elements.len())
+    }
+
+    fn visit_literal(&mut self, value: &Primitive) -> String {
+        format!("{}", value)
+    }
+
+    fn visit_logical(&mut self, operator: &Operator, left: &Expr, right: &Expr) -> String {
+        self.visit_binary(operator, left, right)
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> String {
+        let mut output = String::new();
+        for (key, value) in entries.iter() {
+            output.push_str(&self.visit_expr(key));
+            output.push(' ');
+            output.push_str(&self.visit_expr(value));
+            output.push(' ');
+        }
+        format!("{}map/{}", output, entries.len())
+    }
+
+    fn visit_set_index(&mut self, object: &Expr, index: &Expr, value: &Expr) -> String {
+        format!(
+            "{} {} {} []=",
+            self.visit_expr(object),
+            self.visit_expr(index),
+            self.visit_expr(value)
+        )
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &str, value: &Expr) -> String {
+        format!(
+            "{} {} .{}=",
+            self.visit_expr(object),
+            self.visit_expr(value),
+            name
+        )
+    }
+
+    fn visit_super(&mut self, method: &str) -> String {
+        format!("super.{}", method)
+    }
+
+    fn visit_this(&mut self) -> String {
+        "this".to_owned()
+    }
+
+    fn visit_unary(&mut self, operator: &Operator, right: &Expr) -> String {
+        match operator {
+            Operator::Minus => format!("{} {}", self.visit_expr(right), NEG),
+            _ => format!("{} {}", self.visit_expr(right), operator),
+        }
+    }
+
+    fn visit_variable(&mut self, name: &str) -> String {
+        variable(name)
     }
 }
 
+// Names that would read back as something else get a '$' prefix
+fn variable(name: &str) -> String {
+    match read_word(name) {
+        Item::Name(_) => name.to_owned(),
+        _ => format!("${}", name),
+    }
+}
+
+/// Reads reverse Polish notation back into an expression.
+pub fn parse(text: &str) -> Result<Expr, RpnError> {
+    let mut stack: Vec<Expr> = vec![];
+    for (token, item) in read(text)? {
+        let kind = match item {
+            Item::Literal(p) => ExprKind::Literal(p),
+            Item::Name(name) => ExprKind::Variable(name),
+            Item::Unary(o) => {
+                let [b] = pop_n(&mut stack, &token)?;
+                ExprKind::Unary(o, Box::new(b))
+            }
+            Item::Binary(o) => {
+                let [b1, b2] = pop_n(&mut stack, &token)?;
+                ExprKind::Binary(o, Box::new(b1), Box::new(b2))
+            }
+            Item::Logical(o) => {
+                let [b1, b2] = pop_n(&mut stack, &token)?;
+                ExprKind::Logical(o, Box::new(b1), Box::new(b2))
+            }
+            Item::Comma => {
+                let [b1, b2] = pop_n(&mut stack, &token)?;
+                ExprKind::Comma(Box::new(b1), Box::new(b2))
+            }
+            Item::Conditional => {
+                let [c, b1, b2] = pop_n(&mut stack, &token)?;
+                ExprKind::Conditional(Box::new(c), Box::new(b1), Box::new(b2))
+            }
+            Item::Assign => match pop_n(&mut stack, &token)? {
+                [Expr {
+                    kind: ExprKind::Variable(name),
+                    ..
+                }, value] => ExprKind::Assign(name, Box::new(value)),
+                _ => return Err(RpnError::InvalidAssignmentTarget),
+            },
+            Item::Lambda => return Err(RpnError::Unsupported(token)),
+            Item::Index => {
+                let [object, index] = pop_n(&mut stack, &token)?;
+                ExprKind::Index(Box::new(object), Box::new(index))
+            }
+            Item::SetIndex => {
+                let [object, index, value] = pop_n(&mut stack, &token)?;
+                ExprKind::SetIndex(Box::new(object), Box::new(index), Box::new(value))
+            }
+            Item::Get(name) => {
+                let [object] = pop_n(&mut stack, &token)?;
+                ExprKind::Get(Box::new(object), name)
+            }
+            Item::Set(name) => {
+                let [object, value] = pop_n(&mut stack, &token)?;
+                ExprKind::Set(Box::new(object), name, Box::new(value))
+            }
+            Item::Super(method) => ExprKind::Super(method),
+            Item::This => ExprKind::This,
+            Item::List(len) => {
+                if stack.len() < len {
+                    return Err(RpnError::StackUnderflow(token));
+                }
+                ExprKind::List(stack.split_off(stack.len() - len))
+            }
+            Item::Interpolation(len) => {
+                if stack.len() < len {
+                    return Err(RpnError::StackUnderflow(token));
+                }
+                ExprKind::Interpolation(stack.split_off(stack.len() - len))
+            }
+            Item::Map(len) => {
+                if stack.len() < len * 2 {
+                    return Err(RpnError::StackUnderflow(token));
+                }
+                let mut operands = stack.split_off(stack.len() - len * 2).into_iter();
+                let mut entries = vec![];
+                while let (Some(key), Some(value)) = (operands.next(), operands.next()) {
+                    entries.push((key, value));
+                }
+                ExprKind::Map(entries)
+            }
+            Item::Call(arity) => {
+                if stack.len() < arity + 1 {
+                    return Err(RpnError::StackUnderflow(token));
+                }
+                let args = stack.split_off(stack.len() - arity);
+                let callee = stack.pop().expect("Checked stack length");
+                ExprKind::Call(Box::new(callee), args)
+            }
+        };
+        stack.push(kind.into());
+    }
+    single(stack)
+}
+
+/// Evaluates reverse Polish notation directly on a value stack. Variables are
+/// read from and assigned into `env`. Both operands of `and`/`or` are
+/// evaluated since they are already on the stack by the time it is reached.
+pub fn evaluate(text: &str, env: &mut HashMap<String, Primitive>) -> Result<Primitive, RpnError> {
+    let mut stack: Vec<Slot> = vec![];
+    for (token, item) in read(text)? {
+        let value = match item {
+            Item::Literal(p) => Slot::Value(p),
+            // Resolved once an operator consumes it, `=` needs the name instead
+            Item::Name(name) => Slot::Name(name),
+            Item::Unary(o) => {
+                let [b] = pop_n(&mut stack, &token)?;
+                match (o, b.resolve(env)?) {
+                    (Operator::Minus, Primitive::Number(n)) => Slot::Value(Primitive::Number(-n)),
+                    (Operator::Bang, p) => Slot::Value(Primitive::Boolean(!p.is_truthy())),
+                    _ => return Err(RpnError::InvalidOperands(token)),
+                }
+            }
+            Item::Binary(o) => {
+                let [b1, b2] = pop_n(&mut stack, &token)?;
+                let p = fold_binary(&o, &b1.resolve(env)?, &b2.resolve(env)?);
+                Slot::Value(p.ok_or(RpnError::InvalidOperands(token))?)
+            }
+            Item::Logical(o) => {
+                let [b1, b2] = pop_n(&mut stack, &token)?;
+                let (b1, b2) = (b1.resolve(env)?, b2.resolve(env)?);
+                match (o, b1.is_truthy()) {
+                    (Operator::Or, true) | (Operator::And, false) => Slot::Value(b1),
+                    _ => Slot::Value(b2),
+                }
+            }
+            Item::Comma => {
+                let [b1, b2] = pop_n(&mut stack, &token)?;
+                b1.resolve(env)?;
+                Slot::Value(b2.resolve(env)?)
+            }
+            Item::Conditional => {
+                let [c, b1, b2] = pop_n(&mut stack, &token)?;
+                let (c, b1, b2) = (c.resolve(env)?, b1.resolve(env)?, b2.resolve(env)?);
+                Slot::Value(if c.is_truthy() { b1 } else { b2 })
+            }
+            Item::Assign => match pop_n(&mut stack, &token)? {
+                [Slot::Name(name), value] => {
+                    let value = value.resolve(env)?;
+                    env.insert(name, value.clone());
+                    Slot::Value(value)
+                }
+                _ => return Err(RpnError::InvalidAssignmentTarget),
+            },
+            Item::Interpolation(len) => {
+                if stack.len() < len {
+                    return Err(RpnError::StackUnderflow(token));
+                }
+                let mut text = String::new();
+                for part in stack.split_off(stack.len() - len) {
+                    text.push_str(&part.resolve(env)?.stringify());
+                }
+                Slot::Value(Primitive::String(text))
+            }
+            // Primitives have no lists or objects to index into
+            Item::Call(_)
+            | Item::Lambda
+            | Item::Index
+            | Item::SetIndex
+            | Item::Get(_)
+            | Item::Set(_)
+            | Item::Super(_)
+            | Item::This
+            | Item::List(_)
+            | Item::Map(_) => return Err(RpnError::Unsupported(token)),
+        };
+        stack.push(value);
+    }
+    single(stack)?.resolve(env)
+}
+
+enum Slot {
+    Value(Primitive),
+    Name(String),
+}
+
+impl Slot {
+    fn resolve(self, env: &HashMap<String, Primitive>) -> Result<Primitive, RpnError> {
+        match self {
+            Slot::Value(p) => Ok(p),
+            Slot::Name(name) => env
+                .get(&name)
+                .cloned()
+                .ok_or(RpnError::UnboundVariable(name)),
+        }
+    }
+}
+
+enum Item {
+    Literal(Primitive),
+    Name(String),
+    Unary(Operator),
+    Binary(Operator),
+    Logical(Operator),
+    Comma,
+    Conditional,
+    Assign,
+    Call(usize),
+    Lambda,
+    Index,
+    SetIndex,
+    Get(String),
+    Set(String),
+    Super(String),
+    This,
+    List(usize),
+    // Number of parts joined into a string
+    Interpolation(usize),
+    // Number of key value pairs
+    Map(usize),
+}
+
+fn read(text: &str) -> Result<Vec<(String, Item)>, RpnError> {
+    let mut items = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(escaped) => s.push(escaped),
+                        None => return Err(RpnError::UnterminatedString),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err(RpnError::UnterminatedString),
+                }
+            }
+            items.push((format!("\"{}\"", s), Item::Literal(Primitive::String(s))));
+            continue;
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            word.push(c);
+            chars.next();
+        }
+        let item = read_word(&word);
+        items.push((word, item));
+    }
+    Ok(items)
+}
+
+fn read_word(word: &str) -> Item {
+    match word {
+        "true" => Item::Literal(Primitive::Boolean(true)),
+        "false" => Item::Literal(Primitive::Boolean(false)),
+        "nil" => Item::Literal(Primitive::Nil),
+        "this" => Item::This,
+        NEG => Item::Unary(Operator::Minus),
+        "!" => Item::Unary(Operator::Bang),
+        "+" => Item::Binary(Operator::Plus),
+        "-" => Item::Binary(Operator::Minus),
+        "*" => Item::Binary(Operator::Star),
+        "/" => Item::Binary(Operator::Divide),
+        "==" => Item::Binary(Operator::EqualEqual),
+        "!=" => Item::Binary(Operator::BangEqual),
+        ">" => Item::Binary(Operator::Greater),
+        ">=" => Item::Binary(Operator::GreaterEqual),
+        "<" => Item::Binary(Operator::Less),
+        "<=" => Item::Binary(Operator::LessEqual),
+        ".." => Item::Binary(Operator::Range),
+        "and" => Item::Logical(Operator::And),
+        "or" => Item::Logical(Operator::Or),
+        "," => Item::Comma,
+        "?:" => Item::Conditional,
+        "=" => Item::Assign,
+        "[]" => Item::Index,
+        "[]=" => Item::SetIndex,
+        _ => {
+            if let Some(name) = word.strip_prefix('$') {
+                return Item::Name(name.to_owned());
+            }
+            if word
+                .strip_prefix("fun/")
+                .is_some_and(|n| n.parse::<usize>().is_ok())
+            {
+                return Item::Lambda;
+            }
+            if let Some(arity) = word.strip_prefix("call/") {
+                if let Ok(arity) = arity.parse() {
+                    return Item::Call(arity);
+                }
+            }
+            if let Some(len) = word.strip_prefix("list/") {
+                if let Ok(len) = len.parse() {
+                    return Item::List(len);
+                }
+            }
+            if let Some(len) = word.strip_prefix("str/") {
+                if let Ok(len) = len.parse() {
+                    return Item::Interpolation(len);
+                }
+            }
+            if let Some(len) = word.strip_prefix("map/") {
+                if let Ok(len) = len.parse() {
+                    return Item::Map(len);
+                }
+            }
+            if let Some(method) = word.strip_prefix("super.") {
+                if method.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                    return Item::Super(method.to_owned());
+                }
+            }
+            if let Some(name) = word.strip_prefix('.') {
+                if name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                    return match name.strip_suffix('=') {
+                        Some(name) => Item::Set(name.to_owned()),
+                        None => Item::Get(name.to_owned()),
+                    };
+                }
+            }
+            match word.parse::<f64>() {
+                Ok(n) => Item::Literal(Primitive::Number(n)),
+                Err(_) => Item::Name(word.to_owned()),
+            }
+        }
+    }
+}
+
+fn pop_n<T, const N: usize>(stack: &mut Vec<T>, token: &str) -> Result<[T; N], RpnError> {
+    if stack.len() < N {
+        return Err(RpnError::StackUnderflow(token.to_owned()));
+    }
+    let operands: Vec<T> = stack.split_off(stack.len() - N);
+    Ok(operands
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("Split off exactly N operands")))
+}
+
+fn single<T>(mut stack: Vec<T>) -> Result<T, RpnError> {
+    if stack.len() != 1 {
+        return Err(RpnError::LeftoverOperands(stack.len()));
+    }
+    Ok(stack.pop().expect("Checked stack length"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ast::{Expr, Operator, Primitive};
+    use crate::ast::{Expr, ExprKind, Operator, Primitive};
+    use crate::ast::{Stmt, StmtWrapper};
+    use crate::utils;
+
+    fn parse_lox(source: &str) -> Expr {
+        match utils::parse(&format!("{};", source)).pop() {
+            Some(StmtWrapper {
+                stmt: Stmt::Expression(expr),
+                ..
+            }) => expr,
+            other => panic!("Expected expression statement, got {:?}", other),
+        }
+    }
 
     #[test]
     fn test_rpn() {
-        let expr = Expr::Binary(
+        let number = |n| Box::new(Expr::from(ExprKind::Literal(Primitive::Number(n))));
+        let group = |operator, a, b| {
+            let binary = ExprKind::Binary(operator, number(a), number(b));
+            Box::new(Expr::from(ExprKind::Grouping(Box::new(binary.into()))))
+        };
+        let expr = Expr::from(ExprKind::Binary(
             Operator::Star,
-            Box::new(Expr::Grouping(Box::new(Expr::Binary(
-                Operator::Plus,
-                Box::new(Expr::Literal(Primitive::Number(1.0))),
-                Box::new(Expr::Literal(Primitive::Number(2.0))),
-            )))),
-            Box::new(Expr::Grouping(Box::new(Expr::Binary(
-                Operator::Minus,
-                Box::new(Expr::Literal(Primitive::Number(4.0))),
-                Box::new(Expr::Literal(Primitive::Number(3.0))),
-            )))),
-        );
+            group(Operator::Plus, 1.0, 2.0),
+            group(Operator::Minus, 4.0, 3.0),
+        ));
         assert_eq!(rpn(&expr), "1 2 + 4 3 - *")
     }
+
+    #[test]
+    fn test_rpn_operators() {
+        let test_table = vec![
+            ("-1 - -x", "1 neg x neg -"),
+            ("!a == !!b", "a ! b ! ! =="),
+            ("a or b and c", "a b c and or"),
+            ("f(1, g())(2)", "f 1 g call/0 call/2 2 call/1"),
+            ("neg = nil", "$neg nil ="),
+            ("a ? b : c ? d : e", "a b c d e ?: ?:"),
+            ("(a, b), c", "a b , c ,"),
+            ("[1, [], x[0]]", "1 list/0 x 0 [] list/3"),
+            ("xs[i] = xs.len()", "xs i xs .len call/0 []="),
+            ("this.n = 1..n", "this 1 n .. .n="),
+            ("super.init(n)", "super.init n call/1"),
+            ("{\"a\": 1, b: {}}", "\"a\" 1 b map/0 map/2"),
+            ("\"n = ${n + 1}!\"", "\"n = \" n 1 + \"!\" str/3"),
+        ];
+        for (input, expected) in test_table {
+            assert_eq!(rpn(&parse_lox(input)), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = vec![
+            "1 + 2 * x - 4 / y",
+            "-a * -(-b)",
+            "!(a < b) != (c >= d)",
+            "a or b and !c",
+            "x = y = 10",
+            "f(1, g(x), \"str with spaces\")(true)",
+            "\"a\" + \"b\" == nil",
+            "neg = neg",
+            "a ? b, c : d ? e : f",
+            "x = (1, y), z",
+            "xs[-1] = [a, b.c][0]",
+            "[].push(list)",
+            "{1: [map], nil: {}}[true]",
+            "this.x = 0..this.y",
+            "super.f(this)(super.g)",
+            "\"${a} and ${\"${b}\"}\"",
+        ];
+        for source in sources {
+            let expr = crate::optimizer::fold_expr(parse_lox(source));
+            // Folding drops groupings, as rpn does
+            let text = rpn(&expr);
+            assert_eq!(parse(&text), Ok(expr), "{} -> {}", source, text);
+        }
+        // Escaped quotes survive
+        let quoted = Expr::from(ExprKind::Literal(Primitive::String(
+            "say \"hi\" \\o/".to_owned(),
+        )));
+        assert_eq!(parse(&rpn(&quoted)), Ok(quoted));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut env = HashMap::new();
+        env.insert("x".to_owned(), Primitive::Number(4.0));
+        let test_table = vec![
+            ("1 2 + 4 3 - *", Primitive::Number(3.0)),
+            ("x 2 / neg", Primitive::Number(-2.0)),
+            ("\"con\" \"cat\" +", Primitive::String("concat".to_owned())),
+            (
+                "nil \"default\" or",
+                Primitive::String("default".to_owned()),
+            ),
+            ("1 2 < ! false ==", Primitive::Boolean(true)),
+            ("y x 1 + =", Primitive::Number(5.0)),
+            ("y 5 ==", Primitive::Boolean(true)),
+            ("nil 1 2 ?: 3 ,", Primitive::Number(3.0)),
+            (
+                "x 0 > \"pos\" \"neg\" ?:",
+                Primitive::String("pos".to_owned()),
+            ),
+            (
+                "\"x is \" x \", \" nil str/4",
+                Primitive::String("x is 4, nil".to_owned()),
+            ),
+        ];
+        for (input, expected) in test_table {
+            assert_eq!(evaluate(input, &mut env), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut env = HashMap::new();
+        assert_eq!(parse("1 +"), Err(RpnError::StackUnderflow("+".to_owned())));
+        assert_eq!(parse("1 2"), Err(RpnError::LeftoverOperands(2)));
+        assert_eq!(parse("\"open"), Err(RpnError::UnterminatedString));
+        assert_eq!(parse("1 2 ="), Err(RpnError::InvalidAssignmentTarget));
+        assert_eq!(
+            evaluate("1 \"a\" -", &mut env),
+            Err(RpnError::InvalidOperands("-".to_owned()))
+        );
+        assert_eq!(
+            evaluate("f call/0", &mut env),
+            Err(RpnError::Unsupported("call/0".to_owned()))
+        );
+        assert_eq!(
+            evaluate("list/0", &mut env),
+            Err(RpnError::Unsupported("list/0".to_owned()))
+        );
+        assert_eq!(
+            evaluate("missing", &mut env),
+            Err(RpnError::UnboundVariable("missing".to_owned()))
+        );
+    }
 }
@@ -17,6 +17,7 @@ pub enum ScannerError {
     UnterminatedString(Cause),
 }
 
+#[cfg(test)]
 impl ScannerError {
     fn discriminant(&self) -> usize {
         match *self {
@@ -56,9 +57,13 @@ pub struct Scanner {
     current: usize,
     //
     current_line: usize,
+    // Index of the first character on the current line
+    line_start: usize,
     //
     tokens_wrappers: Vec<TokenWrapper>,
     errors: Vec<ScannerError>,
+    // Brace depth of each `${...}` we are currently inside of
+    interpolations: Vec<usize>,
 }
 
 impl Scanner {
@@ -68,23 +73,34 @@ impl Scanner {
             start: 0,
             current: 0,
             current_line: 1,
+            line_start: 0,
             tokens_wrappers: vec![],
             errors: vec![],
+            interpolations: vec![],
         }
     }
     fn is_empty(&self) -> bool {
         self.current >= self.characters.len()
     }
 
+    // Tokens spanning lines, like multi-line strings, are put on the line
+    // they end on, at its first column
     fn add_token(&mut self, token: Token) {
         self.tokens_wrappers.push(TokenWrapper {
             token,
             location_info: LocationInfo {
                 line: self.current_line,
+                column: self.start.saturating_sub(self.line_start) + 1,
             },
         });
     }
 
+    // Called with the newline consumed
+    fn newline(&mut self) {
+        self.current_line += 1;
+        self.line_start = self.current;
+    }
+
     fn advance(&mut self) -> char {
         if self.is_empty() {
             return '\0';
@@ -112,7 +128,7 @@ impl Scanner {
         if self.is_empty() {
             return '\0';
         }
-        return *self.characters.get(self.current).unwrap();
+        *self.characters.get(self.current).unwrap()
     }
 
     fn peek_next(&self) -> char {
@@ -125,22 +141,38 @@ impl Scanner {
             .expect("Expected character at index for peek_next")
     }
 
+    // Scans a string segment. The opening delimiter, either '"' or the '}'
+    // closing an interpolated expression, has already been consumed.
     fn consume_string(&mut self) {
-        while self.peek() != '"' && !self.is_empty() {
-            if self.peek() == '\n' {
-                self.current_line += 1;
+        let segment_start = self.current;
+        loop {
+            if self.is_empty() {
+                self.errors.push(ScannerError::UnterminatedString(Cause {
+                    line: self.current_line,
+                    source: char_range_to_string(&self.characters, self.start, self.current),
+                }));
+                return;
+            }
+            match self.peek() {
+                '"' => break,
+                '$' if self.peek_next() == '{' => {
+                    let value = char_range_to_string(&self.characters, segment_start, self.current);
+                    self.add_token(Token::Interpolation(value));
+                    // Consume "${", the expression is scanned as ordinary tokens
+                    self.advance();
+                    self.advance();
+                    self.interpolations.push(0);
+                    return;
+                }
+                _ => {}
+            }
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
-        }
-        if self.is_empty() {
-            self.errors.push(ScannerError::UnterminatedString(Cause {
-                line: self.current_line,
-                source: char_range_to_string(&self.characters, self.start, self.current),
-            }));
         }
-        // Must be closing '"'
+        let value = char_range_to_string(&self.characters, segment_start, self.current);
+        // Consume closing '"'
         self.advance();
-        let value = char_range_to_string(&self.characters, self.start, self.current);
         self.add_token(Token::String(value));
     }
 
@@ -162,7 +194,7 @@ impl Scanner {
     }
 
     fn consume_identifier(&mut self) {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
         let value = char_range_to_string(&self.characters, self.start, self.current);
@@ -188,7 +220,7 @@ impl Scanner {
             }
             let c = self.advance();
             if c == '\n' {
-                self.current_line += 1;
+                self.newline();
             } else if c == '*' && self.peek() == '/' {
                 self.advance(); // Consume: '/'
                 if depth == 0 {
@@ -208,14 +240,40 @@ impl Scanner {
         match c {
             '(' => self.add_token(Token::LeftParen),
             ')' => self.add_token(Token::RightParen),
-            '{' => self.add_token(Token::LeftBrace),
-            '}' => self.add_token(Token::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(Token::LeftBrace)
+            }
+            '}' => match self.interpolations.last_mut() {
+                // Closes an interpolated expression, the string continues
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.consume_string();
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(Token::RightBrace)
+                }
+                None => self.add_token(Token::RightBrace),
+            },
+            '[' => self.add_token(Token::LeftBracket),
+            ']' => self.add_token(Token::RightBracket),
             ',' => self.add_token(Token::Comma),
-            '.' => self.add_token(Token::Dot),
+            '.' => {
+                if self.next(&'.') {
+                    self.add_token(Token::DotDot)
+                } else {
+                    self.add_token(Token::Dot)
+                }
+            }
             '-' => self.add_token(Token::Minus),
             '+' => self.add_token(Token::Plus),
             ';' => self.add_token(Token::Semicolon),
             '*' => self.add_token(Token::Star),
+            '?' => self.add_token(Token::Question),
+            ':' => self.add_token(Token::Colon),
             // 2 chars
             '!' => {
                 if self.next(&'=') {
@@ -259,14 +317,14 @@ impl Scanner {
                     self.add_token(Token::Slash);
                 }
             }
-            '\n' => self.current_line += 1,
+            '\n' => self.newline(),
             '"' => {
                 self.consume_string();
             }
             _ => {
                 if c.is_numeric() {
                     self.consume_number();
-                } else if c.is_alphabetic() {
+                } else if c.is_alphabetic() || c == '_' {
                     self.consume_identifier();
                 } else if c.is_whitespace() {
                 } else {
@@ -283,6 +341,12 @@ impl Scanner {
             self.start = self.current;
             self.scan_token();
         }
+        if !self.interpolations.is_empty() {
+            self.errors.push(ScannerError::UnterminatedString(Cause {
+                line: self.current_line,
+                source: char_range_to_string(&self.characters, self.start, self.current),
+            }));
+        }
         self.add_token(Token::Eof);
         if !self.errors.is_empty() {
             return Err(self.errors.clone());
@@ -343,6 +407,69 @@ mod tests {
                     Token::Eof,
                 ],
             },
+            ScanTokensTestCase {
+                input: "print \"hi there\" + snake_case;",
+                expected: vec![
+                    Token::Print,
+                    Token::String(s("hi there")),
+                    Token::Plus,
+                    Token::Identifier(s("snake_case")),
+                    Token::Semicolon,
+                    Token::Eof,
+                ],
+            },
+            ScanTokensTestCase {
+                input: "0..n.len",
+                expected: vec![
+                    Token::Number(0.0),
+                    Token::DotDot,
+                    Token::Identifier(s("n")),
+                    Token::Dot,
+                    Token::Identifier(s("len")),
+                    Token::Eof,
+                ],
+            },
+            ScanTokensTestCase {
+                input: "xs[0]",
+                expected: vec![
+                    Token::Identifier(s("xs")),
+                    Token::LeftBracket,
+                    Token::Number(0.0),
+                    Token::RightBracket,
+                    Token::Eof,
+                ],
+            },
+            ScanTokensTestCase {
+                input: "a ? b : c, d",
+                expected: vec![
+                    Token::Identifier(s("a")),
+                    Token::Question,
+                    Token::Identifier(s("b")),
+                    Token::Colon,
+                    Token::Identifier(s("c")),
+                    Token::Comma,
+                    Token::Identifier(s("d")),
+                    Token::Eof,
+                ],
+            },
+            ScanTokensTestCase {
+                input: "\"a ${b + {}.len} c ${\"${d}\"}\"",
+                expected: vec![
+                    Token::Interpolation(s("a ")),
+                    Token::Identifier(s("b")),
+                    Token::Plus,
+                    Token::LeftBrace,
+                    Token::RightBrace,
+                    Token::Dot,
+                    Token::Identifier(s("len")),
+                    Token::Interpolation(s(" c ")),
+                    Token::Interpolation(s("")),
+                    Token::Identifier(s("d")),
+                    Token::String(s("")),
+                    Token::String(s("")),
+                    Token::Eof,
+                ],
+            },
             ScanTokensTestCase {
                 input: "// fun comment = hello",
                 expected: vec![Token::Eof],
@@ -360,7 +487,7 @@ mod tests {
             let mut scanner = Scanner::new(tc.input.to_string());
             let output = &scanner
                 .scan_tokens()
-                .expect(&format!("Code had errors: {}", tc.input.to_owned()));
+                .unwrap_or_else(|_| panic!("Code had errors: {}", tc.input));
             let actual = join_vec_debug(
                 &output
                     .iter()
@@ -374,10 +501,24 @@ mod tests {
 
     #[test]
     fn test_scanner_errors() {
-        let test_table: Vec<ScannerErrorTestCase> = vec![ScannerErrorTestCase {
-            input: "/* afdsafdf ",
-            expected: vec![ScannerError::UnclosedBlockComment(Default::default())],
-        }];
+        let test_table: Vec<ScannerErrorTestCase> = vec![
+            ScannerErrorTestCase {
+                input: "/* afdsafdf ",
+                expected: vec![ScannerError::UnclosedBlockComment(Default::default())],
+            },
+            ScannerErrorTestCase {
+                input: "\"no closing quote",
+                expected: vec![ScannerError::UnterminatedString(Default::default())],
+            },
+            ScannerErrorTestCase {
+                input: "\"unclosed ${a",
+                expected: vec![ScannerError::UnterminatedString(Default::default())],
+            },
+            ScannerErrorTestCase {
+                input: "\"unclosed ${a} tail",
+                expected: vec![ScannerError::UnterminatedString(Default::default())],
+            },
+        ];
         for tc in test_table {
             let mut scanner = Scanner::new(tc.input.to_owned());
             let errors = scanner
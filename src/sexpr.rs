@@ -0,0 +1,456 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::ast::{Expr, ExprKind, Operator, Primitive};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SexprError {
+    // Byte offset into the input
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SexprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[offset {}] Error: {}", self.offset, self.message)
+    }
+}
+
+type SexprResult<T> = Result<T, SexprError>;
+
+/// Reads the S-expression form printed by `impl Display for Expr` back into
+/// an expression, so fixtures can be written as `(* (- 123) (group 45.67))`.
+pub fn parse(text: &str) -> SexprResult<Expr> {
+    let mut reader = Reader {
+        chars: text.char_indices().peekable(),
+        len: text.len(),
+    };
+    let expr = reader.expr()?;
+    match reader.next_token()? {
+        None => Ok(expr),
+        Some((offset, _)) => Err(error(offset, "Expect end of input.")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    Str(String),
+    Atom(String),
+}
+
+struct Reader<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn expr(&mut self) -> SexprResult<Expr> {
+        match self.next_token()? {
+            Some((_, Token::LeftParen)) => self.list(),
+            Some((_, Token::Str(s))) => Ok(ExprKind::Literal(Primitive::String(s)).into()),
+            Some((offset, Token::Atom(atom))) => atom_expr(offset, atom),
+            Some((offset, Token::RightParen)) => Err(error(offset, "Expect expression.")),
+            None => Err(error(self.len, "Expect expression.")),
+        }
+    }
+
+    // Called after the opening paren
+    fn list(&mut self) -> SexprResult<Expr> {
+        let (offset, head) = match self.next_token()? {
+            Some((offset, Token::Atom(head))) => (offset, head),
+            Some((offset, _)) => return Err(error(offset, "Expect operator after '('.")),
+            None => return Err(error(self.len, "Expect operator after '('.")),
+        };
+        let mut operands = vec![];
+        let close = loop {
+            match self.chars.peek() {
+                Some(&(_, c)) if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                Some(&(close, ')')) => {
+                    self.chars.next();
+                    break close;
+                }
+                Some(_) => operands.push(self.expr()?),
+                None => return Err(error(self.len, "Expect ')' after operands.")),
+            }
+        };
+        let arity_error = || error(close, &format!("Wrong number of operands for '{}'.", head));
+        let kind = match (head.as_str(), operands.len()) {
+            ("=", 2) => {
+                let value = operands.pop().expect("Checked operand count");
+                match operands.pop().map(|e| e.kind) {
+                    Some(ExprKind::Variable(name)) => ExprKind::Assign(name, Box::new(value)),
+                    _ => return Err(error(offset, "Invalid assignment target.")),
+                }
+            }
+            ("call", n) if n > 0 => {
+                let callee = operands.remove(0);
+                ExprKind::Call(Box::new(callee), operands)
+            }
+            (",", 2) => {
+                let (a, b) = pair(operands);
+                ExprKind::Comma(a, b)
+            }
+            ("?", 3) => {
+                let else_expr = operands.pop().expect("Checked operand count");
+                let (condition, then_expr) = pair(operands);
+                ExprKind::Conditional(condition, then_expr, Box::new(else_expr))
+            }
+            ("group", 1) => ExprKind::Grouping(Box::new(operands.remove(0))),
+            ("interpolate", _) => ExprKind::Interpolation(operands),
+            ("list", _) => ExprKind::List(operands),
+            ("map", n) if n % 2 == 0 => {
+                let mut operands = operands.into_iter();
+                let mut entries = vec![];
+                while let (Some(key), Some(value)) = (operands.next(), operands.next()) {
+                    entries.push((key, value));
+                }
+                ExprKind::Map(entries)
+            }
+            ("index", 2) => {
+                let (a, b) = pair(operands);
+                ExprKind::Index(a, b)
+            }
+            ("index=", 3) => {
+                let value = operands.pop().expect("Checked operand count");
+                let (object, index) = pair(operands);
+                ExprKind::SetIndex(object, index, Box::new(value))
+            }
+            (".=", 3) => {
+                let value = operands.pop().expect("Checked operand count");
+                match operands.pop().map(|e| e.kind) {
+                    Some(ExprKind::Variable(name)) => {
+                        ExprKind::Set(Box::new(operands.remove(0)), name, Box::new(value))
+                    }
+                    _ => return Err(error(offset, "Expect property name.")),
+                }
+            }
+            ("super", 1) => match operands.pop().map(|e| e.kind) {
+                Some(ExprKind::Variable(method)) => ExprKind::Super(method),
+                _ => return Err(error(offset, "Expect method name.")),
+            },
+            (".", 2) => match operands.pop().map(|e| e.kind) {
+                Some(ExprKind::Variable(name)) => ExprKind::Get(Box::new(operands.remove(0)), name),
+                _ => return Err(error(offset, "Expect property name.")),
+            },
+            (op @ "and", 2) | (op @ "or", 2) => {
+                let (a, b) = pair(operands);
+                let operator = if op == "and" {
+                    Operator::And
+                } else {
+                    Operator::Or
+                };
+                ExprKind::Logical(operator, a, b)
+            }
+            ("-", 1) => ExprKind::Unary(Operator::Minus, Box::new(operands.remove(0))),
+            ("!", 1) => ExprKind::Unary(Operator::Bang, Box::new(operands.remove(0))),
+            (op, 2) => match binary_operator(op) {
+                Some(operator) => {
+                    let (a, b) = pair(operands);
+                    ExprKind::Binary(operator, a, b)
+                }
+                None => return Err(error(offset, &format!("Unknown operator '{}'.", op))),
+            },
+            (op, _) if is_operator(op) => return Err(arity_error()),
+            (op, _) => return Err(error(offset, &format!("Unknown operator '{}'.", op))),
+        };
+        Ok(kind.into())
+    }
+
+    fn next_token(&mut self) -> SexprResult<Option<(usize, Token)>> {
+        while let Some(&(offset, c)) = self.chars.peek() {
+            let token = match c {
+                c if c.is_whitespace() => {
+                    self.chars.next();
+                    continue;
+                }
+                '(' => {
+                    self.chars.next();
+                    Token::LeftParen
+                }
+                ')' => {
+                    self.chars.next();
+                    Token::RightParen
+                }
+                '"' => {
+                    self.chars.next();
+                    Token::Str(self.string(offset)?)
+                }
+                _ => {
+                    let mut atom = String::new();
+                    while let Some(&(_, c)) = self.chars.peek() {
+                        if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                            break;
+                        }
+                        atom.push(c);
+                        self.chars.next();
+                    }
+                    Token::Atom(atom)
+                }
+            };
+            return Ok(Some((offset, token)));
+        }
+        Ok(None)
+    }
+
+    // Called after the opening quote
+    fn string(&mut self, start: usize) -> SexprResult<String> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, escaped)) => s.push(escaped),
+                    None => break,
+                },
+                Some((_, c)) => s.push(c),
+                None => break,
+            }
+        }
+        Err(error(start, "Unterminated string."))
+    }
+}
+
+fn atom_expr(offset: usize, atom: String) -> SexprResult<Expr> {
+    let kind = match atom.as_str() {
+        "true" => ExprKind::Literal(Primitive::Boolean(true)),
+        "false" => ExprKind::Literal(Primitive::Boolean(false)),
+        "nil" => ExprKind::Literal(Primitive::Nil),
+        "this" => ExprKind::This,
+        _ if atom.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+            match atom.parse() {
+                Ok(n) => ExprKind::Literal(Primitive::Number(n)),
+                Err(_) => return Err(error(offset, &format!("Invalid number '{}'.", atom))),
+            }
+        }
+        _ if atom.starts_with(|c: char| c.is_alphabetic() || c == '_') => ExprKind::Variable(atom),
+        _ => return Err(error(offset, &format!("Unexpected '{}'.", atom))),
+    };
+    Ok(kind.into())
+}
+
+fn binary_operator(op: &str) -> Option<Operator> {
+    let operator = match op {
+        "+" => Operator::Plus,
+        "-" => Operator::Minus,
+        "*" => Operator::Star,
+        "/" => Operator::Divide,
+        "!=" => Operator::BangEqual,
+        "==" => Operator::EqualEqual,
+        ">" => Operator::Greater,
+        ">=" => Operator::GreaterEqual,
+        "<" => Operator::Less,
+        "<=" => Operator::LessEqual,
+        ".." => Operator::Range,
+        _ => return None,
+    };
+    Some(operator)
+}
+
+fn is_operator(op: &str) -> bool {
+    binary_operator(op).is_some()
+        || [
+            "=", "call", ",", "?", "group", "map", "index", "index=", ".", ".=", "super", "and",
+            "or", "!",
+        ]
+        .contains(&op)
+}
+
+fn pair(mut operands: Vec<Expr>) -> (Box<Expr>, Box<Expr>) {
+    let b = operands.pop().expect("Checked operand count");
+    let a = operands.pop().expect("Checked operand count");
+    (Box::new(a), Box::new(b))
+}
+
+fn error(offset: usize, message: &str) -> SexprError {
+    SexprError {
+        offset,
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small deterministic generator, enough to explore every node shape
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+
+        fn expr(&mut self, depth: u32) -> Expr {
+            let leaf = depth == 0 || self.next(4) == 0;
+            let choice = if leaf {
+                self.next(5)
+            } else {
+                5 + self.next(15)
+            };
+            let operators = [
+                Operator::Plus,
+                Operator::Minus,
+                Operator::Star,
+                Operator::Divide,
+                Operator::BangEqual,
+                Operator::EqualEqual,
+                Operator::Greater,
+                Operator::GreaterEqual,
+                Operator::Less,
+                Operator::LessEqual,
+                Operator::Range,
+            ];
+            let kind = match choice {
+                0 => ExprKind::Literal(Primitive::Number(self.next(1000) as f64 / 8.0 - 50.0)),
+                1 => ExprKind::Literal(Primitive::String(
+                    ["", "a b", "quote \" and \\ slash", "(paren)"][self.next(4) as usize]
+                        .to_owned(),
+                )),
+                2 => ExprKind::Literal(Primitive::Boolean(self.next(2) == 0)),
+                3 => ExprKind::Literal(Primitive::Nil),
+                4 => match ["a", "call", "group", "_x1", "this", "super"][self.next(6) as usize] {
+                    "this" => ExprKind::This,
+                    "super" => ExprKind::Super("init".to_owned()),
+                    name => ExprKind::Variable(name.to_owned()),
+                },
+                5 => ExprKind::Assign("v".to_owned(), Box::new(self.expr(depth - 1))),
+                6 => ExprKind::Binary(
+                    operators[self.next(operators.len() as u64) as usize].clone(),
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                7 => {
+                    let args = (0..self.next(3)).map(|_| self.expr(depth - 1)).collect();
+                    ExprKind::Call(Box::new(self.expr(depth - 1)), args)
+                }
+                8 => ExprKind::Grouping(Box::new(self.expr(depth - 1))),
+                9 => ExprKind::Logical(
+                    if self.next(2) == 0 {
+                        Operator::And
+                    } else {
+                        Operator::Or
+                    },
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                11 => ExprKind::Comma(
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                12 => ExprKind::Conditional(
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                13 => ExprKind::List((0..self.next(3)).map(|_| self.expr(depth - 1)).collect()),
+                14 => ExprKind::Index(
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                15 => ExprKind::SetIndex(
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                16 => ExprKind::Get(Box::new(self.expr(depth - 1)), "len".to_owned()),
+                17 => ExprKind::Map(
+                    (0..self.next(3))
+                        .map(|_| (self.expr(depth - 1), self.expr(depth - 1)))
+                        .collect(),
+                ),
+                18 => ExprKind::Set(
+                    Box::new(self.expr(depth - 1)),
+                    "len".to_owned(),
+                    Box::new(self.expr(depth - 1)),
+                ),
+                19 => ExprKind::Interpolation(
+                    (0..1 + self.next(3))
+                        .map(|_| self.expr(depth - 1))
+                        .collect(),
+                ),
+                _ => ExprKind::Unary(
+                    if self.next(2) == 0 {
+                        Operator::Minus
+                    } else {
+                        Operator::Bang
+                    },
+                    Box::new(self.expr(depth - 1)),
+                ),
+            };
+            kind.into()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let number = |n| Box::new(Expr::from(ExprKind::Literal(Primitive::Number(n))));
+        assert_eq!(
+            parse("(* (- 123) (group 45.67))"),
+            Ok(ExprKind::Binary(
+                Operator::Star,
+                Box::new(ExprKind::Unary(Operator::Minus, number(123.0)).into()),
+                Box::new(ExprKind::Grouping(number(45.67)).into()),
+            )
+            .into())
+        );
+        assert_eq!(
+            parse("(call f)"),
+            Ok(ExprKind::Call(Box::new(ExprKind::Variable("f".to_owned()).into()), vec![]).into())
+        );
+        assert_eq!(
+            parse("  (- 1 -2)\n"),
+            Ok(ExprKind::Binary(Operator::Minus, number(1.0), number(-2.0)).into())
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut gen = Gen(0x5eed);
+        for _ in 0..500 {
+            let expr = gen.expr(4);
+            let printed = format!("{}", expr);
+            assert_eq!(parse(&printed), Ok(expr), "{}", printed);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let test_table = vec![
+            ("", 0, "Expect expression."),
+            ("(+ 1 2", 6, "Expect ')' after operands."),
+            ("(+ 1 2 3)", 8, "Wrong number of operands for '+'."),
+            ("(call)", 5, "Wrong number of operands for 'call'."),
+            ("(% 1 2)", 1, "Unknown operator '%'."),
+            ("(= 1 2)", 1, "Invalid assignment target."),
+            ("(. xs 1)", 1, "Expect property name."),
+            ("(super 1)", 1, "Expect method name."),
+            ("(index xs)", 9, "Wrong number of operands for 'index'."),
+            ("(map a)", 6, "Wrong number of operands for 'map'."),
+            ("(1 2)", 1, "Unknown operator '1'."),
+            ("((f) 2)", 1, "Expect operator after '('."),
+            ("\"open", 0, "Unterminated string."),
+            ("1 2", 2, "Expect end of input."),
+            ("1.2.3", 0, "Invalid number '1.2.3'."),
+        ];
+        for (input, offset, message) in test_table {
+            assert_eq!(
+                parse(input),
+                Err(SexprError {
+                    offset,
+                    message: message.to_owned()
+                }),
+                "{}",
+                input
+            );
+        }
+    }
+}
@@ -7,9 +7,13 @@ lazy_static! {
     pub static ref KEYWORDS: HashMap<String, Token> = {
         let mut map = HashMap::new();
         map.insert("and".to_owned(), Token::And);
+        map.insert("break".to_owned(), Token::Break);
+        map.insert("catch".to_owned(), Token::Catch);
         map.insert("class".to_owned(), Token::Class);
+        map.insert("continue".to_owned(), Token::Continue);
         map.insert("else".to_owned(), Token::Else);
         map.insert("false".to_owned(), Token::False);
+        map.insert("finally".to_owned(), Token::Finally);
         map.insert("for".to_owned(), Token::For);
         map.insert("fun".to_owned(), Token::Fun);
         map.insert("if".to_owned(), Token::If);
@@ -19,17 +23,21 @@ lazy_static! {
         map.insert("return".to_owned(), Token::Return);
         map.insert("super".to_owned(), Token::Super);
         map.insert("this".to_owned(), Token::This);
+        map.insert("throw".to_owned(), Token::Throw);
         map.insert("true".to_owned(), Token::True);
+        map.insert("try".to_owned(), Token::Try);
         map.insert("var".to_owned(), Token::Var);
         map.insert("while".to_owned(), Token::While);
         map
     };
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LocationInfo {
     // Which line the token was seen
     pub line: usize,
+    // Counted in characters from 1, at the start of the token
+    pub column: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -51,13 +59,18 @@ pub enum Token {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    DotDot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Question,
+    Colon,
 
     // One or two character tokens.
     Bang,
@@ -71,12 +84,20 @@ pub enum Token {
     // Literals
     Identifier(String),
     String(String),
+    // A string segment that is followed by an embedded `${...}` expression.
+    // The expression's tokens come next, then either another segment or the
+    // closing `String` segment.
+    Interpolation(String),
     Number(f64),
     // Keywords
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
@@ -86,7 +107,9 @@ pub enum Token {
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
     // EOF
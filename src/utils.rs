@@ -1,17 +1,85 @@
-use std::fmt::Debug;
-
-pub fn char_range_to_string(vec: &Vec<char>, start: usize, end: usize) -> String {
+pub fn char_range_to_string(vec: &[char], start: usize, end: usize) -> String {
     vec[start..end].iter().cloned().collect::<String>()
 }
 
-pub fn join_vec_debug<T: Debug>(vec: &Vec<T>) -> String {
-    let mut output = String::new();
-    for item in vec.iter() {
-        output.push_str(&format!("{:?}\n", item));
+#[cfg(test)]
+pub use self::testing::*;
+
+// Helpers for the test modules
+#[cfg(test)]
+mod testing {
+    use std::cell::RefCell;
+    use std::fmt::Debug;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use crate::ast::StmtWrapper;
+    use crate::gc::GcConfig;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::vm::{InterpretError, Vm};
+
+    pub fn join_vec_debug<T: Debug>(vec: &[T]) -> String {
+        let mut output = String::new();
+        for item in vec.iter() {
+            output.push_str(&format!("{:?}\n", item));
+        }
+        output
+    }
+
+    pub fn s(_s: &'static str) -> String {
+        _s.to_owned()
+    }
+
+    // Scans and parses source the test expects to be valid
+    pub fn parse(source: &str) -> Vec<StmtWrapper> {
+        let tokens = Scanner::new(source.to_owned())
+            .scan_tokens()
+            .expect("Source had scanner errors");
+        Parser::new(tokens)
+            .parse()
+            .expect("Source had parse errors")
+    }
+
+    pub fn interpret(vm: &mut Vm, source: &str) -> Result<(), InterpretError> {
+        vm.interpret(&parse(source))
     }
-    return output;
-}
 
-pub fn s(_s: &'static str) -> String {
-    _s.to_owned()
+    // A global formatted the way print shows it
+    pub fn global(vm: &Vm, name: &str) -> String {
+        vm.stringify(vm.get_global(name).expect("Global not defined"))
+    }
+
+    // Collects before every allocation, to catch values the VM forgot to root
+    pub fn gc_stress() -> GcConfig {
+        GcConfig {
+            stress: true,
+            ..Default::default()
+        }
+    }
+
+    // The configurations a test of heap-allocating code should pass under
+    pub fn gc_configs() -> [GcConfig; 2] {
+        [GcConfig::default(), gc_stress()]
+    }
+
+    // Writer whose contents can still be read after handing a clone to the VM
+    #[derive(Clone, Default)]
+    pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuffer {
+        pub fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.borrow()).into_owned()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,298 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::gc::ObjRef;
+
+/// Unpacked view of a `Value`, for matching on what it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ValueKind {
+    #[default]
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+/// A runtime value. Anything that does not fit in a word lives on the
+/// managed heap and is referred to through an `ObjRef`.
+///
+/// The representation is private so the `nan-boxing` feature can swap the
+/// tagged enum for a single NaN-boxed word without changing this API.
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Value(ValueKind);
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub fn kind(self) -> ValueKind {
+        self.0
+    }
+
+    fn from_kind(kind: ValueKind) -> Value {
+        Value(kind)
+    }
+}
+
+// Numbers are stored as their own bits. Every other value is hidden in the
+// payload of a quiet NaN: objects set the sign bit and keep their heap index
+// in the low bits, singletons use small tags.
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const NIL: u64 = QNAN | 1;
+#[cfg(feature = "nan-boxing")]
+const FALSE: u64 = QNAN | 2;
+#[cfg(feature = "nan-boxing")]
+const TRUE: u64 = QNAN | 3;
+
+#[cfg(feature = "nan-boxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    pub fn kind(self) -> ValueKind {
+        if self.0 & QNAN != QNAN {
+            ValueKind::Number(f64::from_bits(self.0))
+        } else if self.0 & SIGN_BIT == SIGN_BIT {
+            ValueKind::Obj(ObjRef::from_index((self.0 & !(SIGN_BIT | QNAN)) as usize))
+        } else {
+            match self.0 {
+                NIL => ValueKind::Nil,
+                FALSE => ValueKind::Bool(false),
+                TRUE => ValueKind::Bool(true),
+                bits => unreachable!("Malformed NaN-boxed value {:#x}", bits),
+            }
+        }
+    }
+
+    fn from_kind(kind: ValueKind) -> Value {
+        match kind {
+            ValueKind::Nil => Value(NIL),
+            ValueKind::Bool(false) => Value(FALSE),
+            ValueKind::Bool(true) => Value(TRUE),
+            // Collapse every NaN to the canonical one so none collide with a tag
+            ValueKind::Number(n) if n.is_nan() => Value(f64::NAN.to_bits()),
+            ValueKind::Number(n) => Value(n.to_bits()),
+            ValueKind::Obj(r) => Value(SIGN_BIT | QNAN | r.index() as u64),
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        // Compare unpacked so NaN is not equal to itself
+        self.kind() == other.kind()
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl Default for Value {
+    fn default() -> Self {
+        Value(NIL)
+    }
+}
+
+impl Value {
+    pub fn nil() -> Value {
+        Value::from_kind(ValueKind::Nil)
+    }
+
+    pub fn is_nil(self) -> bool {
+        self.kind() == ValueKind::Nil
+    }
+
+    pub fn as_bool(self) -> Option<bool> {
+        match self.kind() {
+            ValueKind::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(self) -> Option<f64> {
+        match self.kind() {
+            ValueKind::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_obj(self) -> Option<ObjRef> {
+        match self.kind() {
+            ValueKind::Obj(r) => Some(r),
+            _ => None,
+        }
+    }
+
+    pub fn is_falsey(self) -> bool {
+        match self.kind() {
+            ValueKind::Nil => true,
+            ValueKind::Bool(b) => !b,
+            _ => false,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::from_kind(ValueKind::Bool(b))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::from_kind(ValueKind::Number(n))
+    }
+}
+
+impl From<ObjRef> for Value {
+    fn from(r: ObjRef) -> Value {
+        Value::from_kind(ValueKind::Obj(r))
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.kind())
+    }
+}
+
+/// A value used as a map key. Unlike `Value` equality, every NaN is the same
+/// key, so a NaN key can be found again. Zero and negative zero are equal as
+/// numbers and so are one key. Objects compare by reference, which for
+/// interned strings means by contents.
+#[derive(Debug, Clone, Copy)]
+pub struct HashKey(Value);
+
+impl HashKey {
+    pub fn new(value: Value) -> HashKey {
+        HashKey(value)
+    }
+
+    pub fn value(self) -> Value {
+        self.0
+    }
+}
+
+impl PartialEq for HashKey {
+    fn eq(&self, other: &HashKey) -> bool {
+        match (self.0.kind(), other.0.kind()) {
+            (ValueKind::Number(a), ValueKind::Number(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl Eq for HashKey {}
+
+impl Hash for HashKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0.kind() {
+            ValueKind::Nil => 0u8.hash(state),
+            ValueKind::Bool(b) => {
+                1u8.hash(state);
+                b.hash(state);
+            }
+            ValueKind::Number(n) => {
+                2u8.hash(state);
+                // Equal keys must hash alike
+                let bits = if n.is_nan() {
+                    f64::NAN.to_bits()
+                } else if n == 0.0 {
+                    0
+                } else {
+                    n.to_bits()
+                };
+                bits.hash(state);
+            }
+            ValueKind::Obj(r) => {
+                3u8.hash(state);
+                r.hash(state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Run with and without `--features nan-boxing`
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let numbers = vec![
+            0.0,
+            -0.0,
+            1.5,
+            -123.25,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::INFINITY,
+        ];
+        for n in numbers {
+            assert_eq!(Value::from(n).kind(), ValueKind::Number(n));
+        }
+        assert_eq!(Value::nil().kind(), ValueKind::Nil);
+        assert_eq!(Value::default().kind(), ValueKind::Nil);
+        assert_eq!(Value::from(true).kind(), ValueKind::Bool(true));
+        assert_eq!(Value::from(false).kind(), ValueKind::Bool(false));
+        let r = ObjRef::from_index(0xdead_beef);
+        assert_eq!(Value::from(r).kind(), ValueKind::Obj(r));
+        assert_eq!(Value::from(r).as_obj(), Some(r));
+    }
+
+    #[test]
+    fn test_nan() {
+        let nan = Value::from(f64::NAN);
+        assert!(nan.as_number().expect("NaN is a number").is_nan());
+        assert_ne!(nan, nan);
+        // A NaN whose payload looks like a tag is still a number
+        let tagged_nan = f64::from_bits(0x7ffc_0000_0000_0001);
+        assert!(Value::from(tagged_nan).as_number().is_some());
+    }
+
+    #[test]
+    fn test_equality_and_truthiness() {
+        assert_eq!(Value::from(1.0), Value::from(1.0));
+        assert_ne!(Value::from(1.0), Value::from(true));
+        assert_ne!(Value::nil(), Value::from(false));
+        assert_ne!(Value::from(0.0), Value::nil());
+        assert!(Value::nil().is_falsey());
+        assert!(Value::from(false).is_falsey());
+        assert!(!Value::from(0.0).is_falsey());
+        assert!(!Value::from(ObjRef::from_index(0)).is_falsey());
+    }
+
+    #[test]
+    fn test_hash_keys() {
+        use std::collections::HashSet;
+
+        let keys: HashSet<HashKey> = vec![
+            Value::nil(),
+            Value::from(false),
+            Value::from(0.0),
+            Value::from(-0.0),
+            Value::from(f64::NAN),
+            Value::from(-f64::NAN),
+            Value::from(1.0),
+            Value::from(ObjRef::from_index(1)),
+            Value::from(ObjRef::from_index(1)),
+        ]
+        .into_iter()
+        .map(HashKey::new)
+        .collect();
+        assert_eq!(keys.len(), 6);
+        assert!(keys.contains(&HashKey::new(Value::from(f64::NAN))));
+        assert!(!keys.contains(&HashKey::new(Value::from(true))));
+        // Only keys treat NaN as equal to itself
+        assert_ne!(Value::from(f64::NAN), Value::from(f64::NAN));
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    #[test]
+    fn test_nan_boxed_size() {
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+}
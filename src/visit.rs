@@ -0,0 +1,723 @@
+use crate::ast::{
+    ClassDecl, Expr, ExprKind, FunctionDecl, Operator, Primitive, Stmt, StmtWrapper, TryStmt,
+};
+use crate::token::LocationInfo;
+
+/// Read-only traversal of the AST. Every node has a method whose default
+/// visits the node's children and merges their results with `combine`, so a
+/// pass only overrides the nodes it cares about. Leaves produce
+/// `Output::default()`.
+pub trait Visitor: Sized {
+    type Output: Default;
+
+    // Merges the results of sibling nodes, by default keeping the last one
+    fn combine(&mut self, _first: Self::Output, second: Self::Output) -> Self::Output {
+        second
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
+        walk_expr(self, expr)
+    }
+
+    fn visit_assign(&mut self, _name: &str, value: &Expr) -> Self::Output {
+        self.visit_expr(value)
+    }
+
+    fn visit_binary(&mut self, _operator: &Operator, left: &Expr, right: &Expr) -> Self::Output {
+        let left = self.visit_expr(left);
+        let right = self.visit_expr(right);
+        self.combine(left, right)
+    }
+
+    fn visit_call(&mut self, callee: &Expr, args: &[Expr]) -> Self::Output {
+        let mut output = self.visit_expr(callee);
+        for arg in args.iter() {
+            let arg = self.visit_expr(arg);
+            output = self.combine(output, arg);
+        }
+        output
+    }
+
+    fn visit_comma(&mut self, left: &Expr, right: &Expr) -> Self::Output {
+        let left = self.visit_expr(left);
+        let right = self.visit_expr(right);
+        self.combine(left, right)
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        then_expr: &Expr,
+        else_expr: &Expr,
+    ) -> Self::Output {
+        let condition = self.visit_expr(condition);
+        let then_expr = self.visit_expr(then_expr);
+        let output = self.combine(condition, then_expr);
+        let else_expr = self.visit_expr(else_expr);
+        self.combine(output, else_expr)
+    }
+
+    fn visit_get(&mut self, object: &Expr, _name: &str) -> Self::Output {
+        self.visit_expr(object)
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> Self::Output {
+        self.visit_expr(expr)
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr) -> Self::Output {
+        let object = self.visit_expr(object);
+        let index = self.visit_expr(index);
+        self.combine(object, index)
+    }
+
+    fn visit_interpolation(&mut self, parts: &[Expr]) -> Self::Output {
+        let mut output = Self::Output::default();
+        for part in parts.iter() {
+            let part = self.visit_expr(part);
+            output = self.combine(output, part);
+        }
+        output
+    }
+
+    fn visit_lambda(&mut self, _params: &[String], body: &[StmtWrapper]) -> Self::Output {
+        self.visit_block(body)
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> Self::Output {
+        let mut output = Self::Output::default();
+        for element in elements.iter() {
+            let element = self.visit_expr(element);
+            output = self.combine(output, element);
+        }
+        output
+    }
+
+    fn visit_literal(&mut self, _value: &Primitive) -> Self::Output {
+        Self::Output::default()
+    }
+
+    fn visit_logical(&mut self, _operator: &Operator, left: &Expr, right: &Expr) -> Self::Output {
+        let left = self.visit_expr(left);
+        let right = self.visit_expr(right);
+        self.combine(left, right)
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> Self::Output {
+        let mut output = Self::Output::default();
+        for (key, value) in entries.iter() {
+            let key = self.visit_expr(key);
+            output = self.combine(output, key);
+            let value = self.visit_expr(value);
+            output = self.combine(output, value);
+        }
+        output
+    }
+
+    fn visit_set(&mut self, object: &Expr, _name: &str, value: &Expr) -> Self::Output {
+        let object = self.visit_expr(object);
+        let value = self.visit_expr(value);
+        self.combine(object, value)
+    }
+
+    fn visit_set_index(&mut self, object: &Expr, index: &Expr, value: &Expr) -> Self::Output {
+        let object = self.visit_expr(object);
+        let index = self.visit_expr(index);
+        let output = self.combine(object, index);
+        let value = self.visit_expr(value);
+        self.combine(output, value)
+    }
+
+    fn visit_super(&mut self, _method: &str) -> Self::Output {
+        Self::Output::default()
+    }
+
+    fn visit_this(&mut self) -> Self::Output {
+        Self::Output::default()
+    }
+
+    fn visit_unary(&mut self, _operator: &Operator, right: &Expr) -> Self::Output {
+        self.visit_expr(right)
+    }
+
+    fn visit_variable(&mut self, _name: &str) -> Self::Output {
+        Self::Output::default()
+    }
+
+    fn visit_stmt(&mut self, stmt: &StmtWrapper) -> Self::Output {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_block(&mut self, statements: &[StmtWrapper]) -> Self::Output {
+        let mut output = Self::Output::default();
+        for stmt in statements.iter() {
+            let stmt = self.visit_stmt(stmt);
+            output = self.combine(output, stmt);
+        }
+        output
+    }
+
+    fn visit_break(&mut self) -> Self::Output {
+        Self::Output::default()
+    }
+
+    fn visit_class(&mut self, decl: &ClassDecl) -> Self::Output {
+        let mut output = Self::Output::default();
+        for method in decl.methods.iter() {
+            let method = self.visit_function(method);
+            output = self.combine(output, method);
+        }
+        output
+    }
+
+    fn visit_continue(&mut self) -> Self::Output {
+        Self::Output::default()
+    }
+
+    fn visit_expression_stmt(&mut self, expr: &Expr) -> Self::Output {
+        self.visit_expr(expr)
+    }
+
+    fn visit_for_in(&mut self, _name: &str, iterable: &Expr, body: &StmtWrapper) -> Self::Output {
+        let iterable = self.visit_expr(iterable);
+        let body = self.visit_stmt(body);
+        self.combine(iterable, body)
+    }
+
+    fn visit_function(&mut self, decl: &FunctionDecl) -> Self::Output {
+        self.visit_block(&decl.body)
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &StmtWrapper,
+        else_branch: Option<&StmtWrapper>,
+    ) -> Self::Output {
+        let condition = self.visit_expr(condition);
+        let then_branch = self.visit_stmt(then_branch);
+        let mut output = self.combine(condition, then_branch);
+        if let Some(else_branch) = else_branch {
+            let else_branch = self.visit_stmt(else_branch);
+            output = self.combine(output, else_branch);
+        }
+        output
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> Self::Output {
+        self.visit_expr(expr)
+    }
+
+    fn visit_return(&mut self, value: Option<&Expr>) -> Self::Output {
+        match value {
+            Some(value) => self.visit_expr(value),
+            None => Self::Output::default(),
+        }
+    }
+
+    fn visit_throw(&mut self, value: &Expr) -> Self::Output {
+        self.visit_expr(value)
+    }
+
+    fn visit_try(&mut self, stmt: &TryStmt) -> Self::Output {
+        let mut output = self.visit_block(&stmt.body);
+        if let Some((_, handler)) = &stmt.catch {
+            let handler = self.visit_block(handler);
+            output = self.combine(output, handler);
+        }
+        if let Some(finally) = &stmt.finally {
+            let finally = self.visit_block(finally);
+            output = self.combine(output, finally);
+        }
+        output
+    }
+
+    fn visit_var(&mut self, _name: &str, initializer: Option<&Expr>) -> Self::Output {
+        match initializer {
+            Some(initializer) => self.visit_expr(initializer),
+            None => Self::Output::default(),
+        }
+    }
+
+    fn visit_while(
+        &mut self,
+        condition: &Expr,
+        body: &StmtWrapper,
+        increment: Option<&Expr>,
+    ) -> Self::Output {
+        let condition = self.visit_expr(condition);
+        let body = self.visit_stmt(body);
+        let output = self.combine(condition, body);
+        match increment {
+            Some(increment) => {
+                let increment = self.visit_expr(increment);
+                self.combine(output, increment)
+            }
+            None => output,
+        }
+    }
+}
+
+/// Dispatches an expression to the visitor method for its node type.
+pub fn walk_expr<V: Visitor>(visitor: &mut V, expr: &Expr) -> V::Output {
+    match &expr.kind {
+        ExprKind::Assign(name, value) => visitor.visit_assign(name, value),
+        ExprKind::Binary(operator, left, right) => visitor.visit_binary(operator, left, right),
+        ExprKind::Call(callee, args) => visitor.visit_call(callee, args),
+        ExprKind::Comma(left, right) => visitor.visit_comma(left, right),
+        ExprKind::Conditional(condition, then_expr, else_expr) => {
+            visitor.visit_conditional(condition, then_expr, else_expr)
+        }
+        ExprKind::Get(object, name) => visitor.visit_get(object, name),
+        ExprKind::Grouping(expr) => visitor.visit_grouping(expr),
+        ExprKind::Index(object, index) => visitor.visit_index(object, index),
+        ExprKind::Interpolation(parts) => visitor.visit_interpolation(parts),
+        ExprKind::Lambda(params, body) => visitor.visit_lambda(params, body),
+        ExprKind::List(elements) => visitor.visit_list(elements),
+        ExprKind::Literal(value) => visitor.visit_literal(value),
+        ExprKind::Logical(operator, left, right) => visitor.visit_logical(operator, left, right),
+        ExprKind::Map(entries) => visitor.visit_map(entries),
+        ExprKind::Set(object, name, value) => visitor.visit_set(object, name, value),
+        ExprKind::SetIndex(object, index, value) => visitor.visit_set_index(object, index, value),
+        ExprKind::Super(method) => visitor.visit_super(method),
+        ExprKind::This => visitor.visit_this(),
+        ExprKind::Unary(operator, right) => visitor.visit_unary(operator, right),
+        ExprKind::Variable(name) => visitor.visit_variable(name),
+    }
+}
+
+/// Dispatches a statement to the visitor method for its node type.
+pub fn walk_stmt<V: Visitor>(visitor: &mut V, stmt: &StmtWrapper) -> V::Output {
+    match &stmt.stmt {
+        Stmt::Block(statements) => visitor.visit_block(statements),
+        Stmt::Break => visitor.visit_break(),
+        Stmt::Class(decl) => visitor.visit_class(decl),
+        Stmt::Continue => visitor.visit_continue(),
+        Stmt::Expression(expr) => visitor.visit_expression_stmt(expr),
+        Stmt::ForIn(name, iterable, body) => visitor.visit_for_in(name, iterable, body),
+        Stmt::Function(decl) => visitor.visit_function(decl),
+        Stmt::If(condition, then_branch, else_branch) => {
+            visitor.visit_if(condition, then_branch, else_branch.as_deref())
+        }
+        Stmt::Print(expr) => visitor.visit_print(expr),
+        Stmt::Return(value) => visitor.visit_return(value.as_ref()),
+        Stmt::Throw(value) => visitor.visit_throw(value),
+        Stmt::Try(stmt) => visitor.visit_try(stmt),
+        Stmt::Var(name, initializer) => visitor.visit_var(name, initializer.as_ref()),
+        Stmt::While(condition, body, increment) => {
+            visitor.visit_while(condition, body, increment.as_ref())
+        }
+    }
+}
+
+/// Rewriting traversal of the AST. Takes nodes by value and returns their
+/// replacement; the defaults rebuild each node from its folded children, so
+/// a pass only overrides the nodes it rewrites. Rebuilt nodes keep their
+/// location, and a node a pass replaces with one of its children takes the
+/// child's.
+pub trait Folder: Sized {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        walk_fold_expr(self, expr)
+    }
+
+    fn fold_assign(&mut self, location_info: LocationInfo, name: String, value: Expr) -> Expr {
+        let value = self.fold_expr(value);
+        Expr::new(location_info, ExprKind::Assign(name, Box::new(value)))
+    }
+
+    fn fold_binary(
+        &mut self,
+        location_info: LocationInfo,
+        operator: Operator,
+        left: Expr,
+        right: Expr,
+    ) -> Expr {
+        let left = self.fold_expr(left);
+        let right = self.fold_expr(right);
+        Expr::new(
+            location_info,
+            ExprKind::Binary(operator, Box::new(left), Box::new(right)),
+        )
+    }
+
+    fn fold_call(&mut self, location_info: LocationInfo, callee: Expr, args: Vec<Expr>) -> Expr {
+        let callee = self.fold_expr(callee);
+        let args = args.into_iter().map(|arg| self.fold_expr(arg)).collect();
+        Expr::new(location_info, ExprKind::Call(Box::new(callee), args))
+    }
+
+    fn fold_comma(&mut self, location_info: LocationInfo, left: Expr, right: Expr) -> Expr {
+        let left = self.fold_expr(left);
+        let right = self.fold_expr(right);
+        Expr::new(
+            location_info,
+            ExprKind::Comma(Box::new(left), Box::new(right)),
+        )
+    }
+
+    fn fold_conditional(
+        &mut self,
+        location_info: LocationInfo,
+        condition: Expr,
+        then_expr: Expr,
+        else_expr: Expr,
+    ) -> Expr {
+        let condition = self.fold_expr(condition);
+        let then_expr = self.fold_expr(then_expr);
+        let else_expr = self.fold_expr(else_expr);
+        Expr::new(
+            location_info,
+            ExprKind::Conditional(
+                Box::new(condition),
+                Box::new(then_expr),
+                Box::new(else_expr),
+            ),
+        )
+    }
+
+    fn fold_get(&mut self, location_info: LocationInfo, object: Expr, name: String) -> Expr {
+        let object = self.fold_expr(object);
+        Expr::new(location_info, ExprKind::Get(Box::new(object), name))
+    }
+
+    fn fold_grouping(&mut self, location_info: LocationInfo, expr: Expr) -> Expr {
+        let expr = self.fold_expr(expr);
+        Expr::new(location_info, ExprKind::Grouping(Box::new(expr)))
+    }
+
+    fn fold_index(&mut self, location_info: LocationInfo, object: Expr, index: Expr) -> Expr {
+        let object = self.fold_expr(object);
+        let index = self.fold_expr(index);
+        Expr::new(
+            location_info,
+            ExprKind::Index(Box::new(object), Box::new(index)),
+        )
+    }
+
+    fn fold_interpolation(&mut self, location_info: LocationInfo, parts: Vec<Expr>) -> Expr {
+        let parts = parts.into_iter().map(|e| self.fold_expr(e)).collect();
+        Expr::new(location_info, ExprKind::Interpolation(parts))
+    }
+
+    fn fold_lambda(
+        &mut self,
+        location_info: LocationInfo,
+        params: Vec<String>,
+        body: Vec<StmtWrapper>,
+    ) -> Expr {
+        let body = self.fold_block(body);
+        Expr::new(location_info, ExprKind::Lambda(params, body))
+    }
+
+    fn fold_list(&mut self, location_info: LocationInfo, elements: Vec<Expr>) -> Expr {
+        let elements = elements.into_iter().map(|e| self.fold_expr(e)).collect();
+        Expr::new(location_info, ExprKind::List(elements))
+    }
+
+    fn fold_literal(&mut self, location_info: LocationInfo, value: Primitive) -> Expr {
+        Expr::new(location_info, ExprKind::Literal(value))
+    }
+
+    fn fold_logical(
+        &mut self,
+        location_info: LocationInfo,
+        operator: Operator,
+        left: Expr,
+        right: Expr,
+    ) -> Expr {
+        let left = self.fold_expr(left);
+        let right = self.fold_expr(right);
+        Expr::new(
+            location_info,
+            ExprKind::Logical(operator, Box::new(left), Box::new(right)),
+        )
+    }
+
+    fn fold_map(&mut self, location_info: LocationInfo, entries: Vec<(Expr, Expr)>) -> Expr {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (self.fold_expr(key), self.fold_expr(value)))
+            .collect();
+        Expr::new(location_info, ExprKind::Map(entries))
+    }
+
+    fn fold_set(
+        &mut self,
+        location_info: LocationInfo,
+        object: Expr,
+        name: String,
+        value: Expr,
+    ) -> Expr {
+        let object = self.fold_expr(object);
+        let value = self.fold_expr(value);
+        Expr::new(
+            location_info,
+            ExprKind::Set(Box::new(object), name, Box::new(value)),
+        )
+    }
+
+    fn fold_set_index(
+        &mut self,
+        location_info: LocationInfo,
+        object: Expr,
+        index: Expr,
+        value: Expr,
+    ) -> Expr {
+        let object = self.fold_expr(object);
+        let index = self.fold_expr(index);
+        let value = self.fold_expr(value);
+        Expr::new(
+            location_info,
+            ExprKind::SetIndex(Box::new(object), Box::new(index), Box::new(value)),
+        )
+    }
+
+    fn fold_super(&mut self, location_info: LocationInfo, method: String) -> Expr {
+        Expr::new(location_info, ExprKind::Super(method))
+    }
+
+    fn fold_this(&mut self, location_info: LocationInfo) -> Expr {
+        Expr::new(location_info, ExprKind::This)
+    }
+
+    fn fold_unary(&mut self, location_info: LocationInfo, operator: Operator, right: Expr) -> Expr {
+        let right = self.fold_expr(right);
+        Expr::new(location_info, ExprKind::Unary(operator, Box::new(right)))
+    }
+
+    fn fold_variable(&mut self, location_info: LocationInfo, name: String) -> Expr {
+        Expr::new(location_info, ExprKind::Variable(name))
+    }
+
+    fn fold_stmt(&mut self, stmt: StmtWrapper) -> StmtWrapper {
+        walk_fold_stmt(self, stmt)
+    }
+
+    fn fold_block(&mut self, statements: Vec<StmtWrapper>) -> Vec<StmtWrapper> {
+        statements
+            .into_iter()
+            .map(|stmt| self.fold_stmt(stmt))
+            .collect()
+    }
+
+    fn fold_break(&mut self) -> Stmt {
+        Stmt::Break
+    }
+
+    fn fold_class(&mut self, decl: ClassDecl) -> Stmt {
+        Stmt::Class(ClassDecl {
+            methods: decl
+                .methods
+                .into_iter()
+                .map(|method| FunctionDecl {
+                    body: self.fold_block(method.body),
+                    ..method
+                })
+                .collect(),
+            ..decl
+        })
+    }
+
+    fn fold_continue(&mut self) -> Stmt {
+        Stmt::Continue
+    }
+
+    fn fold_expression_stmt(&mut self, expr: Expr) -> Stmt {
+        Stmt::Expression(self.fold_expr(expr))
+    }
+
+    fn fold_for_in(&mut self, name: String, iterable: Expr, body: StmtWrapper) -> Stmt {
+        let iterable = self.fold_expr(iterable);
+        Stmt::ForIn(name, iterable, Box::new(self.fold_stmt(body)))
+    }
+
+    fn fold_function(&mut self, decl: FunctionDecl) -> Stmt {
+        Stmt::Function(FunctionDecl {
+            body: self.fold_block(decl.body),
+            ..decl
+        })
+    }
+
+    fn fold_if(
+        &mut self,
+        condition: Expr,
+        then_branch: StmtWrapper,
+        else_branch: Option<StmtWrapper>,
+    ) -> Stmt {
+        let condition = self.fold_expr(condition);
+        let then_branch = self.fold_stmt(then_branch);
+        let else_branch = else_branch.map(|b| Box::new(self.fold_stmt(b)));
+        Stmt::If(condition, Box::new(then_branch), else_branch)
+    }
+
+    fn fold_print(&mut self, expr: Expr) -> Stmt {
+        Stmt::Print(self.fold_expr(expr))
+    }
+
+    fn fold_return(&mut self, value: Option<Expr>) -> Stmt {
+        Stmt::Return(value.map(|v| self.fold_expr(v)))
+    }
+
+    fn fold_throw(&mut self, value: Expr) -> Stmt {
+        Stmt::Throw(self.fold_expr(value))
+    }
+
+    fn fold_try(&mut self, stmt: TryStmt) -> Stmt {
+        Stmt::Try(TryStmt {
+            body: self.fold_block(stmt.body),
+            catch: stmt
+                .catch
+                .map(|(name, handler)| (name, self.fold_block(handler))),
+            finally: stmt.finally.map(|finally| self.fold_block(finally)),
+        })
+    }
+
+    fn fold_var(&mut self, name: String, initializer: Option<Expr>) -> Stmt {
+        Stmt::Var(name, initializer.map(|i| self.fold_expr(i)))
+    }
+
+    fn fold_while(&mut self, condition: Expr, body: StmtWrapper, increment: Option<Expr>) -> Stmt {
+        let condition = self.fold_expr(condition);
+        let body = self.fold_stmt(body);
+        Stmt::While(
+            condition,
+            Box::new(body),
+            increment.map(|i| self.fold_expr(i)),
+        )
+    }
+}
+
+/// Dispatches an expression to the folder method for its node type, along
+/// with its location.
+pub fn walk_fold_expr<F: Folder>(folder: &mut F, expr: Expr) -> Expr {
+    let Expr {
+        location_info,
+        kind,
+    } = expr;
+    match kind {
+        ExprKind::Assign(name, value) => folder.fold_assign(location_info, name, *value),
+        ExprKind::Binary(operator, left, right) => {
+            folder.fold_binary(location_info, operator, *left, *right)
+        }
+        ExprKind::Call(callee, args) => folder.fold_call(location_info, *callee, args),
+        ExprKind::Comma(left, right) => folder.fold_comma(location_info, *left, *right),
+        ExprKind::Conditional(condition, then_expr, else_expr) => {
+            folder.fold_conditional(location_info, *condition, *then_expr, *else_expr)
+        }
+        ExprKind::Get(object, name) => folder.fold_get(location_info, *object, name),
+        ExprKind::Grouping(expr) => folder.fold_grouping(location_info, *expr),
+        ExprKind::Index(object, index) => folder.fold_index(location_info, *object, *index),
+        ExprKind::Interpolation(parts) => folder.fold_interpolation(location_info, parts),
+        ExprKind::Lambda(params, body) => folder.fold_lambda(location_info, params, body),
+        ExprKind::List(elements) => folder.fold_list(location_info, elements),
+        ExprKind::Literal(value) => folder.fold_literal(location_info, value),
+        ExprKind::Logical(operator, left, right) => {
+            folder.fold_logical(location_info, operator, *left, *right)
+        }
+        ExprKind::Map(entries) => folder.fold_map(location_info, entries),
+        ExprKind::Set(object, name, value) => folder.fold_set(location_info, *object, name, *value),
+        ExprKind::SetIndex(object, index, value) => {
+            folder.fold_set_index(location_info, *object, *index, *value)
+        }
+        ExprKind::Super(method) => folder.fold_super(location_info, method),
+        ExprKind::This => folder.fold_this(location_info),
+        ExprKind::Unary(operator, right) => folder.fold_unary(location_info, operator, *right),
+        ExprKind::Variable(name) => folder.fold_variable(location_info, name),
+    }
+}
+
+/// Dispatches a statement to the folder method for its node type, keeping
+/// its location.
+pub fn walk_fold_stmt<F: Folder>(folder: &mut F, wrapper: StmtWrapper) -> StmtWrapper {
+    let StmtWrapper {
+        location_info,
+        stmt,
+    } = wrapper;
+    let stmt = match stmt {
+        Stmt::Block(statements) => Stmt::Block(folder.fold_block(statements)),
+        Stmt::Break => folder.fold_break(),
+        Stmt::Class(decl) => folder.fold_class(decl),
+        Stmt::Continue => folder.fold_continue(),
+        Stmt::Expression(expr) => folder.fold_expression_stmt(expr),
+        Stmt::ForIn(name, iterable, body) => folder.fold_for_in(name, iterable, *body),
+        Stmt::Function(decl) => folder.fold_function(decl),
+        Stmt::If(condition, then_branch, else_branch) => {
+            folder.fold_if(condition, *then_branch, else_branch.map(|b| *b))
+        }
+        Stmt::Print(expr) => folder.fold_print(expr),
+        Stmt::Return(value) => folder.fold_return(value),
+        Stmt::Throw(value) => folder.fold_throw(value),
+        Stmt::Try(stmt) => folder.fold_try(stmt),
+        Stmt::Var(name, initializer) => folder.fold_var(name, initializer),
+        Stmt::While(condition, body, increment) => folder.fold_while(condition, *body, increment),
+    };
+    StmtWrapper {
+        location_info,
+        stmt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sexpr;
+    use crate::utils::parse;
+
+    // Only overrides the leaves it counts, the defaults reach every one
+    struct VariableCounter;
+
+    impl Visitor for VariableCounter {
+        type Output = usize;
+
+        fn combine(&mut self, first: usize, second: usize) -> usize {
+            first + second
+        }
+
+        fn visit_variable(&mut self, _name: &str) -> usize {
+            1
+        }
+
+        fn visit_assign(&mut self, _name: &str, value: &Expr) -> usize {
+            1 + self.visit_expr(value)
+        }
+    }
+
+    struct Renamer;
+
+    impl Folder for Renamer {
+        fn fold_variable(&mut self, location_info: LocationInfo, name: String) -> Expr {
+            Expr::new(
+                location_info,
+                ExprKind::Variable(format!("{}_renamed", name)),
+            )
+        }
+    }
+
+    #[test]
+    fn test_visitor_reaches_every_node() {
+        let program = parse(
+            "var a = b;
+            fun f(x) { if (x) return y; else { print -z; } while (w) v = u(t, s or r); }
+            print (p ? q : o, m);
+            for (var i = 0; i < n; i = i + 1) {}",
+        );
+        let count = VariableCounter.visit_block(&program);
+        // b x y z w v u t s r p q o m i n i i
+        assert_eq!(count, 18);
+    }
+
+    #[test]
+    fn test_folder_rebuilds_tree() {
+        let expr = sexpr::parse("(call f (group (+ a 1)) (and b (- c)))").expect("Bad fixture");
+        assert_eq!(
+            Renamer.fold_expr(expr),
+            sexpr::parse("(call f_renamed (group (+ a_renamed 1)) (and b_renamed (- c_renamed)))")
+                .expect("Bad fixture")
+        );
+        let program = Renamer.fold_block(parse("var a = b;\nprint c;"));
+        assert_eq!(program[1].location_info.line, 2);
+        assert!(
+            matches!(&program[0].stmt, Stmt::Var(name, Some(init)) if name == "a" && init.kind == ExprKind::Variable("b_renamed".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,2746 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::{Duration, Instant};
+
+use crate::ast::StmtWrapper;
+use crate::chunk::{Chunk, OpCode};
+use crate::compiler::{CompileError, Compiler};
+use crate::gc::{Collection, GcConfig, Heap, ObjRef, Trace, Tracer};
+use crate::json::{self, Json};
+use crate::math::{self, Rng};
+use crate::native::{self, Arity, NativeClass, NativeFunction, NativeMethod};
+use crate::object::{BoundMethod, Class, Closure, Instance, Iter, Map, Obj, Upvalue};
+use crate::os;
+use crate::value::{HashKey, Value, ValueKind};
+
+const FRAMES_MAX: usize = 64;
+// Instructions run between checks of the wall clock
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+// Bytes in a string built by a single method call
+const MAX_STRING_LEN: usize = 1 << 30;
+// Lists and maps nested deeper are printed elided, like cycles
+const MAX_PRINT_DEPTH: usize = 512;
+
+/// Guards for running untrusted scripts. Budgets start over with each call
+/// to `interpret` or `call_function`.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // Instructions executed
+    pub max_steps: Option<u64>,
+    // Calls made through natives also nest on the Rust stack, so keep this modest
+    pub max_frames: usize,
+    // Bytes in use, checked between instructions and after a full collection
+    pub max_heap: Option<usize>,
+    pub timeout: Option<Duration>,
+    // Refuse natives that reach outside the interpreter, like clock, input
+    // and the io and os modules
+    pub sandbox: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: None,
+            max_frames: FRAMES_MAX,
+            max_heap: None,
+            timeout: None,
+            sandbox: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    CallDepth,
+    Heap,
+    Timeout,
+}
+
+impl Limit {
+    fn message(self) -> &'static str {
+        match self {
+            Limit::Steps => "Step budget exhausted.",
+            Limit::CallDepth => "Stack overflow.",
+            Limit::Heap => "Heap limit exceeded.",
+            Limit::Timeout => "Timed out.",
+        }
+    }
+
+    // Unwinding the stack gives back the frames, every other resource stays
+    // spent, so those errors go straight to the host
+    fn is_catchable(self) -> bool {
+        self == Limit::CallDepth
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    // Calls active when the error was raised, innermost first
+    pub trace: Vec<TraceFrame>,
+    // Set when the script was stopped by one of the VM's limits
+    pub limit: Option<Limit>,
+    // Set when the script asked to exit with this status
+    pub exit: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub line: usize,
+    pub column: usize,
+    // None for top level code
+    pub function: Option<String>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in self.trace.iter() {
+            write!(f, "\n[line {}, column {}] in ", frame.line, frame.column)?;
+            match frame.function {
+                Some(ref name) => write!(f, "{}()", name)?,
+                None => write!(f, "script")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum InterpretError {
+    Compile(CompileError),
+    Runtime(RuntimeError),
+    // A limit stopped the script, see RuntimeError::limit for which
+    Limit(RuntimeError),
+    // The script called os.exit with this status
+    Exit(i32),
+}
+
+impl From<RuntimeError> for InterpretError {
+    fn from(err: RuntimeError) -> InterpretError {
+        match (err.limit, err.exit) {
+            (_, Some(status)) => InterpretError::Exit(status),
+            (Some(_), None) => InterpretError::Limit(err),
+            (None, None) => InterpretError::Runtime(err),
+        }
+    }
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InterpretError::Compile(ref err) => write!(f, "{}", err),
+            InterpretError::Runtime(ref err) | InterpretError::Limit(ref err) => {
+                write!(f, "{}", err)
+            }
+            InterpretError::Exit(status) => write!(f, "Exited with status {}.", status),
+        }
+    }
+}
+
+type RunResult<T> = Result<T, RuntimeError>;
+
+struct CallFrame {
+    closure: ObjRef,
+    // The closure's function, cached to avoid a lookup per instruction
+    function: ObjRef,
+    ip: usize,
+    // Stack index of the callee, its arguments and locals follow it
+    slot_base: usize,
+}
+
+// Where an error raised inside a try statement resumes
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+    // Frame count and stack height when the handler was pushed
+    frame_depth: usize,
+    stack_len: usize,
+    ip: usize,
+}
+
+struct Roots<'a> {
+    stack: &'a [Value],
+    frames: &'a [CallFrame],
+    globals: &'a HashMap<ObjRef, Value>,
+    open_upvalues: &'a [ObjRef],
+    error_class: ObjRef,
+    thrown: Option<Value>,
+}
+
+impl<'a> Trace for Roots<'a> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.stack.trace(tracer);
+        for frame in self.frames.iter() {
+            tracer.mark(frame.closure);
+        }
+        self.globals.trace(tracer);
+        self.open_upvalues.trace(tracer);
+        tracer.mark(self.error_class);
+        if let Some(thrown) = self.thrown {
+            thrown.trace(tracer);
+        }
+    }
+}
+
+/// Stack based bytecode interpreter. Globals and the heap persist across
+/// calls to `interpret`, so a REPL can feed it one line at a time.
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<ObjRef, Value>,
+    // Upvalues still pointing into the stack, at most one per slot
+    open_upvalues: Vec<ObjRef>,
+    // Try handlers, innermost last
+    handlers: Vec<Handler>,
+    // Class of the objects runtime errors are caught as
+    error_class: ObjRef,
+    // The value being thrown while an error unwinds, None for runtime errors
+    thrown: Option<Value>,
+    // Caught errors by the stack slot holding their value, for Rethrow
+    caught: Vec<(usize, RuntimeError)>,
+    // Where print writes to, and input() reads from
+    out: Box<dyn Write>,
+    input: Box<dyn BufRead>,
+    limits: Limits,
+    // Instructions run and the time to stop by, for the current run
+    steps: u64,
+    deadline: Option<Instant>,
+    // Command line arguments for the script, read through os.args
+    args: Vec<String>,
+}
+
+impl Vm {
+    pub fn new(gc_config: GcConfig) -> Vm {
+        Vm::with_output(gc_config, Box::new(io::stdout()))
+    }
+
+    pub fn with_output(gc_config: GcConfig, out: Box<dyn Write>) -> Vm {
+        Vm::with_io(gc_config, Box::new(BufReader::new(io::stdin())), out)
+    }
+
+    pub fn with_io(gc_config: GcConfig, input: Box<dyn BufRead>, out: Box<dyn Write>) -> Vm {
+        let mut heap = Heap::new(gc_config);
+        let name = heap.intern("Error");
+        let error_class = heap.alloc(Obj::Class(Class::new(name)));
+        let mut vm = Vm {
+            heap,
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            handlers: vec![],
+            error_class,
+            thrown: None,
+            caught: vec![],
+            out,
+            input,
+            limits: Default::default(),
+            steps: 0,
+            deadline: None,
+            args: vec![],
+        };
+        for native in native::core() {
+            vm.define_native(native);
+        }
+        vm.define_namespace(math::class(), Rng::from_time());
+        vm.define_namespace(json::class(), ());
+        vm.define_namespace(os::io_class(), ());
+        vm.define_namespace(os::os_class(), ());
+        vm
+    }
+
+    /// Makes a Rust function callable from Lox as a global.
+    pub fn define_native(&mut self, native: NativeFunction) {
+        let name = self.intern(&native.name);
+        self.push(Value::from(name));
+        let function = self.alloc(Obj::Native(native));
+        self.globals.insert(name, Value::from(function));
+        self.pop();
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Makes a Rust type available to Lox as a global class.
+    pub fn define_class(&mut self, class: NativeClass) {
+        let name = self.intern(&class.name);
+        self.push(Value::from(name));
+        let class_ref = self.alloc(Obj::Class(Class::new(name)));
+        self.globals.insert(name, Value::from(class_ref));
+        self.pop();
+        let privileged = class.privileged;
+        for method in class.methods {
+            let (name, method) = self.alloc_native_method(method, privileged);
+            self.class_mut(class_ref, |c| c.methods.insert(name, Value::from(method)));
+        }
+        for getter in class.getters {
+            let (name, getter) = self.alloc_native_method(getter, privileged);
+            self.class_mut(class_ref, |c| c.getters.insert(name, getter));
+        }
+        for setter in class.setters {
+            let (name, setter) = self.alloc_native_method(setter, privileged);
+            self.class_mut(class_ref, |c| c.setters.insert(name, setter));
+        }
+    }
+
+    // Returns the method's interned name and the method object
+    fn alloc_native_method(&mut self, method: NativeMethod, privileged: bool) -> (ObjRef, ObjRef) {
+        let method = NativeMethod {
+            privileged,
+            ..method
+        };
+        let name = self.intern(&method.name);
+        // The name is rooted until the method is allocated
+        self.push(Value::from(name));
+        let method = self.alloc(Obj::NativeMethod(method));
+        self.pop();
+        (name, method)
+    }
+
+    /// Defines `class` and binds its name to a single instance holding
+    /// `data`, so scripts call its methods like functions in a module.
+    pub fn define_namespace<T: Any>(&mut self, class: NativeClass, data: T) {
+        let name = class.name.clone();
+        self.define_class(class);
+        let class_ref = self
+            .get_global(&name)
+            .and_then(|class| class.as_obj())
+            .expect("Class was just defined");
+        let mut instance = Instance::new(class_ref);
+        instance.host = Some(Box::new(data));
+        let instance = self.alloc(Obj::Instance(instance));
+        self.set_global(&name, Value::from(instance));
+    }
+
+    /// Attaches Rust data to an instance, replacing what it held before.
+    pub fn set_host_data<T: Any>(&mut self, instance: Value, data: T) -> RunResult<()> {
+        if let Some(Obj::Instance(i)) = instance.as_obj().map(|r| self.heap.get_mut(r)) {
+            i.host = Some(Box::new(data));
+            return Ok(());
+        }
+        Err(self.error("Only instances can hold host data."))
+    }
+
+    /// The instance's host data, None if it holds none of type T.
+    pub fn host_data<T: Any>(&self, instance: Value) -> Option<&T> {
+        match instance.as_obj().map(|r| self.heap.get(r)) {
+            Some(Obj::Instance(i)) => i.host.as_ref()?.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn host_data_mut<T: Any>(&mut self, instance: Value) -> Option<&mut T> {
+        match self.heap.get_mut(instance.as_obj()?) {
+            Obj::Instance(i) => i.host.as_mut()?.downcast_mut(),
+            _ => None,
+        }
+    }
+
+    /// Allocates a string, for natives returning one.
+    pub fn new_string(&mut self, s: &str) -> Value {
+        Value::from(self.intern(s))
+    }
+
+    /// Reads a line for `input()`, None at the end of input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// The collections made since the last call, when the GC log is on.
+    pub fn take_gc_log(&mut self) -> Vec<Collection> {
+        self.heap.take_log()
+    }
+
+    /// Reports the compile errors in `program` without running it. The
+    /// compiled function is left for the collector.
+    pub fn check(&mut self, program: &[StmtWrapper]) -> Result<(), InterpretError> {
+        Compiler::new(&mut self.heap)
+            .compile(program)
+            .map(|_| ())
+            .map_err(InterpretError::Compile)
+    }
+
+    pub fn interpret(&mut self, program: &[StmtWrapper]) -> Result<(), InterpretError> {
+        let function = Compiler::new(&mut self.heap)
+            .compile(program)
+            .map_err(InterpretError::Compile)?;
+        // Keep the function reachable while the closure is allocated
+        self.stack.push(Value::from(function));
+        let closure = self.alloc(Obj::Closure(Closure {
+            function,
+            upvalues: vec![],
+        }));
+        self.stack.pop();
+        self.stack.push(Value::from(closure));
+
+        self.start();
+        let result = self.call(closure, 0).and_then(|_| self.run(0));
+        self.finish(result)?;
+        // The script's own return value
+        self.pop();
+        Ok(())
+    }
+
+    /// Calls a function, class or method value from the host. The arguments
+    /// must be values this VM handed out, or built from it.
+    pub fn call_function(
+        &mut self,
+        callee: Value,
+        args: &[Value],
+    ) -> Result<Value, InterpretError> {
+        self.start();
+        let result = self.call_sync(callee, args);
+        Ok(self.finish(result)?)
+    }
+
+    // Starts the limits' budgets for a run
+    fn start(&mut self) {
+        self.steps = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    // Drops the state of a run that ended in an error, ready for the next one
+    fn finish<T>(&mut self, result: RunResult<T>) -> RunResult<T> {
+        self.caught.clear();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.handlers.clear();
+            self.thrown = None;
+        }
+        result
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        // Global names are rooted, so a defined one is still interned
+        let name = self.heap.interned(name)?;
+        self.globals.get(&name).copied()
+    }
+
+    /// Defines or overwrites a global, keeping the value alive.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.push(value);
+        let name = self.intern(name);
+        self.globals.insert(name, value);
+        self.pop();
+    }
+
+    /// The contents of a string value, None for anything else.
+    pub fn as_string(&self, value: Value) -> Option<&str> {
+        match value.as_obj().map(|r| self.heap.get(r)) {
+            Some(Obj::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Formats a value the way `print` shows it.
+    pub fn stringify(&self, value: Value) -> String {
+        self.stringify_nested(value, &mut vec![])
+    }
+
+    // `enclosing` holds the lists being printed, so a list containing itself
+    // terminates. Past `MAX_PRINT_DEPTH` contents are elided too, rather
+    // than overflowing the Rust stack.
+    fn stringify_nested(&self, value: Value, enclosing: &mut Vec<ObjRef>) -> String {
+        match value.kind() {
+            ValueKind::Nil => "nil".to_owned(),
+            ValueKind::Bool(b) => format!("{}", b),
+            ValueKind::Number(n) => format!("{}", n),
+            ValueKind::Obj(r) => match self.heap.get(r) {
+                Obj::String(s) => s.clone(),
+                Obj::Function(f) => match f.name {
+                    Some(name) => format!("<fn {}>", self.stringify(Value::from(name))),
+                    None => "<script>".to_owned(),
+                },
+                Obj::Closure(c) => self.stringify(Value::from(c.function)),
+                Obj::Upvalue(_) => "upvalue".to_owned(),
+                Obj::Class(c) => self.stringify(Value::from(c.name)),
+                Obj::Instance(i) => format!("{} instance", self.stringify(Value::from(i.class))),
+                Obj::BoundMethod(b) => self.stringify(Value::from(b.method)),
+                Obj::Range(start, end) => format!("{}..{}", start, end),
+                Obj::Iter(_) => "<iterator>".to_owned(),
+                Obj::Native(n) => format!("<native fn {}>", n.name),
+                Obj::NativeMethod(m) => format!("<native fn {}>", m.name),
+                Obj::List(_) if enclosing.contains(&r) || enclosing.len() == MAX_PRINT_DEPTH => {
+                    "[...]".to_owned()
+                }
+                Obj::Map(_) if enclosing.contains(&r) || enclosing.len() == MAX_PRINT_DEPTH => {
+                    "{...}".to_owned()
+                }
+                Obj::Map(map) => {
+                    enclosing.push(r);
+                    let entries: Vec<String> = map
+                        .entries()
+                        .iter()
+                        .map(|&(key, value)| {
+                            format!(
+                                "{}: {}",
+                                self.repr(key, enclosing),
+                                self.repr(value, enclosing)
+                            )
+                        })
+                        .collect();
+                    enclosing.pop();
+                    format!("{{{}}}", entries.join(", "))
+                }
+                Obj::List(items) => {
+                    enclosing.push(r);
+                    let items: Vec<String> = items
+                        .iter()
+                        .map(|item| self.repr(*item, enclosing))
+                        .collect();
+                    enclosing.pop();
+                    format!("[{}]", items.join(", "))
+                }
+            },
+        }
+    }
+
+    // Like stringify, but quotes strings so list elements stay distinguishable
+    fn repr(&self, value: Value, enclosing: &mut Vec<ObjRef>) -> String {
+        match value.as_obj().map(|r| self.heap.get(r)) {
+            Some(Obj::String(s)) => format!("\"{}\"", s),
+            _ => self.stringify_nested(value, enclosing),
+        }
+    }
+
+    // Runs until the frame count drops back to `base_depth`, leaving the
+    // returned value on the stack. Errors unwind to the innermost handler
+    // pushed since, the rest are returned.
+    fn run(&mut self, base_depth: usize) -> RunResult<()> {
+        loop {
+            match self.execute(base_depth) {
+                Ok(()) => return Ok(()),
+                Err(err) => self.catch(err, base_depth)?,
+            }
+        }
+    }
+
+    fn catch(&mut self, err: RuntimeError, base_depth: usize) -> RunResult<()> {
+        if err.exit.is_some() || err.limit.is_some_and(|limit| !limit.is_catchable()) {
+            return Err(err);
+        }
+        let handler = match self.handlers.last() {
+            Some(handler) if handler.frame_depth > base_depth => *handler,
+            _ => return Err(err),
+        };
+        self.handlers.pop();
+        let value = match self.thrown.take() {
+            Some(value) => value,
+            None => self.error_object(&err),
+        };
+        self.frames.truncate(handler.frame_depth);
+        self.close_upvalues(handler.stack_len);
+        self.stack.truncate(handler.stack_len);
+        self.push(value);
+        self.frame_mut().ip = handler.ip;
+        // Errors caught in slots that were just discarded can't be rethrown
+        self.caught.retain(|&(slot, _)| slot < handler.stack_len);
+        self.caught.push((handler.stack_len, err));
+        Ok(())
+    }
+
+    // Wraps a runtime error in an Error instance for a catch clause
+    fn error_object(&mut self, err: &RuntimeError) -> Value {
+        // Each part is rooted on the stack until the instance holds it
+        let message = self.intern(&err.message);
+        self.push(Value::from(message));
+        let message_key = self.intern("message");
+        self.push(Value::from(message_key));
+        let line_key = self.intern("line");
+        self.push(Value::from(line_key));
+        let column_key = self.intern("column");
+        self.push(Value::from(column_key));
+        let mut fields = HashMap::new();
+        fields.insert(message_key, Value::from(message));
+        fields.insert(line_key, Value::from(err.line as f64));
+        fields.insert(column_key, Value::from(err.column as f64));
+        let instance = self.alloc(Obj::Instance(Instance {
+            fields,
+            ..Instance::new(self.error_class)
+        }));
+        self.stack.truncate(self.stack.len() - 4);
+        Value::from(instance)
+    }
+
+    // The error reported if a thrown value is never caught
+    fn thrown_error(&self, value: Value) -> RuntimeError {
+        let message = match value.as_obj().map(|r| self.heap.get(r)) {
+            // Error-like instances report their message
+            Some(Obj::Instance(instance)) => instance
+                .fields
+                .iter()
+                .find(|(k, _)| matches!(self.heap.get(**k), Obj::String(s) if s == "message"))
+                .map(|(_, message)| self.stringify(*message)),
+            _ => None,
+        };
+        self.error(&message.unwrap_or_else(|| self.stringify(value)))
+    }
+
+    fn execute(&mut self, base_depth: usize) -> RunResult<()> {
+        loop {
+            let op = {
+                let frame = self.frames.last_mut().expect("No frame to run");
+                let op = match self.heap.get(frame.function) {
+                    Obj::Function(f) => f.chunk.code[frame.ip],
+                    _ => unreachable!("Call frame without a function"),
+                };
+                frame.ip += 1;
+                op
+            };
+            // After the fetch, so errors point at the instruction
+            self.check_limits()?;
+
+            match op {
+                OpCode::Constant(index) => {
+                    let constant = self.chunk().constants[index];
+                    self.push(constant);
+                }
+                OpCode::Nil => self.push(Value::nil()),
+                OpCode::True => self.push(Value::from(true)),
+                OpCode::False => self.push(Value::from(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().slot_base + slot];
+                    self.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let index = self.frame().slot_base + slot;
+                    self.stack[index] = self.peek(0);
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = self.constant_ref(index);
+                    match self.globals.get(&name) {
+                        Some(value) => {
+                            let value = *value;
+                            self.push(value);
+                        }
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::DefineGlobal(index) => {
+                    let name = self.constant_ref(index);
+                    let value = self.peek(0);
+                    self.globals.insert(name, value);
+                    self.pop();
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = self.constant_ref(index);
+                    if !self.globals.contains_key(&name) {
+                        return Err(self.undefined_variable(name));
+                    }
+                    let value = self.peek(0);
+                    self.globals.insert(name, value);
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = self.upvalue(index);
+                    let value = match self.heap.get(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => self.stack[*slot],
+                        Obj::Upvalue(Upvalue::Closed(value)) => *value,
+                        _ => unreachable!("Closure captured a non upvalue"),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = self.upvalue(index);
+                    let value = self.peek(0);
+                    match self.heap.get_mut(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => {
+                            let slot = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Obj::Upvalue(closed) => *closed = Upvalue::Closed(value),
+                        _ => unreachable!("Closure captured a non upvalue"),
+                    }
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::from(a == b));
+                }
+                OpCode::Greater => self.binary_number_op(|a, b| Value::from(a > b))?,
+                OpCode::Less => self.binary_number_op(|a, b| Value::from(a < b))?,
+                OpCode::Add => match (self.peek(1).kind(), self.peek(0).kind()) {
+                    (ValueKind::Number(a), ValueKind::Number(b)) => {
+                        self.pop();
+                        self.pop();
+                        self.push(Value::from(a + b));
+                    }
+                    (ValueKind::Obj(a), ValueKind::Obj(b))
+                        if self.is_string(a) && self.is_string(b) =>
+                    {
+                        self.concatenate(a, b);
+                    }
+                    _ => return Err(self.error("Operands must be two numbers or two strings.")),
+                },
+                OpCode::Subtract => self.binary_number_op(|a, b| Value::from(a - b))?,
+                OpCode::Multiply => self.binary_number_op(|a, b| Value::from(a * b))?,
+                OpCode::Divide => self.binary_number_op(|a, b| Value::from(a / b))?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::from(value.is_falsey()));
+                }
+                OpCode::Negate => match self.peek(0).as_number() {
+                    Some(n) => {
+                        self.pop();
+                        self.push(Value::from(-n));
+                    }
+                    _ => return Err(self.error("Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    let text = self.stringify(value);
+                    if let Err(err) = writeln!(self.out, "{}", text) {
+                        return Err(self.error(&format!("Could not write output: {}", err)));
+                    }
+                }
+                OpCode::Jump(offset) => self.frame_mut().ip += offset,
+                OpCode::JumpIfFalse(offset) => {
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop(offset) => self.frame_mut().ip -= offset,
+                OpCode::Call(arg_count) => {
+                    let callee = self.peek(arg_count);
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::Invoke(name, arg_count) => {
+                    let name = self.constant_ref(name);
+                    self.invoke(name, arg_count)?;
+                }
+                OpCode::GetProperty(name) => {
+                    let name = self.constant_ref(name);
+                    let instance = match self.peek(0).as_obj().map(|r| self.heap.get(r)) {
+                        Some(Obj::Instance(i)) => i,
+                        _ => return Err(self.error("Only instances have properties.")),
+                    };
+                    // Getters replace the receiver with the property's value
+                    if let Some(getter) = self.find_accessor(instance.class, name, false) {
+                        self.call_native_method(getter, 0)?;
+                        continue;
+                    }
+                    // Fields shadow methods
+                    let value = match instance.fields.get(&name) {
+                        Some(value) => *value,
+                        None => match self.find_method(instance.class, name) {
+                            Some(method) => {
+                                // The receiver stays on the stack while the binding is allocated
+                                let receiver = self.peek(0);
+                                Value::from(
+                                    self.alloc(Obj::BoundMethod(BoundMethod { receiver, method })),
+                                )
+                            }
+                            None => return Err(self.undefined_property(name)),
+                        },
+                    };
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::SetProperty(name) => {
+                    let name = self.constant_ref(name);
+                    let value = self.peek(0);
+                    let (instance, class) =
+                        match self.peek(1).as_obj().map(|r| (r, self.heap.get(r))) {
+                            Some((r, Obj::Instance(i))) => (r, i.class),
+                            _ => return Err(self.error("Only instances have fields.")),
+                        };
+                    if let Some(setter) = self.find_accessor(class, name, true) {
+                        self.call_native_method(setter, 1)?;
+                        continue;
+                    }
+                    if self.find_accessor(class, name, false).is_some() {
+                        return Err(self.error(&format!(
+                            "Property '{}' is read-only.",
+                            self.stringify(Value::from(name))
+                        )));
+                    }
+                    if let Obj::Instance(i) = self.heap.get_mut(instance) {
+                        i.fields.insert(name, value);
+                    }
+                    self.heap.resize(instance);
+                    self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::Class(name) => {
+                    let name = self.constant_ref(name);
+                    let class = self.alloc(Obj::Class(Class::new(name)));
+                    self.push(Value::from(class));
+                }
+                OpCode::Method(name) => {
+                    let name = self.constant_ref(name);
+                    let method = self.peek(0);
+                    let class = self.peek(1).as_obj().expect("Method outside of a class");
+                    self.class_mut(class, |c| c.methods.insert(name, method));
+                    self.pop();
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1).as_obj().map(|r| self.heap.get(r)) {
+                        Some(Obj::Class(c)) => {
+                            (c.methods.clone(), c.getters.clone(), c.setters.clone())
+                        }
+                        _ => return Err(self.error("Superclass must be a class.")),
+                    };
+                    let class = self.peek(0).as_obj().expect("Inherit without a class");
+                    // Methods are copied down, the subclass's own are added after
+                    self.class_mut(class, |c| (c.methods, c.getters, c.setters) = superclass);
+                    self.pop();
+                }
+                OpCode::GetSuper(name) => {
+                    let name = self.constant_ref(name);
+                    // The superclass stays rooted by the 'super' variable
+                    let superclass = self.pop().as_obj().expect("Super without a superclass");
+                    let method = match self.find_method(superclass, name) {
+                        Some(method) => method,
+                        None => return Err(self.undefined_property(name)),
+                    };
+                    let receiver = self.peek(0);
+                    let bound = self.alloc(Obj::BoundMethod(BoundMethod { receiver, method }));
+                    self.pop();
+                    self.push(Value::from(bound));
+                }
+                OpCode::BuildList(len) => {
+                    let start = self.stack.len() - len;
+                    let items = self.stack[start..].to_vec();
+                    // Elements stay on the stack until the list is allocated
+                    let list = self.alloc(Obj::List(items));
+                    self.stack.truncate(start);
+                    self.push(Value::from(list));
+                }
+                OpCode::BuildMap(len) => {
+                    let start = self.stack.len() - len * 2;
+                    let mut map = Map::default();
+                    for i in (start..self.stack.len()).step_by(2) {
+                        let key = self.map_key(self.stack[i])?;
+                        map.insert(key, self.stack[i + 1]);
+                    }
+                    // Entries stay on the stack until the map is allocated
+                    let map = self.alloc(Obj::Map(map));
+                    self.stack.truncate(start);
+                    self.push(Value::from(map));
+                }
+                OpCode::Interpolate(len) => {
+                    let start = self.stack.len() - len;
+                    let text: String = self.stack[start..]
+                        .iter()
+                        .map(|&part| self.stringify(part))
+                        .collect();
+                    // Parts stay on the stack until the string is interned
+                    let string = self.intern(&text);
+                    self.stack.truncate(start);
+                    self.push(Value::from(string));
+                }
+                OpCode::Range => match (self.peek(1).as_number(), self.peek(0).as_number()) {
+                    (Some(start), Some(end)) => {
+                        let range = self.alloc(Obj::Range(start, end));
+                        self.pop();
+                        self.pop();
+                        self.push(Value::from(range));
+                    }
+                    _ => return Err(self.error("Range bounds must be numbers.")),
+                },
+                OpCode::GetIterator => {
+                    let iterator = self.iterator(self.peek(0))?;
+                    self.pop();
+                    self.push(iterator);
+                }
+                OpCode::ForIter(slot, offset) => {
+                    let iterator = self.stack[self.frame().slot_base + slot];
+                    match self.next_item(iterator)? {
+                        Some(item) => self.push(item),
+                        None => self.frame_mut().ip += offset,
+                    }
+                }
+                OpCode::PushHandler(offset) => {
+                    let handler = Handler {
+                        frame_depth: self.frames.len(),
+                        stack_len: self.stack.len(),
+                        ip: self.frame().ip + offset,
+                    };
+                    self.handlers.push(handler);
+                }
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    self.thrown = Some(value);
+                    return Err(self.thrown_error(value));
+                }
+                OpCode::Rethrow => {
+                    let value = self.peek(0);
+                    let slot = self.stack.len() - 1;
+                    while self.caught.last().is_some_and(|&(s, _)| s > slot) {
+                        self.caught.pop();
+                    }
+                    let err = match self.caught.last() {
+                        Some(&(s, _)) if s == slot => self.caught.pop().expect("Checked above").1,
+                        _ => self.thrown_error(value),
+                    };
+                    self.thrown = Some(value);
+                    return Err(err);
+                }
+                OpCode::GetIndex => {
+                    let value = self.get_index(self.peek(1), self.peek(0))?;
+                    self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.peek(0);
+                    self.set_index(self.peek(2), self.peek(1), value)?;
+                    self.pop();
+                    self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::Closure(index) => {
+                    let function = self.constant_ref(index);
+                    let closure = self.alloc(Obj::Closure(Closure {
+                        function,
+                        upvalues: vec![],
+                    }));
+                    // Rooted on the stack while its upvalues are captured
+                    self.push(Value::from(closure));
+                    let descriptors = match self.heap.get(function) {
+                        Obj::Function(f) => f.upvalues.clone(),
+                        _ => unreachable!("Closure over a non function"),
+                    };
+                    for descriptor in descriptors {
+                        let upvalue = if descriptor.is_local {
+                            let slot = self.frame().slot_base + descriptor.index;
+                            self.capture_upvalue(slot)
+                        } else {
+                            self.upvalue(descriptor.index)
+                        };
+                        if let Obj::Closure(c) = self.heap.get_mut(closure) {
+                            c.upvalues.push(upvalue);
+                        }
+                    }
+                    self.heap.resize(closure);
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Returned without a frame");
+                    self.close_upvalues(frame.slot_base);
+                    self.stack.truncate(frame.slot_base);
+                    self.push(result);
+                    if self.frames.len() == base_depth {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> RunResult<()> {
+        let slot = self.stack.len() - arg_count - 1;
+        match callee.as_obj().map(|r| (r, self.heap.get(r))) {
+            Some((closure, Obj::Closure(_))) => self.call(closure, arg_count),
+            Some((_, Obj::BoundMethod(bound))) => {
+                let method = bound.method;
+                self.stack[slot] = bound.receiver;
+                self.call_method(method, arg_count)
+            }
+            Some((class, Obj::Class(_))) => {
+                // The class stays rooted in the callee slot until the instance replaces it
+                let instance = self.alloc(Obj::Instance(Instance::new(class)));
+                self.stack[slot] = Value::from(instance);
+                let init = self.intern("init");
+                match self.find_method(class, init) {
+                    Some(init) => self.call_method(init, arg_count),
+                    None if arg_count != 0 => {
+                        Err(self.error(&format!("Expected 0 arguments but got {}.", arg_count)))
+                    }
+                    None => Ok(()),
+                }
+            }
+            Some((_, Obj::Native(native))) => {
+                if native.privileged && self.limits.sandbox {
+                    let message = format!("'{}' is not available in the sandbox.", native.name);
+                    return Err(self.error(&message));
+                }
+                let function = native.function;
+                self.check_arity(native.arity, arg_count)?;
+                // The arguments stay on the stack, rooted, during the call
+                let args = self.stack[slot + 1..].to_vec();
+                let result = function(self, &args)?;
+                self.return_native(arg_count, result);
+                Ok(())
+            }
+            _ => Err(self.error("Can only call functions and classes.")),
+        }
+    }
+
+    // Calls a closure or native method with the receiver in the callee slot
+    fn call_method(&mut self, method: ObjRef, arg_count: usize) -> RunResult<()> {
+        match self.heap.get(method) {
+            Obj::NativeMethod(_) => self.call_native_method(method, arg_count),
+            _ => self.call(method, arg_count),
+        }
+    }
+
+    fn call_native_method(&mut self, method: ObjRef, arg_count: usize) -> RunResult<()> {
+        let (arity, function) = match self.heap.get(method) {
+            Obj::NativeMethod(m) if m.privileged && self.limits.sandbox => {
+                let message = format!("'{}' is not available in the sandbox.", m.name);
+                return Err(self.error(&message));
+            }
+            Obj::NativeMethod(m) => (m.arity, m.function.clone()),
+            _ => unreachable!("Expected a native method"),
+        };
+        self.check_arity(arity, arg_count)?;
+        let slot = self.stack.len() - arg_count - 1;
+        let args = self.stack[slot + 1..].to_vec();
+        let result = function(self, self.stack[slot], &args)?;
+        self.return_native(arg_count, result);
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: Arity, arg_count: usize) -> RunResult<()> {
+        match arity {
+            Arity::Fixed(arity) if arity != arg_count => Err(self.error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn find_method(&self, class: ObjRef, name: ObjRef) -> Option<ObjRef> {
+        match self.heap.get(class) {
+            Obj::Class(c) => c.methods.get(&name).and_then(|m| m.as_obj()),
+            _ => unreachable!("Instance of a non class"),
+        }
+    }
+
+    // A native getter, or setter when `set` is true
+    fn find_accessor(&self, class: ObjRef, name: ObjRef, set: bool) -> Option<ObjRef> {
+        match self.heap.get(class) {
+            Obj::Class(c) if set => c.setters.get(&name).copied(),
+            Obj::Class(c) => c.getters.get(&name).copied(),
+            _ => unreachable!("Instance of a non class"),
+        }
+    }
+
+    // Calls a method by name from native code and returns the result
+    fn invoke_sync(&mut self, receiver: Value, name: &str, args: &[Value]) -> RunResult<Value> {
+        let name = self.intern(name);
+        self.push(receiver);
+        for arg in args.iter() {
+            self.push(*arg);
+        }
+        let depth = self.frames.len();
+        self.invoke(name, args.len())?;
+        if self.frames.len() > depth {
+            self.run(depth)?;
+        }
+        Ok(self.pop())
+    }
+
+    // Built in iterables get a native iterator, instances provide their own
+    fn iterator(&mut self, iterable: Value) -> RunResult<Value> {
+        let iter = match iterable.as_obj().map(|r| (r, self.heap.get(r))) {
+            Some((list, Obj::List(_))) => Iter::List(list, 0),
+            Some((string, Obj::String(_))) => Iter::String(string, 0),
+            Some((_, Obj::Range(start, end))) => Iter::Range(*start, *end),
+            Some((_, Obj::Map(map))) => {
+                // Iterate over a snapshot of the keys, so the loop may change the map
+                let keys = map.entries().iter().map(|&(key, _)| key).collect();
+                let keys = self.alloc(Obj::List(keys));
+                self.push(Value::from(keys));
+                let iter = self.alloc(Obj::Iter(Iter::List(keys, 0)));
+                self.pop();
+                return Ok(Value::from(iter));
+            }
+            Some((_, Obj::Instance(_))) => return self.invoke_sync(iterable, "iterator", &[]),
+            _ => {
+                return Err(
+                    self.error("Can only iterate over lists, maps, strings, ranges and instances.")
+                )
+            }
+        };
+        Ok(Value::from(self.alloc(Obj::Iter(iter))))
+    }
+
+    fn next_item(&mut self, iterator: Value) -> RunResult<Option<Value>> {
+        let (iter, state) = match iterator.as_obj().map(|r| (r, self.heap.get(r))) {
+            Some((r, Obj::Iter(state))) => (r, *state),
+            // Anything else follows the iterator protocol
+            _ => {
+                if self.invoke_sync(iterator, "hasNext", &[])?.is_falsey() {
+                    return Ok(None);
+                }
+                return self.invoke_sync(iterator, "next", &[]).map(Some);
+            }
+        };
+        let (item, next) = match state {
+            Iter::List(list, i) => match self.list(list).get(i) {
+                Some(item) => (*item, Iter::List(list, i + 1)),
+                None => return Ok(None),
+            },
+            Iter::String(string, offset) => {
+                let c = match self.heap.get(string) {
+                    Obj::String(s) => s[offset..].chars().next(),
+                    _ => unreachable!("String iterator over a non string"),
+                };
+                match c {
+                    Some(c) => {
+                        // The iterator is rooted in its local, and it roots the string
+                        let item = self.intern(c.encode_utf8(&mut [0; 4]));
+                        (
+                            Value::from(item),
+                            Iter::String(string, offset + c.len_utf8()),
+                        )
+                    }
+                    None => return Ok(None),
+                }
+            }
+            Iter::Range(n, end) if n < end => (Value::from(n), Iter::Range(n + 1.0, end)),
+            Iter::Range(..) => return Ok(None),
+        };
+        if let Obj::Iter(state) = self.heap.get_mut(iter) {
+            *state = next;
+        }
+        Ok(Some(item))
+    }
+
+    // Calls back into the interpreter from native code and returns the result
+    fn call_sync(&mut self, callee: Value, args: &[Value]) -> RunResult<Value> {
+        self.push(callee);
+        for arg in args.iter() {
+            self.push(*arg);
+        }
+        let depth = self.frames.len();
+        self.call_value(callee, args.len())?;
+        if self.frames.len() > depth {
+            self.run(depth)?;
+        }
+        Ok(self.pop())
+    }
+
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> RunResult<()> {
+        let receiver = self.peek(arg_count);
+        match receiver.as_obj().map(|r| (r, self.heap.get(r))) {
+            Some((list, Obj::List(_))) => {
+                let result = self.list_method(list, name, arg_count)?;
+                self.return_native(arg_count, result);
+                Ok(())
+            }
+            Some((map, Obj::Map(_))) => {
+                let result = self.map_method(map, name, arg_count)?;
+                self.return_native(arg_count, result);
+                Ok(())
+            }
+            Some((string, Obj::String(_))) => {
+                let result = self.string_method(string, name, arg_count)?;
+                self.return_native(arg_count, result);
+                Ok(())
+            }
+            Some((_, Obj::Instance(instance))) => {
+                let class = instance.class;
+                let field = instance.fields.get(&name).copied();
+                // Getters and fields hold values to call, like any other
+                let property = match self.find_accessor(class, name, false) {
+                    Some(getter) => {
+                        self.push(receiver);
+                        self.call_native_method(getter, 0)?;
+                        Some(self.pop())
+                    }
+                    None => field,
+                };
+                match property {
+                    Some(value) => {
+                        let slot = self.stack.len() - arg_count - 1;
+                        self.stack[slot] = value;
+                        self.call_value(value, arg_count)
+                    }
+                    // The receiver stays in the callee slot as the method's `this`
+                    None => match self.find_method(class, name) {
+                        Some(method) => self.call_method(method, arg_count),
+                        None => Err(self.undefined_property(name)),
+                    },
+                }
+            }
+            _ => Err(self.error("Only instances have methods.")),
+        }
+    }
+
+    // The receiver and arguments stay on the stack, rooting them while callbacks run
+    fn list_method(&mut self, list: ObjRef, name: ObjRef, arg_count: usize) -> RunResult<Value> {
+        let method = self.stringify(Value::from(name));
+        let arity = match method.as_str() {
+            "pop" | "len" => 0,
+            "push" | "remove" | "map" | "filter" => 1,
+            "insert" | "reduce" => 2,
+            "slice" if arg_count == 1 => 1,
+            "slice" => 2,
+            _ => return Err(self.undefined_property(name)),
+        };
+        if arg_count != arity {
+            return Err(self.error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+        let args = self.stack[self.stack.len() - arg_count..].to_vec();
+        let len = self.list(list).len();
+        let result = match method.as_str() {
+            "push" => {
+                self.list_mut(list, |items| items.push(args[0]));
+                Value::nil()
+            }
+            "pop" => match self.list_mut(list, |items| items.pop()) {
+                Some(value) => value,
+                None => return Err(self.error("Can't pop from an empty list.")),
+            },
+            "len" => Value::from(len as f64),
+            "insert" => {
+                // Inserting at the length appends
+                let index = if args[0].as_number() == Some(len as f64) {
+                    len
+                } else {
+                    self.list_index(args[0], len)?
+                };
+                self.list_mut(list, |items| items.insert(index, args[1]));
+                Value::nil()
+            }
+            "remove" => {
+                let index = self.list_index(args[0], len)?;
+                self.list_mut(list, |items| items.remove(index))
+            }
+            "slice" => {
+                let start = self.slice_bound(args[0], len)?;
+                let end = match args.get(1) {
+                    Some(end) => self.slice_bound(*end, len)?,
+                    None => len,
+                };
+                let items = self.list(list)[start..end.max(start)].to_vec();
+                Value::from(self.alloc(Obj::List(items)))
+            }
+            "map" | "filter" => {
+                let result = self.alloc(Obj::List(vec![]));
+                self.push(Value::from(result));
+                // The callback may change the list, so re-check its length each time
+                let mut i = 0;
+                while let Some(item) = self.list(list).get(i).copied() {
+                    let value = self.call_sync(args[0], &[item])?;
+                    if method == "map" {
+                        self.list_mut(result, |items| items.push(value));
+                    } else if !value.is_falsey() {
+                        self.list_mut(result, |items| items.push(item));
+                    }
+                    i += 1;
+                }
+                self.pop()
+            }
+            "reduce" => {
+                let mut accumulator = args[1];
+                let mut i = 0;
+                while let Some(item) = self.list(list).get(i).copied() {
+                    accumulator = self.call_sync(args[0], &[accumulator, item])?;
+                    i += 1;
+                }
+                accumulator
+            }
+            _ => unreachable!("Checked list method name"),
+        };
+        Ok(result)
+    }
+
+    fn map_method(&mut self, map: ObjRef, name: ObjRef, arg_count: usize) -> RunResult<Value> {
+        let method = self.stringify(Value::from(name));
+        let arity = match method.as_str() {
+            "len" | "keys" | "values" => 0,
+            "has" | "remove" => 1,
+            _ => return Err(self.undefined_property(name)),
+        };
+        if arg_count != arity {
+            return Err(self.error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+        let result = match method.as_str() {
+            "len" => Value::from(self.map(map).len() as f64),
+            "keys" | "values" => {
+                let items = self
+                    .map(map)
+                    .entries()
+                    .iter()
+                    .map(|&(key, value)| if method == "keys" { key } else { value })
+                    .collect();
+                Value::from(self.alloc(Obj::List(items)))
+            }
+            "has" => {
+                let key = self.map_key(self.peek(0))?;
+                Value::from(self.map(map).contains(key))
+            }
+            "remove" => {
+                let key = self.map_key(self.peek(0))?;
+                self.map_mut(map, |m| m.remove(key)).unwrap_or_default()
+            }
+            _ => unreachable!("Checked map method name"),
+        };
+        Ok(result)
+    }
+
+    // Positions and lengths count characters, not bytes
+    fn string_method(
+        &mut self,
+        string: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+    ) -> RunResult<Value> {
+        let method = self.stringify(Value::from(name));
+        let arity = match method.as_str() {
+            "len" | "upper" | "lower" | "trim" | "chars" => 0,
+            "split" | "join" | "contains" | "startsWith" | "endsWith" | "indexOf" | "repeat" => 1,
+            "replace" => 2,
+            "substring" if arg_count == 1 => 1,
+            "substring" => 2,
+            // Takes one argument per placeholder
+            "format" => arg_count,
+            _ => return Err(self.undefined_property(name)),
+        };
+        self.check_arity(Arity::Fixed(arity), arg_count)?;
+        let args = self.stack[self.stack.len() - arg_count..].to_vec();
+        let s = match self.heap.get(string) {
+            Obj::String(s) => s.clone(),
+            _ => unreachable!("Expected a string"),
+        };
+        let result = match method.as_str() {
+            "len" => Value::from(s.chars().count() as f64),
+            "upper" => self.new_string(&s.to_uppercase()),
+            "lower" => self.new_string(&s.to_lowercase()),
+            "trim" => self.new_string(s.trim()),
+            "chars" => self.string_list(s.chars().map(String::from)),
+            "split" => {
+                let separator = self.string_arg(args[0])?;
+                if separator.is_empty() {
+                    self.string_list(s.chars().map(String::from))
+                } else {
+                    self.string_list(s.split(separator.as_str()).map(String::from))
+                }
+            }
+            "join" => {
+                let items = match args[0].as_obj().map(|r| self.heap.get(r)) {
+                    Some(Obj::List(items)) => items,
+                    _ => return Err(self.error("Argument must be a list.")),
+                };
+                let items: Vec<String> = items.iter().map(|item| self.stringify(*item)).collect();
+                self.new_string(&items.join(&s))
+            }
+            "contains" => Value::from(s.contains(self.string_arg(args[0])?.as_str())),
+            "startsWith" => Value::from(s.starts_with(self.string_arg(args[0])?.as_str())),
+            "endsWith" => Value::from(s.ends_with(self.string_arg(args[0])?.as_str())),
+            "indexOf" => {
+                let needle = self.string_arg(args[0])?;
+                let index = match s.find(needle.as_str()) {
+                    Some(offset) => s[..offset].chars().count() as f64,
+                    None => -1.0,
+                };
+                Value::from(index)
+            }
+            "replace" => {
+                let from = self.string_arg(args[0])?;
+                let to = self.string_arg(args[1])?;
+                self.new_string(&s.replace(from.as_str(), &to))
+            }
+            "substring" => {
+                let len = s.chars().count();
+                let start = self.slice_bound(args[0], len)?;
+                let end = match args.get(1) {
+                    Some(end) => self.slice_bound(*end, len)?,
+                    None => len,
+                };
+                let substring: String = s
+                    .chars()
+                    .skip(start)
+                    .take(end.saturating_sub(start))
+                    .collect();
+                self.new_string(&substring)
+            }
+            "repeat" => {
+                let count = match args[0].as_number() {
+                    Some(n) if n.fract() == 0.0 && n >= 0.0 => n as usize,
+                    _ => return Err(self.error("Repeat count must be a non-negative integer.")),
+                };
+                // Fail before allocating, the heap limit is only checked between instructions
+                let size = s.len().checked_mul(count);
+                if self
+                    .limits
+                    .max_heap
+                    .zip(size)
+                    .is_some_and(|(max, size)| size > max)
+                {
+                    return Err(self.limit_error(Limit::Heap));
+                }
+                // Whatever the limits, a string this long can't be allocated
+                if size.is_none_or(|size| size > MAX_STRING_LEN) {
+                    return Err(self.error("Repeated string is too long."));
+                }
+                self.new_string(&s.repeat(count))
+            }
+            "format" => {
+                let text = self.format(&s, &args)?;
+                self.new_string(&text)
+            }
+            _ => unreachable!("Checked string method name"),
+        };
+        Ok(result)
+    }
+
+    fn string_arg(&self, value: Value) -> RunResult<String> {
+        match self.as_string(value) {
+            Some(s) => Ok(s.to_owned()),
+            None => Err(self.error("Argument must be a string.")),
+        }
+    }
+
+    /// Builds Lox values from parsed JSON, rooting each list and map while
+    /// it fills.
+    pub fn alloc_json(&mut self, json: &Json) -> Value {
+        match json {
+            Json::Null => Value::nil(),
+            Json::Bool(b) => Value::from(*b),
+            Json::Number(n) => Value::from(*n),
+            Json::String(s) => self.new_string(s),
+            Json::Array(items) => {
+                let list = self.alloc(Obj::List(Vec::with_capacity(items.len())));
+                self.push(Value::from(list));
+                for item in items {
+                    let item = self.alloc_json(item);
+                    self.list_mut(list, |items| items.push(item));
+                }
+                self.pop()
+            }
+            Json::Object(members) => {
+                let map = self.alloc(Obj::Map(Map::default()));
+                self.push(Value::from(map));
+                for (key, value) in members {
+                    let key = self.new_string(key);
+                    self.push(key);
+                    let value = self.alloc_json(value);
+                    self.pop();
+                    self.map_mut(map, |m| m.insert(HashKey::new(key), value));
+                }
+                self.pop()
+            }
+        }
+    }
+
+    /// Allocates a list of strings, rooting it while the strings are interned.
+    pub fn string_list<I: Iterator<Item = String>>(&mut self, strings: I) -> Value {
+        let list = self.alloc(Obj::List(vec![]));
+        self.push(Value::from(list));
+        for s in strings {
+            let item = self.new_string(&s);
+            self.list_mut(list, |items| items.push(item));
+        }
+        self.pop()
+    }
+
+    // Replaces each {} with the next argument as print shows it, {{ and }} escape braces
+    fn format(&self, template: &str, args: &[Value]) -> RunResult<String> {
+        let mut output = String::new();
+        let mut placeholders = 0;
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    output.push(c);
+                }
+                ('{', Some('}')) => {
+                    chars.next();
+                    if let Some(arg) = args.get(placeholders) {
+                        output.push_str(&self.stringify(*arg));
+                    }
+                    placeholders += 1;
+                }
+                ('{', _) | ('}', _) => {
+                    return Err(self.error(&format!("Unmatched '{}' in format string.", c)))
+                }
+                _ => output.push(c),
+            }
+        }
+        if placeholders != args.len() {
+            return Err(self.error(&format!(
+                "Expected {} arguments but got {}.",
+                placeholders,
+                args.len()
+            )));
+        }
+        Ok(output)
+    }
+
+    // Replaces a native call's receiver and arguments with its result
+    fn return_native(&mut self, arg_count: usize, result: Value) {
+        let len = self.stack.len() - arg_count - 1;
+        self.stack.truncate(len);
+        self.push(result);
+    }
+
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> RunResult<()> {
+        let function = match self.heap.get(closure) {
+            Obj::Closure(c) => c.function,
+            _ => unreachable!("Called a non closure"),
+        };
+        let arity = match self.heap.get(function) {
+            Obj::Function(f) => f.arity,
+            _ => unreachable!("Closure over a non function"),
+        };
+        if arg_count != arity {
+            return Err(self.error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+        if self.frames.len() >= self.limits.max_frames {
+            return Err(self.limit_error(Limit::CallDepth));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slot_base: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        for upvalue in self.open_upvalues.iter() {
+            if let Obj::Upvalue(Upvalue::Open(s)) = self.heap.get(*upvalue) {
+                if *s == slot {
+                    return *upvalue;
+                }
+            }
+        }
+        let upvalue = self.alloc(Obj::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // Moves every captured variable at or above `last` off the stack
+    fn close_upvalues(&mut self, last: usize) {
+        let heap = &mut self.heap;
+        let stack = &self.stack;
+        self.open_upvalues
+            .retain(|upvalue| match heap.get_mut(*upvalue) {
+                Obj::Upvalue(u) => match *u {
+                    Upvalue::Open(slot) if slot >= last => {
+                        *u = Upvalue::Closed(stack[slot]);
+                        false
+                    }
+                    _ => true,
+                },
+                _ => unreachable!("Open upvalue list holds a non upvalue"),
+            });
+    }
+
+    fn concatenate(&mut self, a: ObjRef, b: ObjRef) {
+        let mut result = match self.heap.get(a) {
+            Obj::String(s) => s.clone(),
+            _ => unreachable!(),
+        };
+        if let Obj::String(s) = self.heap.get(b) {
+            result.push_str(s);
+        }
+        // Operands stay on the stack until the result is allocated
+        let result = self.intern(&result);
+        self.pop();
+        self.pop();
+        self.push(Value::from(result));
+    }
+
+    fn binary_number_op(&mut self, op: fn(f64, f64) -> Value) -> RunResult<()> {
+        match (self.peek(1).as_number(), self.peek(0).as_number()) {
+            (Some(a), Some(b)) => {
+                self.pop();
+                self.pop();
+                self.push(op(a, b));
+                Ok(())
+            }
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        self.collect_if_needed();
+        self.heap.alloc(obj)
+    }
+
+    fn intern(&mut self, s: &str) -> ObjRef {
+        self.collect_if_needed();
+        self.heap.intern(s)
+    }
+
+    fn collect_if_needed(&mut self) {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        let roots = Roots {
+            stack: &self.stack,
+            frames: &self.frames,
+            globals: &self.globals,
+            open_upvalues: &self.open_upvalues,
+            error_class: self.error_class,
+            thrown: self.thrown,
+        };
+        self.heap.collect(&roots);
+    }
+
+    fn get_index(&self, object: Value, index: Value) -> RunResult<Value> {
+        match object.as_obj().map(|r| self.heap.get(r)) {
+            Some(Obj::List(items)) => Ok(items[self.list_index(index, items.len())?]),
+            Some(Obj::Map(map)) => match map.get(self.map_key(index)?) {
+                Some(value) => Ok(value),
+                None => Err(self.error(&format!(
+                    "Key {} not found in map.",
+                    self.repr(index, &mut vec![])
+                ))),
+            },
+            _ => Err(self.error("Only lists and maps can be indexed.")),
+        }
+    }
+
+    fn set_index(&mut self, object: Value, index: Value, value: Value) -> RunResult<()> {
+        let r = match object.as_obj() {
+            Some(r) => r,
+            None => return Err(self.error("Only lists and maps can be indexed.")),
+        };
+        match self.heap.get(r) {
+            Obj::List(items) => {
+                let index = self.list_index(index, items.len())?;
+                self.list_mut(r, |items| items[index] = value);
+            }
+            Obj::Map(_) => {
+                let key = self.map_key(index)?;
+                self.map_mut(r, |m| m.insert(key, value));
+            }
+            _ => return Err(self.error("Only lists and maps can be indexed.")),
+        }
+        Ok(())
+    }
+
+    fn map_key(&self, value: Value) -> RunResult<HashKey> {
+        match value.as_obj() {
+            Some(r) if !self.is_string(r) => {
+                Err(self.error("Map keys must be strings, numbers, booleans or nil."))
+            }
+            _ => Ok(HashKey::new(value)),
+        }
+    }
+
+    // Changes a class in place, then recounts its size
+    fn class_mut<T>(&mut self, class: ObjRef, f: impl FnOnce(&mut Class) -> T) -> T {
+        let result = match self.heap.get_mut(class) {
+            Obj::Class(c) => f(c),
+            _ => unreachable!("Expected a class"),
+        };
+        self.heap.resize(class);
+        result
+    }
+
+    fn map(&self, map: ObjRef) -> &Map {
+        match self.heap.get(map) {
+            Obj::Map(m) => m,
+            _ => unreachable!("Expected a map"),
+        }
+    }
+
+    fn map_mut<T>(&mut self, map: ObjRef, f: impl FnOnce(&mut Map) -> T) -> T {
+        let result = match self.heap.get_mut(map) {
+            Obj::Map(m) => f(m),
+            _ => unreachable!("Expected a map"),
+        };
+        self.heap.resize(map);
+        result
+    }
+
+    // Negative indices count back from the end
+    fn list_index(&self, index: Value, len: usize) -> RunResult<usize> {
+        let n = match index.as_number() {
+            Some(n) if n.fract() == 0.0 => n,
+            _ => return Err(self.error("List index must be an integer.")),
+        };
+        let position = if n < 0.0 { n + len as f64 } else { n };
+        if position < 0.0 || position >= len as f64 {
+            return Err(self.error(&format!(
+                "List index {} out of range for length {}.",
+                n, len
+            )));
+        }
+        Ok(position as usize)
+    }
+
+    // Like list_index, but clamped to the list instead of failing
+    fn slice_bound(&self, bound: Value, len: usize) -> RunResult<usize> {
+        let n = match bound.as_number() {
+            Some(n) if n.fract() == 0.0 => n,
+            _ => return Err(self.error("Slice bounds must be integers.")),
+        };
+        let position = if n < 0.0 { n + len as f64 } else { n };
+        Ok(position.max(0.0).min(len as f64) as usize)
+    }
+
+    fn list(&self, list: ObjRef) -> &Vec<Value> {
+        match self.heap.get(list) {
+            Obj::List(items) => items,
+            _ => unreachable!("Expected a list"),
+        }
+    }
+
+    fn list_mut<T>(&mut self, list: ObjRef, f: impl FnOnce(&mut Vec<Value>) -> T) -> T {
+        let result = match self.heap.get_mut(list) {
+            Obj::List(items) => f(items),
+            _ => unreachable!("Expected a list"),
+        };
+        self.heap.resize(list);
+        result
+    }
+
+    fn is_string(&self, r: ObjRef) -> bool {
+        matches!(self.heap.get(r), Obj::String(_))
+    }
+
+    fn upvalue(&self, index: usize) -> ObjRef {
+        match self.heap.get(self.frame().closure) {
+            Obj::Closure(c) => c.upvalues[index],
+            _ => unreachable!("Call frame without a closure"),
+        }
+    }
+
+    fn constant_ref(&self, index: usize) -> ObjRef {
+        self.chunk().constants[index]
+            .as_obj()
+            .expect("Expected object constant")
+    }
+
+    fn chunk(&self) -> &Chunk {
+        match self.heap.get(self.frame().function) {
+            Obj::Function(f) => &f.chunk,
+            _ => unreachable!("Call frame without a function"),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("No active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("No active call frame")
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Popped an empty stack")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn undefined_variable(&self, name: ObjRef) -> RuntimeError {
+        self.error(&format!(
+            "Undefined variable '{}'.",
+            self.stringify(Value::from(name))
+        ))
+    }
+
+    fn undefined_property(&self, name: ObjRef) -> RuntimeError {
+        self.error(&format!(
+            "Undefined property '{}'.",
+            self.stringify(Value::from(name))
+        ))
+    }
+
+    // Called for every instruction
+    fn check_limits(&mut self) -> RunResult<()> {
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(self.limit_error(Limit::Steps));
+        }
+        if let Some(max) = self.limits.max_heap {
+            // Only garbage may be pushing the heap over
+            if self.heap.bytes_allocated() > max {
+                self.collect_garbage();
+                if self.heap.bytes_allocated() > max {
+                    return Err(self.limit_error(Limit::Heap));
+                }
+            }
+        }
+        if self.steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(self.limit_error(Limit::Timeout));
+        }
+        Ok(())
+    }
+
+    fn limit_error(&self, limit: Limit) -> RuntimeError {
+        RuntimeError {
+            limit: Some(limit),
+            ..self.error(limit.message())
+        }
+    }
+
+    /// Builds an error at the current instruction, for natives to return.
+    pub fn error(&self, message: &str) -> RuntimeError {
+        let trace: Vec<TraceFrame> = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| match self.heap.get(frame.function) {
+                Obj::Function(f) => TraceFrame {
+                    line: f.chunk.lines[frame.ip - 1],
+                    column: f.chunk.columns[frame.ip - 1],
+                    function: f.name.map(|name| self.stringify(Value::from(name))),
+                },
+                _ => unreachable!("Call frame without a function"),
+            })
+            .collect();
+        RuntimeError {
+            // Calls made by the host have no line to blame
+            line: trace.first().map_or(0, |frame| frame.line),
+            column: trace.first().map_or(0, |frame| frame.column),
+            message: message.to_owned(),
+            trace,
+            limit: None,
+            exit: None,
+        }
+    }
+
+    /// Ends the run with `status`, past any try statement, like a limit.
+    pub fn exit_error(&self, status: i32) -> RuntimeError {
+        RuntimeError {
+            exit: Some(status),
+            ..self.error(&format!("Exited with status {}.", status))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{gc_configs, gc_stress, global, interpret};
+
+    fn run(source: &str) -> Vm {
+        run_with(source, Default::default())
+    }
+
+    fn run_with(source: &str, gc_config: GcConfig) -> Vm {
+        let mut vm = Vm::new(gc_config);
+        if let Err(err) = interpret(&mut vm, source) {
+            panic!("{}\n{}", err, source);
+        }
+        vm
+    }
+
+    const COUNTER: &str = "
+        fun makeCounter() {
+            var i = 0;
+            fun count() {
+                i = i + 1;
+                return i;
+            }
+            return count;
+        }
+        var counter = makeCounter();
+        counter();
+        var a = counter();
+        var b = makeCounter()();
+    ";
+
+    #[test]
+    fn test_arithmetic_and_globals() {
+        let vm = run(
+            "var a = (1 + 2) * 3 - 4 / 2; var s = \"con\" + \"cat\"; var t = !(a > 6) or a == 7;",
+        );
+        assert_eq!(global(&vm, "a"), "7");
+        assert_eq!(global(&vm, "s"), "concat");
+        assert_eq!(global(&vm, "t"), "true");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let vm = run("
+            var total = 0;
+            for (var i = 0; i < 5; i = i + 1) {
+                if (i == 2) total = total + 100; else total = total + i;
+            }
+            fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            var f = fib(10);
+        ");
+        assert_eq!(global(&vm, "total"), "108");
+        assert_eq!(global(&vm, "f"), "55");
+    }
+
+    #[test]
+    fn test_conditional_and_comma() {
+        let vm = run("
+            fun sign(n) { return n < 0 ? \"neg\" : n == 0 ? \"zero\" : \"pos\"; }
+            var signs = sign(-3) + sign(0) + sign(2);
+            var calls = 0;
+            fun tick() { calls = calls + 1; return calls; }
+            var skipped = true ? 1 : tick();
+            var sum = 0;
+            var i; var j;
+            for (i = 0, j = 10; i < j; i = i + 1, j = j - 1) sum = sum + j - i;
+            var last = (tick(), tick(), calls * 10);
+        ");
+        assert_eq!(global(&vm, "signs"), "negzeropos");
+        assert_eq!(global(&vm, "skipped"), "1");
+        assert_eq!(global(&vm, "sum"), "30");
+        assert_eq!(global(&vm, "last"), "20");
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let vm = run("
+            var kept = \"\";
+            for (var i = 0; i < 10; i = i + 1) {
+                // Would spin forever if continue skipped the increment
+                if (i == 2 or i == 5) continue;
+                if (i > 7) break;
+                kept = kept + \"x\";
+            }
+            var n = 0;
+            while (true) {
+                var a = 1;
+                { var b = 2; if (n == 3) break; }
+                n = n + a;
+            }
+            var pairs = 0;
+            for (var x = 0; x < 3; x = x + 1) {
+                for (var y = 0; y < 3; y = y + 1) {
+                    if (y == x) continue;
+                    if (y > x) break;
+                    pairs = pairs + 1;
+                }
+            }
+            var fns = \"\";
+            for (var k = 0; k < 5; k = k + 1) {
+                var j = k;
+                fun f() { return j; }
+                if (k == 2) break;
+                fns = fns + \"f\";
+            }
+        ");
+        assert_eq!(global(&vm, "kept"), "xxxxxx");
+        assert_eq!(global(&vm, "n"), "3");
+        assert_eq!(global(&vm, "pairs"), "3");
+        assert_eq!(global(&vm, "fns"), "ff");
+        // Nothing was left behind on the stack by the jumps out of the loops
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let vm = run("
+            fun map3(f) { return f(1) + f(2) + f(3); }
+            var base = 10;
+            var sum = map3(fun (x) { return x * base; });
+            fun adder(n) { return fun (x) { return x + n; }; }
+            var add5 = adder(5);
+            var result = add5(1) + adder(100)(1);
+            var shown = fun () {};
+            var iife = fun (a, b) { return a - b; }(7, 2);
+        ");
+        assert_eq!(global(&vm, "sum"), "60");
+        assert_eq!(global(&vm, "result"), "107");
+        assert_eq!(global(&vm, "shown"), "<fn anonymous>");
+        assert_eq!(global(&vm, "iife"), "5");
+    }
+
+    const LISTS: &str = "
+        var xs = [1, 2, 3];
+        xs[0] = xs[-1] * 10;
+        xs.push(\"four\");
+        var popped = xs.pop();
+        xs.insert(1, 5);
+        xs.insert(xs.len(), 6);
+        var removed = xs.remove(-2);
+        var shown = [xs, [], [nil, \"a\"]];
+        var tail = xs.slice(1);
+        var clamped = xs.slice(-10, 2);
+        var empty = xs.slice(3, 1);
+        var doubled = xs.map(fun (x) { return x * 2; });
+        var big = xs.filter(fun (x) { return x > 4; });
+        var sum = xs.reduce(fun (a, b) { return a + b; }, 0);
+        var nested = [[1, 2], [3]].map(fun (l) { return l.len(); });
+    ";
+
+    #[test]
+    fn test_lists() {
+        let vm = run(LISTS);
+        assert_eq!(global(&vm, "xs"), "[30, 5, 2, 6]");
+        assert_eq!(global(&vm, "popped"), "four");
+        assert_eq!(global(&vm, "removed"), "3");
+        assert_eq!(global(&vm, "shown"), "[[30, 5, 2, 6], [], [nil, \"a\"]]");
+        assert_eq!(global(&vm, "tail"), "[5, 2, 6]");
+        assert_eq!(global(&vm, "clamped"), "[30, 5]");
+        assert_eq!(global(&vm, "empty"), "[]");
+        assert_eq!(global(&vm, "doubled"), "[60, 10, 4, 12]");
+        assert_eq!(global(&vm, "big"), "[30, 5, 6]");
+        assert_eq!(global(&vm, "sum"), "43");
+        assert_eq!(global(&vm, "nested"), "[2, 1]");
+
+        let vm = run("var xs = [1]; xs.push(xs);");
+        assert_eq!(global(&vm, "xs"), "[1, [...]]");
+
+        // Printing stops at a depth limit instead of overflowing
+        let vm = run("var xs = []; for (var i = 0; i < 200000; i = i + 1) xs = [xs];");
+        let depth = MAX_PRINT_DEPTH;
+        assert_eq!(
+            global(&vm, "xs"),
+            format!("{}[...]{}", "[".repeat(depth), "]".repeat(depth))
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        let vm = run("
+            var m = {\"name\": \"lox\", 1: true, nil: [1], false: {}};
+            m[\"name\"] = m[\"name\"] + \"!\";
+            m[2] = 0 / 0;
+            m[0 / 0] = \"nan\";
+            var nan = m[0 / 0];
+            m[-0] = \"zero\";
+            var zero = m[0];
+            var has = [m.has(1), m.has(\"missing\"), m.has(nil)];
+            var removed = m.remove(1);
+            var missing = m.remove(1);
+            var keys = m.keys();
+            var values = {\"a\": 1, \"b\": 2}.values();
+            var len = m.len();
+            var same = {} == {};
+        ");
+        assert_eq!(global(&vm, "nan"), "nan");
+        assert_eq!(global(&vm, "zero"), "zero");
+        assert_eq!(global(&vm, "has"), "[true, false, true]");
+        assert_eq!(global(&vm, "removed"), "true");
+        assert_eq!(global(&vm, "missing"), "nil");
+        assert_eq!(global(&vm, "keys"), "[\"name\", nil, false, 2, NaN, -0]");
+        assert_eq!(global(&vm, "values"), "[1, 2]");
+        assert_eq!(global(&vm, "len"), "6");
+        assert_eq!(global(&vm, "same"), "false");
+        assert_eq!(
+            global(&vm, "m"),
+            "{\"name\": \"lox!\", nil: [1], false: {}, 2: NaN, NaN: \"nan\", -0: \"zero\"}"
+        );
+
+        let vm = run("var m = {}; m[\"self\"] = m;");
+        assert_eq!(global(&vm, "m"), "{\"self\": {...}}");
+    }
+
+    #[test]
+    fn test_classes() {
+        let vm = run("
+            class Counter {
+                init(start) { this.count = start; }
+                add(n) { this.count = this.count + n; return this; }
+                adder() { return fun (n) { return this.add(n); }; }
+            }
+            var c = Counter(10);
+            c.add(1).add(2);
+            var add = c.add;
+            add(3);
+            c.adder()(4);
+            var count = c.count;
+            var shown = [Counter, c, add];
+            c.add = fun (n) { return n; };
+            var shadowed = c.add(100);
+            class Empty {}
+            var reinit = c.init(0).count;
+        ");
+        assert_eq!(global(&vm, "count"), "20");
+        assert_eq!(
+            global(&vm, "shown"),
+            "[Counter, Counter instance, <fn add>]"
+        );
+        assert_eq!(global(&vm, "shadowed"), "100");
+        assert_eq!(global(&vm, "reinit"), "0");
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let vm = run("
+            class Shape {
+                init(name) { this.name = name; }
+                describe() { return this.name + \" with area \" + str(this.area()); }
+                area() { return 0; }
+            }
+            class Square < Shape {
+                init(side) { super.init(\"square\"); this.side = side; }
+                area() { return this.side * this.side; }
+                base() { return super.area; }
+            }
+            class Unit < Square {
+                init() { super.init(1); }
+                describe() {
+                    var parent = fun () { return super.describe(); };
+                    return \"unit \" + parent();
+                }
+            }
+            class Plain < Shape {}
+            var square = Square(3).describe();
+            var unit = Unit().describe();
+            var base = Square(2).base()();
+            var plain = Plain(\"plain\").describe();
+        ");
+        assert_eq!(global(&vm, "square"), "square with area 9");
+        assert_eq!(global(&vm, "unit"), "unit square with area 1");
+        assert_eq!(global(&vm, "base"), "0");
+        assert_eq!(global(&vm, "plain"), "plain with area 0");
+    }
+
+    const FOR_IN: &str = "
+        var items = [];
+        for (var x in [1, 2, 3]) items.push(x * 10);
+        for (var k in {\"a\": 1, \"b\": 2}) items.push(k);
+        for (var c in \"hé!\") items.push(c);
+        for (var i in 0..6) {
+            if (i == 1) continue;
+            if (i == 4) break;
+            var doubled = i * 2;
+            items.push(doubled);
+        }
+        class Countdown {
+            init(n) { this.n = n; }
+            iterator() { return this; }
+            hasNext() { return this.n > 0; }
+            next() { this.n = this.n - 1; return this.n + 1; }
+        }
+        for (var n in Countdown(3)) items.push(n);
+        var closures = [];
+        for (var x in 0..3) closures.push(fun () { return x; });
+        var captured = closures.map(fun (f) { return f(); });
+        var m = {1: 1};
+        for (var k in m) m[k + 1] = 0;
+    ";
+
+    #[test]
+    fn test_for_in() {
+        let vm = run(FOR_IN);
+        assert_eq!(
+            global(&vm, "items"),
+            "[10, 20, 30, \"a\", \"b\", \"h\", \"é\", \"!\", 0, 4, 6, 3, 2, 1]"
+        );
+        assert_eq!(global(&vm, "captured"), "[0, 1, 2]");
+        // Keys added during the loop are not visited
+        assert_eq!(global(&vm, "m"), "{1: 1, 2: 0}");
+    }
+
+    #[test]
+    fn test_for_in_survives_gc_stress() {
+        let vm = run_with(FOR_IN, gc_stress());
+        assert_eq!(
+            global(&vm, "items"),
+            "[10, 20, 30, \"a\", \"b\", \"h\", \"é\", \"!\", 0, 4, 6, 3, 2, 1]"
+        );
+        assert_eq!(global(&vm, "captured"), "[0, 1, 2]");
+    }
+
+    const STRING_METHODS: &str = "
+        var s = \"  Grüße, Welt  \";
+        var word = \"héllo\";
+        var basics = [word.len(), s.trim(), word.upper(), \"ÀB\".lower(), \"\".len()];
+        var parts = [\"a,b,,c\".split(\",\"), word.split(\"\"), word.chars(), \"abc\".split(\"x\")];
+        var joined = [\", \".join([1, \"two\", nil, [3]]), \"\".join([])];
+        var search = [
+            word.contains(\"ll\"), word.startsWith(\"hé\"), word.endsWith(\"x\"),
+            word.indexOf(\"l\"), word.indexOf(\"z\"), word.indexOf(\"\")
+        ];
+        var edits = [
+            \"a-b-c\".replace(\"-\", \"+\"), word.substring(1, 3), word.substring(-2),
+            word.substring(3, 1), \"ab\".repeat(3), \"ab\".repeat(0)
+        ];
+        var formatted = [
+            \"{} + {} = {}\".format(1, 2, 1 + 2),
+            \"{{literal}} {}\".format([1, \"é\"]),
+            \"none\".format()
+        ];
+        var chained = \" a b \".trim().split(\" \").map(fun (p) { return p.upper(); });
+    ";
+
+    #[test]
+    fn test_string_methods() {
+        for config in gc_configs() {
+            let vm = run_with(STRING_METHODS, config);
+            assert_eq!(
+                global(&vm, "basics"),
+                "[5, \"Grüße, Welt\", \"HÉLLO\", \"àb\", 0]"
+            );
+            assert_eq!(
+                global(&vm, "parts"),
+                "[[\"a\", \"b\", \"\", \"c\"], [\"h\", \"é\", \"l\", \"l\", \"o\"], \
+                 [\"h\", \"é\", \"l\", \"l\", \"o\"], [\"abc\"]]"
+            );
+            assert_eq!(global(&vm, "joined"), "[\"1, two, nil, [3]\", \"\"]");
+            assert_eq!(global(&vm, "search"), "[true, true, false, 2, -1, 0]");
+            assert_eq!(
+                global(&vm, "edits"),
+                "[\"a+b+c\", \"él\", \"lo\", \"\", \"ababab\", \"\"]"
+            );
+            assert_eq!(
+                global(&vm, "formatted"),
+                "[\"1 + 2 = 3\", \"{literal} [1, \"é\"]\", \"none\"]"
+            );
+            assert_eq!(global(&vm, "chained"), "[\"A\", \"B\"]");
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let source = "
+            var name = \"lox\";
+            var count = 2;
+            var greeting = \"Hello ${name}, you have ${count + 1} items\";
+            var nested = \"${\"[${name + \"!\"}]\"} ${ {\"k\": [nil, true]} }\";
+            var values = \"${1 / 2}${-0} ${[\"a\", 1]} ${nil}\";
+            class Point {}
+            var point = \"${Point()}\";
+            var lines = \"a
+${count}\";
+        ";
+        for config in gc_configs() {
+            let vm = run_with(source, config);
+            assert_eq!(global(&vm, "greeting"), "Hello lox, you have 3 items");
+            assert_eq!(global(&vm, "nested"), "[lox!] {\"k\": [nil, true]}");
+            assert_eq!(global(&vm, "values"), "0.5-0 [\"a\", 1] nil");
+            assert_eq!(global(&vm, "point"), "Point instance");
+            assert_eq!(global(&vm, "lines"), "a\n2");
+        }
+    }
+
+    const EXCEPTIONS: &str = "
+        var log = [];
+        fun risky(n) {
+            if (n > 1) throw \"too big: ${n}\";
+            return n;
+        }
+        try {
+            log.push(risky(1));
+            log.push(risky(2));
+            log.push(\"unreachable\");
+        } catch (e) {
+            log.push(e);
+        }
+        try { nil.x; } catch (e) { log.push(e.message); log.push(e); }
+        fun f() {
+            try { return \"body\"; } finally { log.push(\"finally on return\"); }
+        }
+        log.push(f());
+        for (var i in 0..3) {
+            try {
+                if (i == 1) continue;
+                if (i == 2) break;
+                log.push(i);
+            } finally {
+                log.push(\"f${i}\");
+            }
+        }
+        try {
+            try { throw \"inner\"; } finally { log.push(\"cleanup\"); }
+        } catch (e) {
+            log.push(\"outer caught ${e}\");
+        }
+        try {
+            try { throw 1; } catch (e) { throw e + 1; } finally { log.push(\"finally after catch\"); }
+        } catch (e) {
+            log.push(e);
+        }
+        try {
+            [1, 2].map(fun (x) { throw \"from callback\"; });
+        } catch (e) {
+            log.push(e);
+        }
+        fun deep(n) {
+            if (n == 0) throw \"deep\";
+            return deep(n - 1);
+        }
+        var unwound;
+        {
+            var a = 1;
+            try { var b = 2; deep(5); } catch (e) { var c = 3; unwound = [a, e, c]; }
+        }
+        fun forever() { forever(); }
+        try { forever(); } catch (e) { log.push(e.message); }
+        fun lost() {
+            try { throw \"lost\"; } finally { return \"finally wins\"; }
+        }
+        log.push(lost());
+        var getter;
+        try {
+            var captured = \"captured\";
+            getter = fun () { return captured; };
+            throw nil;
+        } catch (e) {}
+        log.push(getter());
+    ";
+
+    #[test]
+    fn test_exceptions() {
+        for config in gc_configs() {
+            let vm = run_with(EXCEPTIONS, config);
+            assert_eq!(
+                global(&vm, "log"),
+                "[1, \"too big: 2\", \"Only instances have properties.\", Error instance, \
+                 \"finally on return\", \"body\", 0, \"f0\", \"f1\", \"f2\", \"cleanup\", \
+                 \"outer caught inner\", \"finally after catch\", 2, \"from callback\", \
+                 \"Stack overflow.\", \"finally wins\", \"captured\"]"
+            );
+            assert_eq!(global(&vm, "unwound"), "[1, \"deep\", 3]");
+        }
+
+        let vm = run("var line;\ntry {\n  nil.x;\n} catch (e) {\n  line = e.line;\n}");
+        assert_eq!(global(&vm, "line"), "3");
+    }
+
+    #[test]
+    fn test_uncaught_errors() {
+        let test_table = vec![
+            (
+                "fun inner() {\n  throw \"boom\";\n}\nfun outer() {\n  inner();\n}\nouter();",
+                "boom\n[line 2, column 3] in inner()\n[line 5, column 8] in outer()\n[line 7, column 6] in script",
+            ),
+            // Rethrowing after a finally block keeps the original trace
+            (
+                "fun f() {\n  nil.x;\n}\ntry {\n  f();\n} finally {\n  var done = true;\n}",
+                "Only instances have properties.\n[line 2, column 6] in f()\n[line 5, column 4] in script",
+            ),
+            (
+                "class Oops { init(message) { this.message = message; } }\nthrow Oops(\"bad\");",
+                "bad\n[line 2, column 1] in script",
+            ),
+            (
+                "var f = fun () { throw [1]; };\ntry { f(); } catch (e) { throw e; }",
+                "[1]\n[line 2, column 26] in script",
+            ),
+        ];
+        for (source, expected) in test_table {
+            let mut vm = Vm::new(Default::default());
+            match interpret(&mut vm, source) {
+                Err(err) => assert_eq!(format!("{}", err), expected, "{}", source),
+                Ok(()) => panic!("Expected an uncaught error: {}", source),
+            }
+            interpret(&mut vm, "try { throw 1; } catch (e) {}").expect("VM not reset after error");
+        }
+    }
+
+    #[test]
+    fn test_lists_survive_gc_stress() {
+        let vm = run_with(LISTS, gc_stress());
+        assert_eq!(global(&vm, "shown"), "[[30, 5, 2, 6], [], [nil, \"a\"]]");
+        assert_eq!(global(&vm, "doubled"), "[60, 10, 4, 12]");
+        assert_eq!(global(&vm, "nested"), "[2, 1]");
+    }
+
+    #[test]
+    fn test_counter_closure() {
+        let vm = run(COUNTER);
+        assert_eq!(global(&vm, "a"), "2");
+        // Every call to makeCounter captures a fresh variable
+        assert_eq!(global(&vm, "b"), "1");
+    }
+
+    #[test]
+    fn test_sibling_closures_share_upvalue() {
+        let vm = run("
+            var get; var set;
+            fun make() {
+                var x = 1;
+                fun g() { return x; }
+                fun s(v) { x = v; }
+                get = g;
+                set = s;
+            }
+            make();
+            set(42);
+            var result = get();
+        ");
+        assert_eq!(global(&vm, "result"), "42");
+    }
+
+    #[test]
+    fn test_loop_variable_capture() {
+        let vm = run("
+            var body0; var body1; var loop0; var loop1;
+            for (var i = 0; i < 2; i = i + 1) {
+                var j = i;
+                fun fromBody() { return j; }
+                fun fromLoop() { return i; }
+                if (i == 0) { body0 = fromBody; loop0 = fromLoop; }
+                else { body1 = fromBody; loop1 = fromLoop; }
+            }
+            var b0 = body0(); var b1 = body1();
+            var l0 = loop0(); var l1 = loop1();
+        ");
+        // A variable declared in the body is fresh every iteration
+        assert_eq!(global(&vm, "b0"), "0");
+        assert_eq!(global(&vm, "b1"), "1");
+        // The loop variable itself is a single variable shared by all iterations
+        assert_eq!(global(&vm, "l0"), "2");
+        assert_eq!(global(&vm, "l1"), "2");
+    }
+
+    #[test]
+    fn test_upvalue_closed_at_scope_exit() {
+        let vm = run("
+            var f;
+            {
+                var a = \"outer\";
+                fun g() { return a; }
+                f = g;
+            }
+            var a = \"global\";
+            var result = f();
+        ");
+        assert_eq!(global(&vm, "result"), "outer");
+    }
+
+    #[test]
+    fn test_closures_survive_gc_stress() {
+        let vm = run_with(COUNTER, gc_stress());
+        assert_eq!(global(&vm, "a"), "2");
+        assert_eq!(global(&vm, "b"), "1");
+        assert!(vm.heap().stats().collections > 0);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let test_table = vec![
+            ("print -\"a\";", "Operand must be a number.", 1),
+            (
+                "var a = 1;\nprint a + nil;",
+                "Operands must be two numbers or two strings.",
+                2,
+            ),
+            ("print undefined;", "Undefined variable 'undefined'.", 1),
+            ("fun f(a) {}\nf();", "Expected 1 arguments but got 0.", 2),
+            ("\"str\"();", "Can only call functions and classes.", 1),
+            (
+                "var xs = [1, 2];\nxs[2];",
+                "List index 2 out of range for length 2.",
+                2,
+            ),
+            (
+                "[1][-2] = 0;",
+                "List index -2 out of range for length 1.",
+                1,
+            ),
+            ("[1][0.5];", "List index must be an integer.", 1),
+            ("nil[0];", "Only lists and maps can be indexed.", 1),
+            ("var m = {};\nm[\"a\"];", "Key \"a\" not found in map.", 2),
+            (
+                "({[]: 1});",
+                "Map keys must be strings, numbers, booleans or nil.",
+                1,
+            ),
+            (
+                "var m = {1: 2};\nm[m] = 3;",
+                "Map keys must be strings, numbers, booleans or nil.",
+                2,
+            ),
+            ("({}).get(1);", "Undefined property 'get'.", 1),
+            ("[].pop();", "Can't pop from an empty list.", 1),
+            ("[].push();", "Expected 1 arguments but got 0.", 1),
+            ("[].size();", "Undefined property 'size'.", 1),
+            (
+                "[1].map(fun (x) {\nreturn -nil;\n});",
+                "Operand must be a number.",
+                2,
+            ),
+            ("1 .len;", "Only instances have properties.", 1),
+            ("class A {}\nA().x;", "Undefined property 'x'.", 2),
+            ("class A {}\nA(1);", "Expected 0 arguments but got 1.", 2),
+            ("[].x = 1;", "Only instances have fields.", 1),
+            (
+                "for (var x in 1) {}",
+                "Can only iterate over lists, maps, strings, ranges and instances.",
+                1,
+            ),
+            ("1..\"a\";", "Range bounds must be numbers.", 1),
+            ("var a = 1;\nthrow a + 1;", "2", 2),
+            (
+                "class A { iterator() { return 1; } }\nfor (var x in A()) {}",
+                "Only instances have methods.",
+                2,
+            ),
+            (
+                "var A = 1;\nclass B < A {}",
+                "Superclass must be a class.",
+                2,
+            ),
+            (
+                "class A {}\nclass B < A { f() { return super.f(); } }\nB().f();",
+                "Undefined property 'f'.",
+                2,
+            ),
+            ("\"a\".size();", "Undefined property 'size'.", 1),
+            ("\"a\".upper(1);", "Expected 0 arguments but got 1.", 1),
+            ("\"a\".split(1);", "Argument must be a string.", 1),
+            ("\",\".join(\"ab\");", "Argument must be a list.", 1),
+            (
+                "\"ab\".substring(0.5);",
+                "Slice bounds must be integers.",
+                1,
+            ),
+            (
+                "\"ab\".repeat(-1);",
+                "Repeat count must be a non-negative integer.",
+                1,
+            ),
+            (
+                "print \"x\".repeat(10000000000000000000);",
+                "Repeated string is too long.",
+                1,
+            ),
+            (
+                "\"x\".repeat(100000000000);",
+                "Repeated string is too long.",
+                1,
+            ),
+            ("\"{} {}\".format(1);", "Expected 2 arguments but got 1.", 1),
+            ("\"{\".format();", "Unmatched '{' in format string.", 1),
+        ];
+        for (source, message, line) in test_table {
+            let mut vm = Vm::new(Default::default());
+            match interpret(&mut vm, source) {
+                Err(InterpretError::Runtime(err)) => {
+                    assert_eq!(err.message, message, "{}", source);
+                    assert_eq!(err.line, line, "{}", source);
+                }
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+            // The VM is usable again after an error
+            interpret(&mut vm, "var ok = 1;").expect("VM not reset after error");
+        }
+    }
+
+    #[test]
+    fn test_error_columns() {
+        let test_table = vec![
+            ("var xs = [1, 2];\nprint xs[0] + xs[2];", 2, 17),
+            ("var xs = [[1]];\n  xs[0][-2] = 3;", 2, 13),
+            // Bad arguments to string methods point at the call
+            (
+                "var s = \"ab\";\nprint s.len() + s.repeat(-1).len();",
+                2,
+                25,
+            ),
+            ("\"{}\".format(\n  1, 2);", 1, 12),
+        ];
+        for (source, line, column) in test_table {
+            let mut vm = Vm::new(Default::default());
+            match interpret(&mut vm, source) {
+                Err(InterpretError::Runtime(err)) => {
+                    assert_eq!((err.line, err.column), (line, column), "{}", source)
+                }
+                other => panic!("Expected runtime error for {}, got {:?}", source, other),
+            }
+        }
+
+        let vm = run("var at;\ntry {\n  [1][5];\n} catch (e) {\n  at = [e.line, e.column];\n}");
+        assert_eq!(global(&vm, "at"), "[3, 6]");
+    }
+
+    #[test]
+    fn test_limits() {
+        let limited = |limits: Limits| {
+            let mut vm = Vm::with_output(Default::default(), Box::new(io::sink()));
+            vm.set_limits(limits);
+            vm
+        };
+        let test_table = vec![
+            (
+                Limits {
+                    max_steps: Some(1000),
+                    ..Default::default()
+                },
+                "try { while (true) {} } catch (e) {}",
+                Limit::Steps,
+            ),
+            (
+                Limits {
+                    timeout: Some(Duration::from_millis(10)),
+                    ..Default::default()
+                },
+                "try { while (true) {} } finally {}",
+                Limit::Timeout,
+            ),
+            (
+                Limits {
+                    max_heap: Some(100_000),
+                    ..Default::default()
+                },
+                "fun grow(xs) { while (true) xs.push(\"item ${xs.len()}\"); }\ntry { grow([]); } catch (e) {}",
+                Limit::Heap,
+            ),
+            (
+                Limits {
+                    max_heap: Some(100_000),
+                    ..Default::default()
+                },
+                "\"ab\".repeat(1000000000);",
+                Limit::Heap,
+            ),
+            (
+                Limits {
+                    max_heap: Some(100_000),
+                    ..Default::default()
+                },
+                // One list growing without allocating anything else
+                "{ var xs = []; while (true) xs.push(1); }",
+                Limit::Heap,
+            ),
+            (
+                Limits {
+                    max_heap: Some(100_000),
+                    ..Default::default()
+                },
+                "{ var m = {}; var i = 0; while (true) { m[i] = i; i = i + 1; } }",
+                Limit::Heap,
+            ),
+            (
+                Default::default(),
+                "fun f() { f(); }\nf();",
+                Limit::CallDepth,
+            ),
+            (
+                Limits {
+                    max_frames: 8,
+                    ..Default::default()
+                },
+                "fun depth(n) { if (n == 0) return 0; return depth(n - 1); }\ndepth(8);",
+                Limit::CallDepth,
+            ),
+        ];
+        for (limits, source, limit) in test_table {
+            let mut vm = limited(limits);
+            match interpret(&mut vm, source) {
+                Err(InterpretError::Limit(err)) => {
+                    assert_eq!(err.limit, Some(limit), "{}", source);
+                    assert_eq!(err.message, limit.message(), "{}", source);
+                }
+                other => panic!("Expected {:?} for {}, got {:?}", limit, source, other),
+            }
+            // Budgets start over, and what filled the heap is garbage now
+            interpret(&mut vm, "var ok = 1;").expect("VM not reset after a limit");
+        }
+
+        let mut vm = limited(Limits {
+            max_steps: Some(20),
+            ..Default::default()
+        });
+        interpret(&mut vm, "fun f() { return 1; }").expect("Script failed");
+        let f = vm.get_global("f").expect("Global not defined");
+        for _ in 0..3 {
+            assert!(vm.call_function(f, &[]).is_ok(), "Budget not reset");
+        }
+        interpret(&mut vm, "fun spin() { while (true) {} }").expect("Script failed");
+        let spin = vm.get_global("spin").expect("Global not defined");
+        assert!(matches!(
+            vm.call_function(spin, &[]),
+            Err(InterpretError::Limit(_))
+        ));
+
+        // Scripts may recover from a stack overflow, not from the other limits
+        let vm =
+            run("fun f() { f(); }\nvar message;\ntry { f(); } catch (e) { message = e.message; }");
+        assert_eq!(global(&vm, "message"), "Stack overflow.");
+
+        let mut vm = limited(Limits {
+            sandbox: true,
+            ..Default::default()
+        });
+        match interpret(&mut vm, "clock();") {
+            Err(InterpretError::Runtime(err)) => {
+                assert_eq!(err.message, "'clock' is not available in the sandbox.")
+            }
+            other => panic!("Expected runtime error, got {:?}", other),
+        }
+        interpret(&mut vm, "var n = len(str(1.5));").expect("Script failed");
+        assert_eq!(global(&vm, "n"), "3");
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let test_table = vec![
+            ("return 1;", "Can't return from top-level code."),
+            (
+                "{ var a = a; }",
+                "Can't read local variable in its own initializer.",
+            ),
+            (
+                "{ var a; var a; }",
+                "Already a variable with this name in this scope.",
+            ),
+            ("break;", "Can't use 'break' outside of a loop."),
+            ("print this;", "Can't use 'this' outside of a class."),
+            ("class A < A {}", "A class can't inherit from itself."),
+            ("super.f();", "Can't use 'super' outside of a class."),
+            (
+                "class A { f() { super.f(); } }",
+                "Can't use 'super' in a class with no superclass.",
+            ),
+            (
+                "class A { init() { return 1; } }",
+                "Can't return a value from an initializer.",
+            ),
+            (
+                "while (true) { fun f() { continue; } }",
+                "Can't use 'continue' outside of a loop.",
+            ),
+        ];
+        for (source, message) in test_table {
+            match interpret(&mut Vm::new(Default::default()), source) {
+                Err(InterpretError::Compile(err)) => assert_eq!(err.message, message),
+                other => panic!("Expected compile error for {}, got {:?}", source, other),
+            }
+        }
+    }
+}